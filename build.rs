@@ -12,6 +12,14 @@ use std::{
     process::{self, Command},
 };
 
+/// Magic bytes of the gzip, zstd, LZ4 and legacy LZ4 formats.
+const COMPRESSION_MAGICS: [&[u8]; 4] = [
+    &[0x1f, 0x8b],
+    &[0x28, 0xb5, 0x2f, 0xfd],
+    &[0x04, 0x22, 0x4d, 0x18],
+    &[0x02, 0x21, 0x4c, 0x18],
+];
+
 fn main() {
     let out_dir = PathBuf::from(
         env::var("OUT_DIR").expect("OUT_DIR not set")
@@ -46,35 +54,103 @@ fn main() {
         }
     };
 
+    // kernels compressed beforehand are embedded as they are, and
+    // decompressed by the loader at boot
+    let kernel_data = fs::read(&kernel_path).expect("failed to read kernel");
+    let precompressed = COMPRESSION_MAGICS.iter().any(|magic| kernel_data.starts_with(magic));
+
     // strip debug symbols from kernel for faster loading
     let stripped_kernel_file_name = format!("kernel_stripped-{}", kernel_file_name);
     let stripped_kernel = out_dir.join(&stripped_kernel_file_name);
-    let objcopy = llvm_tools
-        .tool(&llvm_tools::exe("llvm-objcopy"))
-        .expect("llvm-objcopy not found in llvm-tools");
-    let mut cmd = Command::new(&objcopy);
-    cmd.arg("--strip-debug");
-    cmd.arg(&kernel_path);
-    cmd.arg(&stripped_kernel);
-    let exit_status = cmd
-        .status()
-        .expect("failed to run objcopy to strip debug symbols");
-    if !exit_status.success() {
-        eprintln!("Error: Stripping debug symbols failed");
-        process::exit(1);
+    if precompressed {
+        fs::copy(&kernel_path, &stripped_kernel).expect("failed to copy compressed kernel");
+    } else {
+        let objcopy = llvm_tools
+            .tool(&llvm_tools::exe("llvm-objcopy"))
+            .expect("llvm-objcopy not found in llvm-tools");
+        let mut cmd = Command::new(&objcopy);
+        cmd.arg("--strip-debug");
+        cmd.arg(&kernel_path);
+        cmd.arg(&stripped_kernel);
+        let exit_status = cmd
+            .status()
+            .expect("failed to run objcopy to strip debug symbols");
+        if !exit_status.success() {
+            eprintln!("Error: Stripping debug symbols failed");
+            process::exit(1);
+        }
     }
 
+    // optionally compress the stripped kernel (gzip, zstd or lz4)
+    println!("cargo:rerun-if-env-changed=KERNEL_COMPRESSION");
+    let embedded_kernel = match env::var("KERNEL_COMPRESSION") {
+        Ok(compression) if !precompressed && !compression.is_empty() => {
+            let compressed_kernel = out_dir.join(
+                format!("{}.{}", stripped_kernel_file_name, compression)
+            );
+            let mut cmd = match compression.as_str() {
+                "gzip" => {
+                    let mut cmd = Command::new("gzip");
+                    cmd.args(&["-9", "-n", "-c"]);
+                    cmd
+                }
+                "zstd" => {
+                    let mut cmd = Command::new("zstd");
+                    cmd.args(&["-19", "-q", "-c"]);
+                    cmd
+                }
+                "lz4" => {
+                    let mut cmd = Command::new("lz4");
+                    cmd.args(&["-9", "-q", "-c", "--content-size"]);
+                    cmd
+                }
+                _ => {
+                    eprintln!("Error: KERNEL_COMPRESSION must be gzip, zstd or lz4");
+                    process::exit(1);
+                }
+            };
+            cmd.arg(&stripped_kernel);
+            cmd.stdout(File::create(&compressed_kernel).expect("failed to create compressed kernel"));
+            let exit_status = cmd
+                .status()
+                .unwrap_or_else(|_| panic!("failed to run {} to compress the kernel", compression));
+            if !exit_status.success() {
+                eprintln!("Error: Compressing the kernel failed");
+                process::exit(1);
+            }
+            compressed_kernel
+        }
+        _ => stripped_kernel,
+    };
+
     // write file for including kernel in binary
     let file_path = out_dir.join("kernel_info.rs");
     let mut file = File::create(file_path).expect("failed to create kernel_info.rs");
-    let kernel_size = fs::metadata(&stripped_kernel)
-        .expect("Failed to read file metadata of stripped kernel")
+    let kernel_size = fs::metadata(&embedded_kernel)
+        .expect("Failed to read file metadata of embedded kernel")
         .len();
     file.write_all(
         format!(
             "const KERNEL_SIZE: usize = {}; const KERNEL_BYTES: [u8; KERNEL_SIZE] = *include_bytes!(r\"{}\");",
             kernel_size,
-            stripped_kernel.display(),
+            embedded_kernel.display(),
         ).as_bytes(),
     ).expect("write to kernel_info.rs failed");
+
+    // optionally embed a boot splash image (BMP or QOI)
+    println!("cargo:rerun-if-env-changed=SPLASH");
+    let splash_info = match env::var("SPLASH") {
+        Ok(splash_path) => {
+            let splash_path = fs::canonicalize(&splash_path)
+                .expect("failed to resolve SPLASH path");
+            println!("cargo:rerun-if-changed={}", splash_path.display());
+            format!(
+                "const SPLASH_IMAGE: Option<&[u8]> = Some(include_bytes!(r\"{}\"));",
+                splash_path.display(),
+            )
+        }
+        Err(_) => String::from("const SPLASH_IMAGE: Option<&[u8]> = None;"),
+    };
+    fs::write(out_dir.join("splash_info.rs"), splash_info)
+        .expect("write to splash_info.rs failed");
 }
@@ -1 +1,2 @@
 global_asm!(include_str!("arch/riscv/riscv64-efi.S"));
+global_asm!(include_str!("arch/riscv/riscv64-entry.S"));
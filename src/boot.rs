@@ -1,14 +1,69 @@
-use core::fmt::Write;
-
 use mercuros_uefi::{EfiStatus, UEFIError};
 
-use super::{elf, kernel};
+use super::{
+    boot_info::{BootInfo, EfiMemoryMap, MemoryRange},
+    chainload, compression, config, console, efi, elf, entry, fdt, kernel, limine, log,
+    log_buffer, log_file, menu, multiboot2, pe, serial,
+    firmware_tables::FirmwareTables,
+    memory_map::{PhysicalMemoryMap, RegionKind},
+    framebuffer::{self, font, splash, Font, Framebuffer, Image, Splash, TextConsole},
+    kernel_format::{self, KernelFormat},
+    linux_image::{self, LinuxImage},
+};
+
+/// Free space reserved for additions to the device tree passed to the kernel.
+const DEVICE_TREE_HEADROOM: usize = 64 * 1024;
+
+/// Size limit for splash images loaded from the boot volume.
+const MAX_SPLASH_SIZE: usize = 16 * 1024 * 1024;
+
+/// Size limit for device tree blobs loaded from the boot volume.
+const MAX_DEVICE_TREE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Size limit for the ELF file header and program headers of kernels
+/// loaded from the boot volume.
+const MAX_ELF_HEADERS_SIZE: usize = 64 * 1024;
+
+/// Size limit for compressed kernel files and for decompressed kernels.
+const MAX_KERNEL_SIZE: usize = 256 * 1024 * 1024;
+
+/// Time an error starting a chainloaded application is shown before the
+/// boot menu returns.
+const CHAINLOAD_ERROR_DELAY_US: usize = 3_000_000;
+
+/// Maximum number of device tree overlays applied at boot.
+const MAX_OVERLAYS: usize = 16;
+
+/// Maximum number of modules loaded for Multiboot2 and Limine kernels.
+pub const MAX_MODULES: usize = 8;
+
+/// Multiboot2 modules must lie below 4 GiB, having 32 bit addresses.
+const MODULE_ADDRESS_LIMIT: u64 = u32::MAX as u64;
+
+/// Boot loader name passed to Multiboot2 and Limine kernels.
+pub const BOOT_LOADER_NAME: &str = "MercurOS Maia";
+
+/// Size of the stack the kernel is entered with, unless set by the
+/// `kernel_stack` option.
+const DEFAULT_KERNEL_STACK_SIZE: usize = 64 * 1024;
 
 pub enum Error {
     MemoryAllocationFailed,
     MemoryMapUnavailable,
     DeviceTreeUnavailable,
+    BootHartUnknown,
     InvalidKernelImage,
+    KernelRangeUnavailable,
+    KernelFileUnreadable(efi::Status),
+    InvalidCompressedKernel(compression::DecompressionError),
+    InvalidElf(elf::ElfError),
+    InvalidLinuxImage(linux_image::LinuxImageError),
+    InvalidPe(pe::PeError),
+    KernelNotStarted(efi::Status),
+    KernelExited(efi::Status),
+    ModuleUnreadable(efi::Status),
+    InvalidMultiboot(multiboot2::Multiboot2Error),
+    InvalidLimine(limine::LimineError),
 }
 
 impl core::convert::From<Error> for EfiStatus {
@@ -33,353 +88,1754 @@ impl core::convert::From<UEFIError> for Error {
     }
 }
 
+impl core::convert::From<compression::DecompressionError> for Error {
+    fn from(error: compression::DecompressionError) -> Error {
+        Error::InvalidCompressedKernel(error)
+    }
+}
+
 impl core::convert::From<elf::ElfError> for Error {
-    fn from(_error: elf::ElfError) -> Error {
-        Error::InvalidKernelImage
+    fn from(error: elf::ElfError) -> Error {
+        Error::InvalidElf(error)
+    }
+}
+
+impl core::convert::From<linux_image::LinuxImageError> for Error {
+    fn from(error: linux_image::LinuxImageError) -> Error {
+        Error::InvalidLinuxImage(error)
+    }
+}
+
+impl core::convert::From<pe::PeError> for Error {
+    fn from(error: pe::PeError) -> Error {
+        Error::InvalidPe(error)
+    }
+}
+
+impl core::convert::From<multiboot2::Multiboot2Error> for Error {
+    fn from(error: multiboot2::Multiboot2Error) -> Error {
+        Error::InvalidMultiboot(error)
+    }
+}
+
+impl core::convert::From<limine::LimineError> for Error {
+    fn from(error: limine::LimineError) -> Error {
+        Error::InvalidLimine(error)
     }
 }
 
 impl core::fmt::Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Error::MemoryAllocationFailed =>
-                    "Memory allocation failed!",
-                Error::MemoryMapUnavailable =>
-                    "Memory map unavailable!",
-                Error::DeviceTreeUnavailable =>
-                    "DeviceTree not found!",
-                Error::InvalidKernelImage =>
-                    "Invalid kernel image!",
-            }
-        )
+        match self {
+            Error::MemoryAllocationFailed =>
+                write!(f, "Memory allocation failed!"),
+            Error::MemoryMapUnavailable =>
+                write!(f, "Memory map unavailable!"),
+            Error::DeviceTreeUnavailable =>
+                write!(f, "Neither DeviceTree nor ACPI tables found!"),
+            Error::BootHartUnknown =>
+                write!(f, "Unable to determine the boot hart id!"),
+            Error::InvalidKernelImage =>
+                write!(f, "Invalid kernel image!"),
+            Error::KernelRangeUnavailable =>
+                write!(f, "Kernel load address unavailable!"),
+            Error::KernelFileUnreadable(status) =>
+                write!(f, "Unable to read kernel file: {:#x}!", status),
+            Error::InvalidCompressedKernel(error) =>
+                write!(f, "Unable to decompress kernel: {}!", error),
+            Error::InvalidElf(error) =>
+                write!(f, "Invalid kernel image: {}!", error),
+            Error::InvalidLinuxImage(error) =>
+                write!(f, "Invalid Linux kernel image: {}!", error),
+            Error::InvalidPe(error) =>
+                write!(f, "Invalid PE kernel image: {}!", error),
+            Error::KernelNotStarted(status) =>
+                write!(f, "Unable to start kernel: {:#x}!", status),
+            Error::KernelExited(status) =>
+                write!(f, "Kernel exited with status {:#x}!", status),
+            Error::ModuleUnreadable(status) =>
+                write!(f, "Unable to read module: {:#x}!", status),
+            Error::InvalidMultiboot(error) =>
+                write!(f, "Unable to provide Multiboot2 information: {}!", error),
+            Error::InvalidLimine(error) =>
+                write!(f, "Unable to boot Limine kernel: {}!", error),
+        }
     }
 }
 
 pub fn boot(mut uefi: mercuros_uefi::Application) -> Result<(), Error> {
     mercuros_uefi::Console::clear_screen(&mut uefi);
-    mercuros_uefi::Console::write_string(
-        &mut uefi,
-        "MercurOS Maia Bootloader\r\n"
-    );
 
-    #[cfg(feature = "debug_mmap")]
-    debug_mmap(&mut uefi)?;
+    let config_status = config::load();
+    attach_log_buffer();
+
+    info!("MercurOS Maia Bootloader");
+    configure_logging(config_status);
+
+    // the menu is shown if a timeout is configured, and whenever a
+    // chainloaded application returns
+    let mut timeout = config::get_usize("timeout");
+    let mut show_menu = timeout.is_some();
+    loop {
+        if show_menu {
+            if let Some(entry) = menu::choose_entry(timeout) {
+                config::select_entry(entry);
+                configure_logging(Ok(()));
+            }
+        }
+
+        if let Some(entry) = config::entry() {
+            if config::entries().any(|name| name == entry) {
+                info!("Boot entry: {}", entry);
+            } else {
+                warn!("Unknown boot entry: {}", entry);
+            }
+        }
+
+        let path = match config::get("chainload") {
+            Some(path) => path,
+            None => break,
+        };
+        run_chainloaded(path);
+
+        // without entries, there is no menu to return to
+        if config::entries().next().is_none() {
+            return Ok(());
+        }
+        show_menu = true;
+        timeout = None;
+    }
+
+    let result = boot_kernel(uefi);
+    if let Err(error) = result.as_ref() {
+        report_failure(error);
+    }
+
+    result
+}
+
+/// Run the EFI application at `path` on the boot volume until it exits.
+fn run_chainloaded(path: &str) {
+    info!("Starting {}...", path);
+
+    match chainload::chainload(path, config::get("load_options")) {
+        Ok(efi::status::SUCCESS) => info!("{} exited", path),
+        Ok(status) => warn!("{} exited with status {:#x}", path, status),
+        Err(status) => {
+            error!("Unable to start {}: {:#x}", path, status);
+            // leave the error on screen for a moment before the menu returns
+            efi::stall(CHAINLOAD_ERROR_DELAY_US);
+        },
+    }
+}
+
+/// Load the kernel and jump to it. Only returns on failure.
+fn boot_kernel(mut uefi: mercuros_uefi::Application) -> Result<(), Error> {
+    let mut tables = FirmwareTables::discover();
+    if let Some(path) = config::get("dtb") {
+        if let Some(dtb) = load_device_tree(path) {
+            tables.device_tree = Some(dtb);
+        }
+    }
+
+    if tables.device_tree.is_none() && tables.acpi_rsdp.is_none() {
+        error!("{}", Error::DeviceTreeUnavailable);
+        return Err(Error::DeviceTreeUnavailable);
+    }
+
+    attach_serial_console(tables.device_tree);
+    log_firmware_tables(&tables);
 
-    let entry_point = load_kernel(&mut uefi, &kernel::KERNEL.borrow()[..])
+    let framebuffer = discover_framebuffer();
+    if let Some(framebuffer) = framebuffer {
+        attach_framebuffer_console(framebuffer);
+        show_splash(framebuffer);
+    }
+
+    if log::enabled(log::Level::Debug, "mmap") {
+        log_memory_map()?;
+    }
+
+    let mut kernel = load_kernel()
         .map_err(|error| {
-            uefi.write_fmt(format_args!("{}\r\n", error));
+            error!("{}", error);
             error
         })?;
+    let entry_point = kernel.entry_point;
+    debug!("Kernel format: {}", kernel.format.name());
 
     if entry_point.is_null() {
-        mercuros_uefi::Console::write_string(
-            &mut uefi,
-            "Unable to determine entry point!\r\n"
-        );
+        error!("Unable to determine entry point!");
         return Err(Error::InvalidKernelImage);
     }
+    console::set_progress(60);
 
-    let dtb = match mercuros_uefi::Configuration::get_dtb(&mut uefi) {
-        Some(dtb) => dtb,
-        None => {
-            uefi.write_fmt(format_args!("{}\r\n", Error::DeviceTreeUnavailable));
+    let boot_info = BootInfo::allocate()
+        .map_err(|_| {
+            error!("{}", Error::MemoryAllocationFailed);
+            Error::MemoryAllocationFailed
+        })?;
+
+    let log_buffer = console::with_log_buffer(|log_buffer| MemoryRange {
+        address: log_buffer.address(),
+        size: log_buffer.size(),
+    });
+    if let Some(log_buffer) = log_buffer {
+        boot_info.log_buffer = log_buffer;
+    }
+
+    if let Some(framebuffer) = framebuffer {
+        boot_info.framebuffer = framebuffer;
+    }
+
+    boot_info.acpi_rsdp = tables.acpi_rsdp.unwrap_or(0);
+    boot_info.smbios = tables.smbios.unwrap_or(0);
+    boot_info.smbios3 = tables.smbios3.unwrap_or(0);
+
+    let dtb = tables.device_tree
+        .map(|dtb| prepare_device_tree(dtb, framebuffer.as_ref()))
+        .unwrap_or(core::ptr::null());
+    console::set_progress(90);
+
+    // Limine kernels may ask for a stack size, overriding the option
+    let stack_size = kernel.limine.as_ref()
+        .and_then(limine::Kernel::stack_size)
+        .or_else(|| config::get_usize("kernel_stack"))
+        .unwrap_or(DEFAULT_KERNEL_STACK_SIZE);
+    boot_info.kernel_stack = allocate_kernel_stack(stack_size)?;
+    boot_info.entry_abi = entry::ABI_VERSION;
+    boot_info.boot_hart_id = boot_hart_id(tables.device_tree).unwrap_or(u64::MAX);
+
+    // Linux finds everything through the device tree, on the hart it is told
+    if kernel.format == KernelFormat::LinuxImage {
+        if dtb.is_null() {
+            error!("Linux kernels require a DeviceTree");
             return Err(Error::DeviceTreeUnavailable);
+        }
+        if boot_info.boot_hart_id == u64::MAX {
+            error!("{}", Error::BootHartUnknown);
+            return Err(Error::BootHartUnknown);
+        }
+    }
+
+    // modules are only loaded for kernels that can find them
+    let mut modules = [None; MAX_MODULES];
+    let wants_modules = kernel.multiboot.is_some()
+        || kernel.limine.as_ref().map_or(false, limine::Kernel::wants_modules);
+    let module_count = match wants_modules {
+        true => load_modules(&mut modules)?,
+        false => 0,
+    };
+    let modules = &modules[..module_count];
+
+    // the memory maps are allocated after everything else, but the copies
+    // made for the kernel need to know their size now
+    let memory_map_capacity = plan_memory_map_capacity()?;
+
+    let multiboot_info = match kernel.multiboot.as_ref() {
+        Some(multiboot) => {
+            let writer = multiboot2::build_info(
+                multiboot,
+                modules,
+                framebuffer.as_ref(),
+                memory_map_capacity,
+            ).map_err(|error| {
+                error!("{}", error);
+                error
+            })?;
+            Some(writer)
         },
+        None => None,
     };
 
-    let memory_map = mercuros_uefi::Memory::get_memory_map(&mut uefi)
+    let limine_handoff = match kernel.limine.as_mut() {
+        Some(limine_kernel) => {
+            let handoff = limine::prepare(
+                limine_kernel,
+                &tables,
+                boot_info,
+                dtb,
+                modules,
+                framebuffer.as_ref(),
+                memory_map_capacity,
+            ).map_err(|error| {
+                error!("{}", error);
+                error
+            })?;
+            Some(handoff)
+        },
+        None => None,
+    };
+
+    let (mut efi_memory_map, mut physical_memory_map) = allocate_memory_maps(memory_map_capacity)?;
+    let memory_map_pages = allocate_memory_map_pages()?;
+    claim_boot_memory(&mut physical_memory_map, &kernel, boot_info, dtb, modules);
+    physical_memory_map.claim(
+        memory_map_pages.as_ptr() as u64,
+        memory_map_pages.len() as u64,
+        RegionKind::BootInfo,
+    );
+    if let (Some(multiboot), Some(writer)) = (kernel.multiboot.as_ref(), multiboot_info.as_ref()) {
+        multiboot2::claim_memory(&mut physical_memory_map, multiboot, writer);
+    }
+
+    boot_info.memory_map = {
+        let (address, size) = physical_memory_map.allocation();
+        MemoryRange { address, size }
+    };
+
+    info!("Booting to OS");
+    save_log_file();
+    console::set_progress(100);
+
+    // fetched after the last allocation, so it matches the map boot
+    // services are exited with unless firmware changes it in between
+    let memory_map = fetch_memory_map(&mut uefi, memory_map_pages)?;
+
+    // From here on, only the serial and framebuffer consoles are usable
+    let mut memory_map_current = false;
+    let result = efi::exit_boot_services(&mut efi_memory_map, |efi_memory_map| {
+        memory_map_current = same_memory_map(memory_map, efi_memory_map);
+        physical_memory_map.build(efi_memory_map)
+    });
+    if let Err(status) = result {
+        exit_boot_services_failed(status);
+    }
+    if !memory_map_current {
+        warn!(target: "mmap", "Memory map passed in a1 is not the final one");
+    }
+    fill_memory_map_info(boot_info, &efi_memory_map, &physical_memory_map);
+    let multiboot_info = multiboot_info
+        .and_then(|writer| multiboot2::finish_info(writer, &physical_memory_map));
+
+    debug!("Entering kernel at {:#018X}", entry_point as usize);
+
+    if let Some(handoff) = limine_handoff {
+        let entry = handoff.finish(physical_memory_map.regions());
+        unsafe { entry::enter_limine(entry.entry_point, entry.stack_top, entry.satp) }
+    }
+
+    unsafe {
+        match kernel.format {
+            KernelFormat::Elf | KernelFormat::Pe => entry::enter_kernel(
+                entry_point,
+                dtb,
+                memory_map as *const _ as *const core::ffi::c_void,
+                boot_info,
+                multiboot_info,
+            ),
+            KernelFormat::LinuxImage => entry::enter_linux(entry_point, dtb, boot_info),
+        }
+    }
+}
+
+/// Id of the hart Maia runs on, from the RISC-V boot protocol or else
+/// `/chosen/boot-hartid` in the device tree.
+fn boot_hart_id(device_tree: Option<*const u8>) -> Option<u64> {
+    if let Ok(riscv_boot) = efi::RiscvBoot::locate() {
+        match riscv_boot.boot_hart_id() {
+            Ok(hart_id) => return Some(hart_id as u64),
+            Err(status) => warn!("Unable to get boot hart id: {:#x}", status),
+        }
+    }
+
+    let device_tree = unsafe { fdt::DeviceTree::from_address(device_tree?) }.ok()?;
+    device_tree.chosen()?.property("boot-hartid")?.as_u64()
+}
+
+/// Allocate the stack the kernel is entered with.
+fn allocate_kernel_stack(size: usize) -> Result<MemoryRange, Error> {
+    let page_count = efi::memory::page_count(size.max(1));
+
+    let stack = efi::allocate_pages(efi::memory::LOADER_DATA, page_count)
+        .map_err(|status| {
+            error!("Unable to allocate kernel stack: {:#x}", status);
+            Error::MemoryAllocationFailed
+        })?;
+
+    Ok(MemoryRange {
+        address: stack.as_ptr() as u64,
+        size: stack.len() as u64,
+    })
+}
+
+/// Boot services are in an indeterminate state after a failed
+/// `ExitBootServices`, so there is no returning to firmware. Report the
+/// failure through the serial console and reset the system.
+fn exit_boot_services_failed(status: efi::Status) -> ! {
+    error!("ExitBootServices failed: {:#x}", status);
+    error!("Resetting system");
+
+    serial::sbi::system_reset(
+        serial::sbi::RESET_TYPE_COLD_REBOOT,
+        serial::sbi::RESET_REASON_SYSTEM_FAILURE,
+    );
+    efi::reset_system(status);
+
+    loop {}
+}
+
+/// Allocate the UEFI and physical memory maps handed to the kernel.
+/// Both are filled in right before exiting boot services.
+/// Number of regions the physical memory map, and the copies made of it for
+/// the kernel, are allocated for.
+fn plan_memory_map_capacity() -> Result<usize, Error> {
+    efi::MemoryMap::planned_capacity()
+        .map(PhysicalMemoryMap::capacity_for)
+        .map_err(|status| {
+            error!("Unable to get memory map: {:#x}", status);
+            Error::MemoryMapUnavailable
+        })
+}
+
+fn allocate_memory_maps(capacity: usize) -> Result<(efi::MemoryMap, PhysicalMemoryMap), Error> {
+    let efi_memory_map = efi::MemoryMap::allocate()
+        .map_err(|status| {
+            error!("Unable to get memory map: {:#x}", status);
+            Error::MemoryMapUnavailable
+        })?;
+
+    let physical_memory_map = PhysicalMemoryMap::allocate(capacity)
+        .map_err(|_| {
+            error!("{}", Error::MemoryAllocationFailed);
+            Error::MemoryAllocationFailed
+        })?;
+
+    Ok((efi_memory_map, physical_memory_map))
+}
+
+/// Pages for the `mercuros_uefi` memory map passed in `a1`, which must not
+/// live on Maia's stack. Allocated up front, as the map is fetched last.
+fn allocate_memory_map_pages() -> Result<&'static mut [u8], Error> {
+    let page_count = efi::memory::page_count(core::mem::size_of::<mercuros_uefi::MemoryMap>());
+    efi::allocate_pages(efi::memory::LOADER_DATA, page_count)
+        .map_err(|_| {
+            error!("{}", Error::MemoryAllocationFailed);
+            Error::MemoryAllocationFailed
+        })
+}
+
+/// Fetch the memory map passed in `a1` into `pages`.
+fn fetch_memory_map(
+    uefi: &mut mercuros_uefi::Application,
+    pages: &'static mut [u8],
+) -> Result<&'static mercuros_uefi::MemoryMap, Error> {
+    let memory_map = mercuros_uefi::Memory::get_memory_map(uefi)
         .map_err(|error| {
             let error: Error = error.into();
-            uefi.write_fmt(format_args!("{}\r\n", error));
+            error!("{}", error);
             error
         })?;
 
-    mercuros_uefi::Console::write_string(&mut uefi, "\r\nBooting to OS\r\n");
-    if mercuros_uefi::Image::exit_boot_services(uefi, &memory_map).is_err() {
-        // Unfortunately, we currently cannot handle errors here.
-        // UEFI boot services are in an indeterminate state so we cannot
-        // return either...
-        loop {}
+    let pointer = pages.as_mut_ptr() as *mut mercuros_uefi::MemoryMap;
+    unsafe {
+        pointer.write(memory_map);
+        Ok(&*pointer)
     }
+}
 
-    // Jump to kernel
-    unsafe {
-        asm!(
-            "jalr ra, 0({0})",
-            in(reg) entry_point,
-            in("a0") dtb,
-            in("a1") &memory_map as *const _,
-            out("ra") _,
-        );
+/// Whether both maps list the same descriptors. Does not allocate, so it can
+/// be used between fetching the memory map and exiting boot services.
+fn same_memory_map(memory_map: &mercuros_uefi::MemoryMap, efi_memory_map: &efi::MemoryMap) -> bool {
+    let descriptors = memory_map.into_iter()
+        .map(|descriptor| (descriptor.r#type, descriptor.physical_start, descriptor.number_of_pages));
+    let efi_descriptors = efi_memory_map.iter()
+        .map(|descriptor| (descriptor.r#type, descriptor.physical_start, descriptor.number_of_pages));
+
+    descriptors.eq(efi_descriptors)
+}
+
+/// Mark the memory holding data for the kernel in the physical memory map.
+fn claim_boot_memory(
+    memory_map: &mut PhysicalMemoryMap,
+    kernel: &LoadedKernel,
+    boot_info: &BootInfo,
+    dtb: *const u8,
+    modules: &[Option<Module>],
+) {
+    memory_map.claim(kernel.image.address, kernel.image.size, RegionKind::Kernel);
+    memory_map.claim(boot_info.kernel_stack.address, boot_info.kernel_stack.size, RegionKind::KernelStack);
+
+    for module in modules.iter().flatten() {
+        memory_map.claim(module.data.as_ptr() as u64, module.data.len() as u64, RegionKind::Initrd);
     }
 
-    loop {}
+    let boot_info_address = boot_info as *const BootInfo as u64;
+    let boot_info_size = core::mem::size_of::<BootInfo>() as u64;
+    let log_buffer = boot_info.log_buffer;
+    let dtb_size = unsafe { fdt::DeviceTree::from_address(dtb) }
+        .map(|device_tree| device_tree.total_size() as u64)
+        .unwrap_or(0);
+    let (map_address, map_size) = memory_map.allocation();
+
+    let ranges = [
+        (boot_info_address, boot_info_size),
+        (log_buffer.address, log_buffer.size),
+        (dtb as u64, dtb_size),
+        (map_address, map_size),
+    ];
+
+    for &(address, size) in ranges.iter() {
+        memory_map.claim(address, size, RegionKind::BootInfo);
+    }
 }
 
-/// Load and prepare kernel from ELF image.
-fn load_kernel(
-    uefi: &mut mercuros_uefi::Application,
-    elf_data: &[u8],
-) -> Result<*const core::ffi::c_void, Error> {
-    if let Ok(kernel_elf) = unsafe { elf::ElfFile::from_buffer(elf_data) } {
-        mercuros_uefi::Console::write_string(uefi, "\r\nLoading kernel...\r\n");
-
-        let virtual_entry = kernel_elf.header().get_entry_point();
-        let (virtual_base, page_count) = get_elf_memory_info(uefi, &kernel_elf)?;
-        let relocation_table: Option<elf::RelocationTable> = kernel_elf.relocation_table()?;
-
-        #[cfg(feature = "debug_kernel")]
-        {
-            uefi.write_fmt(format_args!(
-                "\r\nEntry point (virtual address): {:#018X}\r\n",
-                virtual_entry
-            ));
-
-            uefi.write_fmt(format_args!(
-                "Segment count: {}\r\n",
-                 kernel_elf.header().get_program_header_info().entry_count,
-            ));
-
-            if relocation_table.is_some() {
-                mercuros_uefi::Console::write_string(uefi, "Relocation table present\r\n");
-            }
+fn fill_memory_map_info(
+    boot_info: &mut BootInfo,
+    efi_memory_map: &efi::MemoryMap,
+    physical_memory_map: &PhysicalMemoryMap,
+) {
+    let regions = physical_memory_map.regions();
+    boot_info.memory_map.size = core::mem::size_of_val(regions) as u64;
+
+    let raw = efi_memory_map.as_bytes();
+    boot_info.efi_memory_map = EfiMemoryMap {
+        address: raw.as_ptr() as u64,
+        size: raw.len() as u64,
+        descriptor_size: efi_memory_map.descriptor_size() as u32,
+        descriptor_version: efi_memory_map.descriptor_version(),
+    };
+}
+
+/// Keep a copy of all console output for the kernel, sized by the
+/// `log_buffer` option (zero disables the buffer).
+fn attach_log_buffer() {
+    let size = config::get_usize("log_buffer").unwrap_or(log_buffer::DEFAULT_SIZE);
+    if size == 0 {
+        return;
+    }
+
+    match log_buffer::LogBuffer::allocate(size) {
+        Ok(log_buffer) => console::attach_log_buffer(log_buffer),
+        Err(status) => warn!("Unable to allocate log buffer: {:#x}", status),
+    }
+}
+
+/// Report configuration errors and apply the log level settings.
+fn configure_logging(config_status: Result<(), efi::Status>) {
+    if let Err(status) = config_status {
+        warn!("Unable to read configuration: {:#x}", status);
+    }
+
+    // levels of a previously selected entry do not carry over
+    let spec = config::get("log").unwrap_or("");
+    if !log::configure(spec) {
+        warn!("Invalid log level specification: {}", spec);
+    }
+}
+
+/// Append the boot log to the file named by the `log_file` option.
+fn save_log_file() {
+    let path = config::get("log_file").unwrap_or(log_file::DEFAULT_PATH);
+    if path.is_empty() {
+        return;
+    }
+    let max_size = config::get_usize("log_file_size").unwrap_or(log_file::DEFAULT_MAX_SIZE);
+
+    if let Err(status) = log_file::save(path, max_size) {
+        warn!("Unable to write log file {}: {:#x}", path, status);
+    }
+}
+
+/// Log a summary of the failure and persist the log, if configured.
+fn report_failure(error: &Error) {
+    error!("Boot failed: {}", error);
+    log_memory_summary();
+    save_log_file();
+}
+
+/// Log the amount of memory per memory map category.
+fn log_memory_summary() {
+    use efi::memory;
+
+    let memory_map = match efi::MemoryMap::allocate() {
+        Ok(memory_map) => memory_map,
+        Err(status) => {
+            warn!(target: "mmap", "Memory map unavailable: {:#x}", status);
+            return;
+        },
+    };
+
+    // conventional, loader, boot services, runtime services, ACPI, other
+    let mut pages = [0u64; 6];
+    let mut entry_count = 0;
+    for descriptor in memory_map.iter() {
+        let category = match descriptor.r#type {
+            memory::CONVENTIONAL_MEMORY => 0,
+            memory::LOADER_CODE | memory::LOADER_DATA => 1,
+            memory::BOOT_SERVICES_CODE | memory::BOOT_SERVICES_DATA => 2,
+            memory::RUNTIME_SERVICES_CODE | memory::RUNTIME_SERVICES_DATA => 3,
+            memory::ACPI_RECLAIM_MEMORY | memory::ACPI_MEMORY_NVS => 4,
+            _ => 5,
+        };
+        pages[category] += descriptor.number_of_pages;
+        entry_count += 1;
+    }
+    memory_map.free();
+
+    let kib = |pages: u64| pages * (memory::PAGE_SIZE as u64 / 1024);
+    info!(
+        target: "mmap",
+        "Memory map: {} entries, {} KiB free, {} KiB loader, {} KiB boot services, \
+         {} KiB runtime services, {} KiB ACPI, {} KiB other",
+        entry_count,
+        kib(pages[0]),
+        kib(pages[1]),
+        kib(pages[2]),
+        kib(pages[3]),
+        kib(pages[4]),
+        kib(pages[5]),
+    );
+}
+
+/// Mirror console output to the framebuffer if the `fb_console` option is set.
+fn attach_framebuffer_console(framebuffer: Framebuffer) {
+    if config::get_bool("fb_console") != Some(true) {
+        return;
+    }
+
+    let font = match Font::from_buffer(font::DEFAULT_FONT) {
+        Some(font) => font,
+        None => {
+            warn!("Invalid framebuffer console font");
+            return;
+        },
+    };
+
+    // UEFI identity maps all memory, and Maia never changes the mapping
+    match unsafe { TextConsole::new(framebuffer, font) } {
+        Some(text_console) => {
+            console::attach_framebuffer(text_console);
+            debug!("Framebuffer console attached");
+        },
+        None => warn!("Framebuffer too small for a text console"),
+    }
+}
+
+/// Show the boot splash given by the `splash` option, or the one embedded
+/// at build time.
+fn show_splash(framebuffer: Framebuffer) {
+    let data = match config::get("splash") {
+        Some("off") => return,
+        Some(path) => match read_file(path, MAX_SPLASH_SIZE) {
+            Ok(data) => data,
+            Err(status) => {
+                warn!("Unable to read splash image {}: {:#x}", path, status);
+                return;
+            },
+        },
+        None => match splash::embedded_image() {
+            Some(data) => data,
+            None => return,
+        },
+    };
+
+    let image = match Image::from_buffer(data) {
+        Ok(image) => image,
+        Err(error) => {
+            warn!("Unable to show splash image: {}", error);
+            return;
+        },
+    };
+
+    // UEFI identity maps all memory, and Maia never changes the mapping
+    match unsafe { Splash::show(framebuffer, &image) } {
+        Ok(splash) => {
+            console::show_splash(splash);
+            console::set_progress(10);
+        },
+        Err(error) => warn!("Unable to show splash image: {}", error),
+    }
+}
+
+/// Read a whole file from the boot volume into loader data pages.
+fn read_file(path: &str, max_size: usize) -> Result<&'static [u8], efi::Status> {
+    let mut file = efi::File::open_boot_volume()?
+        .open(path, efi::file::FILE_MODE_READ)?;
+
+    file.read_to_pages(efi::memory::LOADER_DATA, max_size)
+        .map(|data| &*data)
+}
+
+/// Locate the GOP framebuffer, switching to the resolution given by the
+/// `resolution` option if set.
+fn discover_framebuffer() -> Option<Framebuffer> {
+    let resolution = config::get("resolution").and_then(|resolution| {
+        let parsed = framebuffer::parse_resolution(resolution);
+        if parsed.is_none() {
+            warn!("Invalid resolution: {}", resolution);
         }
+        parsed
+    });
 
-        let kernel_buffer = allocate_elf_memory(
-            uefi,
-            virtual_base,
-            page_count,
-            relocation_table.is_some(),
-        )?;
-
-        // TODO: This is how the ELF base address is defined. I don't see the point, so
-        // probably I'm doing something wrong here...
-        let base_address = calculate_base_address(
-            uefi,
-            virtual_base,
-            kernel_buffer,
-        );
-        let physical_base = (virtual_base as i64 + base_address) as *const core::ffi::c_void;
-
-        copy_elf_memory(uefi, &kernel_elf, virtual_base, kernel_buffer)?;
-
-        // apply relocations
-        if let Some(relocations) = relocation_table.as_ref() {
-            #[cfg(feature = "debug_kernel")]
-            mercuros_uefi::Console::write_string(uefi, "\r\nApplying relocations:\r\n");
-
-            for rela in relocations {
-                #[cfg(feature = "debug_kernel")]
-                uefi.write_fmt(format_args!(
-                    "RELA [{:#x}] {:#018x}, {:#018x}\r\n",
-                    rela.info,
-                    rela.offset,
-                    rela.addend,
-                ));
-
-                match rela.info {
-                    elf::dynamic::R_RISCV_RELATIVE => {
-                        unsafe {
-                            let address = physical_base.add(rela.offset) as *mut u64;
-                            let value = physical_base.offset(rela.addend as isize) as u64;
-
-                            address.write(value);
-                        }
-                    },
-                    _ => return Err(Error::InvalidKernelImage),
-                }
+    match Framebuffer::discover(resolution) {
+        Ok(Some(framebuffer)) => {
+            debug!(
+                "Framebuffer: {}x{} at {:#018X}, stride {}",
+                framebuffer.width,
+                framebuffer.height,
+                framebuffer.address,
+                framebuffer.stride,
+            );
+            Some(framebuffer)
+        },
+        Ok(None) => {
+            debug!("No linear framebuffer available");
+            None
+        },
+        Err(efi::status::NOT_FOUND) => None,
+        Err(status) => {
+            warn!("Unable to set up framebuffer: {:#x}", status);
+            None
+        },
+    }
+}
+
+/// Copy the device tree to loader data memory, add the nodes describing
+/// what Maia set up for the kernel and apply the overlays listed in the
+/// `overlays` option.
+///
+/// Falls back to the unmodified device tree if it cannot be updated.
+fn prepare_device_tree(dtb: *const u8, framebuffer: Option<&Framebuffer>) -> *const u8 {
+    let device_tree = match unsafe { fdt::DeviceTree::from_address(dtb) } {
+        Ok(device_tree) => device_tree,
+        Err(_) => return dtb,
+    };
+
+    let mut overlays = [None; MAX_OVERLAYS];
+    let overlay_count = load_overlays(&mut overlays);
+    let overlays = &overlays[..overlay_count];
+    let overlay_size: usize = overlays.iter()
+        .flatten()
+        .map(|(_, overlay)| overlay.total_size())
+        .sum();
+
+    let page_count = efi::memory::page_count(
+        device_tree.total_size() + overlay_size + DEVICE_TREE_HEADROOM,
+    );
+    let buffer = match efi::allocate_pages(efi::memory::LOADER_DATA, page_count) {
+        Ok(buffer) => buffer,
+        Err(status) => {
+            warn!("Unable to allocate memory for DeviceTree: {:#x}", status);
+            return dtb;
+        },
+    };
+
+    let result = fdt::DeviceTreeWriter::new(&device_tree, buffer)
+        .and_then(|mut writer| {
+            if let Some(framebuffer) = framebuffer {
+                framebuffer.add_to_device_tree(&mut writer)?;
+            }
+
+            if let Some(cmdline) = config::get("cmdline") {
+                let chosen = writer.find_or_add_node("/chosen")?;
+                writer.set_property_str(chosen, "bootargs", cmdline)?;
             }
-        };
 
-        let entry_point = (virtual_entry as i64 + base_address) as *const core::ffi::c_void;
+            for (path, overlay) in overlays.iter().flatten() {
+                fdt::apply_overlay(&mut writer, overlay).map_err(|error| {
+                    error!("Unable to apply DeviceTree overlay {}: {:?}", path, error);
+                    error
+                })?;
+                info!("Applied DeviceTree overlay {}", path);
+            }
 
-        #[cfg(feature = "debug_kernel")]
-        uefi.write_fmt(format_args!(
-            "Kernel entry point in memory: {:#018X}\r\n",
-            entry_point as usize,
-        ));
+            Ok(writer.as_ptr())
+        });
 
-        Ok(entry_point)
-    } else {
-        Err(Error::InvalidKernelImage)
+    match result {
+        Ok(dtb) => dtb,
+        Err(error) => {
+            warn!("Unable to update DeviceTree: {:?}", error);
+            dtb
+        },
     }
 }
 
-fn get_elf_memory_info(
-    _uefi: &mut mercuros_uefi::Application,
-    kernel_elf: &elf::ElfFile,
-) -> Result<(usize, usize), Error> {
-    let program_headers = kernel_elf.program_headers()
-        .map_err(|_| Error::InvalidKernelImage)?;
+/// Read the overlays named by the comma separated `overlays` option,
+/// skipping any that cannot be read or are invalid.
+fn load_overlays(
+    overlays: &mut [Option<(&'static str, fdt::DeviceTree<'static>)>],
+) -> usize {
+    let paths = match config::get("overlays") {
+        Some(paths) => paths,
+        None => return 0,
+    };
 
-    let mut memory_limits: Option<(usize, usize, usize)> = None;
-    for program_header in program_headers {
-        if program_header.get_type() != Some(elf::SegmentType::Load) {
+    let mut count = 0;
+    for path in paths.split(',').map(str::trim).filter(|path| !path.is_empty()) {
+        if count == overlays.len() {
+            warn!("Too many DeviceTree overlays, ignoring {}", path);
             continue;
         }
 
-        let base = program_header.get_page_base();
-        let address = program_header.get_virtual_address();
-        let size = program_header.get_memory_size();
-
-        #[cfg(feature = "debug_kernel")]
-        {
-            mercuros_uefi::Console::write_string(_uefi, "\r\nSegment:\r\n");
-            _uefi.write_fmt(format_args!("offset: {:#018x}\r\n", program_header.get_offset()));
-            _uefi.write_fmt(format_args!("vaddr: {:#018x}\r\n", address));
-            _uefi.write_fmt(format_args!("filesz: {:#018x}\r\n", program_header.get_file_size()));
-            _uefi.write_fmt(format_args!("memsz: {:#018x}\r\n", size));
+        let data = match read_file(path, MAX_DEVICE_TREE_SIZE) {
+            Ok(data) => data,
+            Err(status) => {
+                warn!("Unable to read DeviceTree overlay {}: {:#x}", path, status);
+                continue;
+            },
+        };
+
+        match fdt::DeviceTree::from_buffer(data) {
+            Ok(overlay) => {
+                overlays[count] = Some((path, overlay));
+                count += 1;
+            },
+            Err(error) => warn!("Invalid DeviceTree overlay {}: {:?}", path, error),
         }
+    }
 
-        if let Some((lowest_base, highest_address, highest_size)) = memory_limits {
-            if base < lowest_base {
-                memory_limits = Some((base, highest_address, highest_size));
-            } else if address > highest_address {
-                memory_limits = Some((lowest_base, address, size));
-            }
-        } else {
-            memory_limits = Some((base, address, size));
+    count
+}
+
+/// Read the device tree blob named by the `dtb` option, replacing the one
+/// provided by firmware. Falls back to the firmware DTB on failure.
+fn load_device_tree(path: &str) -> Option<*const u8> {
+    let data = match read_file(path, MAX_DEVICE_TREE_SIZE) {
+        Ok(data) => data,
+        Err(status) => {
+            warn!("Unable to read DeviceTree {}: {:#x}", path, status);
+            return None;
+        },
+    };
+
+    match fdt::DeviceTree::from_buffer(data) {
+        Ok(device_tree) => {
+            info!("Using DeviceTree {}", path);
+            Some(device_tree.as_bytes().as_ptr())
+        },
+        Err(error) => {
+            warn!("Invalid DeviceTree {}: {:?}", path, error);
+            None
+        },
+    }
+}
+
+fn log_firmware_tables(tables: &FirmwareTables) {
+    let tables = [
+        ("DeviceTree", tables.device_tree.map(|dtb| dtb as u64)),
+        ("ACPI RSDP", tables.acpi_rsdp),
+        ("SMBIOS", tables.smbios),
+        ("SMBIOS 3", tables.smbios3),
+    ];
+
+    for &(name, address) in tables.iter() {
+        if let Some(address) = address {
+            debug!("{} at {:#018X}", name, address);
         }
     }
+}
 
-    if let Some((lowest_base, highest_address, highest_size)) = memory_limits {
-        let mut page_count = (highest_address + highest_size - lowest_base) / 4096;
-        // round up
-        if highest_size & 0xFFF > 0 {
-            page_count += 1;
+/// Mirror console output to the UART named in `/chosen/stdout-path`,
+/// falling back to the SBI debug console.
+fn attach_serial_console(dtb: Option<*const u8>) {
+    let serial = match dtb.map(|dtb| unsafe { fdt::DeviceTree::from_address(dtb) }) {
+        Some(Ok(device_tree)) => serial::Serial::from_device_tree(&device_tree),
+        Some(Err(_)) => {
+            warn!("Unable to parse DeviceTree!");
+            None
+        },
+        None => None,
+    };
+
+    if let Some(serial) = serial.or_else(serial::Serial::sbi) {
+        debug!("Serial console: {}", serial.name());
+        console::attach_serial(serial);
+    }
+}
+
+/// Kernel image placed in memory, ready to be entered.
+pub struct LoadedKernel {
+    format: KernelFormat,
+    entry_point: *const core::ffi::c_void,
+    /// Memory allocated for the kernel segments.
+    image: MemoryRange,
+    /// Set for kernels with a Multiboot2 header.
+    multiboot: Option<multiboot2::Kernel>,
+    /// Set for ELF kernels with Limine requests, entered at a virtual
+    /// address.
+    limine: Option<limine::Kernel>,
+}
+
+impl LoadedKernel {
+    /// Kernel entered at `entry_point`, loaded into `image`, without
+    /// anything for Multiboot2 or Limine.
+    pub fn new(format: KernelFormat, entry_point: *const core::ffi::c_void, image: &[u8]) -> LoadedKernel {
+        LoadedKernel {
+            format,
+            entry_point,
+            image: MemoryRange {
+                address: image.as_ptr() as u64,
+                size: image.len() as u64,
+            },
+            multiboot: None,
+            limine: None,
         }
+    }
+}
 
-        #[cfg(feature = "debug_kernel")]
-        _uefi.write_fmt(format_args!("\r\nvirtual_base: {:#018x}\r\n", lowest_base));
+/// Module loaded for a Multiboot2 or Limine kernel.
+#[derive(Clone, Copy)]
+pub struct Module {
+    pub data: &'static [u8],
+    pub path: &'static str,
+    /// Text following the path in the `modules` option.
+    pub string: &'static str,
+}
 
-        Ok((lowest_base, page_count))
-    } else {
-        Err(Error::InvalidKernelImage)
+/// Load the kernel named by the `kernel` option from the boot volume, or
+/// else the one embedded at build time.
+fn load_kernel() -> Result<LoadedKernel, Error> {
+    match config::get("kernel") {
+        Some(path) => load_kernel_file(path),
+        None => load_kernel_image(&kernel::KERNEL.borrow()[..]),
     }
 }
 
-/// Allocate memory for the ELF file.
-fn allocate_elf_memory(
-    uefi: &mut mercuros_uefi::Application,
-    virtual_base: usize,
-    page_count: usize,
-    dynamic: bool,
+/// Load and prepare kernel from an image in memory, decompressing it first
+/// if it is compressed.
+fn load_kernel_image(data: &[u8]) -> Result<LoadedKernel, Error> {
+    let format = match compression::Format::detect(data) {
+        Some(format) => format,
+        None => return load_uncompressed_image(data),
+    };
+
+    let kernel_data = decompress_kernel(format, data)?;
+    let result = load_uncompressed_image(kernel_data);
+    efi::free_pages(kernel_data);
+
+    result
+}
+
+/// Load and prepare kernel from an image in memory, in any format Maia
+/// can boot.
+fn load_uncompressed_image(data: &[u8]) -> Result<LoadedKernel, Error> {
+    match kernel_format(data) {
+        Some(KernelFormat::Elf) => load_elf_image(data),
+        Some(KernelFormat::LinuxImage) => {
+            let image = LinuxImage::from_header(data)?;
+            linux_image::load(&image, data.len(), |offset, destination| {
+                destination.copy_from_slice(&data[offset..(offset + destination.len())]);
+                Ok(())
+            })
+        },
+        Some(KernelFormat::Pe) if firmware_pe_loader() => Err(start_efi_stub(None, data)),
+        Some(KernelFormat::Pe) => load_pe_image(data),
+        None => Err(Error::InvalidKernelImage),
+    }
+}
+
+/// Format the kernel image is booted as, given its first bytes. Linux
+/// Images with an EFI stub are started as PE images by the firmware
+/// loader, if selected.
+fn kernel_format(header: &[u8]) -> Option<KernelFormat> {
+    match KernelFormat::detect(header) {
+        Some(KernelFormat::LinuxImage) if header.starts_with(&pe::MAGIC) && firmware_pe_loader() =>
+            Some(KernelFormat::Pe),
+        format => format,
+    }
+}
+
+/// Whether PE kernels are loaded by the firmware, as selected by the
+/// `pe_loader` option, instead of by Maia.
+fn firmware_pe_loader() -> bool {
+    match config::get("pe_loader") {
+        Some("firmware") => true,
+        Some("maia") | None => false,
+        Some(other) => {
+            warn!("Unknown PE loader: {}", other);
+            false
+        },
+    }
+}
+
+/// Decompress a kernel image into newly allocated pages. Without a size
+/// recorded in the image, the buffer grows until the kernel fits.
+fn decompress_kernel(
+    format: compression::Format,
+    data: &[u8],
 ) -> Result<&'static mut [u8], Error> {
-    #[cfg(feature = "debug_kernel")]
-    {
-        if dynamic {
-            uefi.write_fmt(format_args!("\r\nAllocating {} page(s)\r\n", page_count));
-        } else {
-            uefi.write_fmt(format_args!(
-                "\r\nAllocating {} page(s) at {:#018X}\r\n",
-                page_count,
-                virtual_base,
-            ));
+    info!("Decompressing {} kernel image...", format.name());
+
+    let recorded_size = format.decompressed_size(data);
+    let mut size = recorded_size.unwrap_or(data.len() * 4).max(efi::memory::PAGE_SIZE);
+
+    let workspace = efi::allocate_pages(
+        efi::memory::LOADER_DATA,
+        efi::memory::page_count(compression::WORKSPACE_SIZE),
+    ).map_err(|_| Error::MemoryAllocationFailed)?;
+
+    let result = loop {
+        if size > MAX_KERNEL_SIZE {
+            break Err(compression::DecompressionError::OutputOverflow.into());
         }
+
+        let buffer = match efi::allocate_pages(
+            efi::memory::LOADER_DATA,
+            efi::memory::page_count(size),
+        ) {
+            Ok(buffer) => buffer,
+            Err(_) => break Err(Error::MemoryAllocationFailed),
+        };
+
+        match format.decompress(data, buffer, workspace) {
+            Ok(decompressed_size) => {
+                // give back the pages beyond the kernel
+                let used = efi::memory::page_count(decompressed_size) * efi::memory::PAGE_SIZE;
+                let (kernel, rest) = buffer.split_at_mut(used);
+                if !rest.is_empty() {
+                    efi::free_pages(rest);
+                }
+                debug!("Decompressed kernel size: {} bytes", decompressed_size);
+                break Ok(&mut kernel[..decompressed_size]);
+            },
+            Err(compression::DecompressionError::OutputOverflow) if recorded_size.is_none() => {
+                efi::free_pages(buffer);
+                size *= 2;
+            },
+            Err(error) => {
+                efi::free_pages(buffer);
+                break Err(error.into());
+            },
+        }
+    };
+    efi::free_pages(workspace);
+
+    result
+}
+
+/// Load and prepare kernel from an ELF image in memory.
+fn load_elf_image(elf_data: &[u8]) -> Result<LoadedKernel, Error> {
+    let kernel_elf = unsafe { elf::ElfFile::from_buffer(elf_data) }?;
+    info!("Loading kernel...");
+
+    load_elf(&kernel_elf, |offset, destination| {
+        destination.copy_from_slice(kernel_elf.data(offset, destination.len())?);
+        Ok(())
+    })
+}
+
+/// Load the kernel from a file on the boot volume. Only the headers are
+/// read up front, the kernel is read straight into place. Compressed files
+/// are read whole and decompressed instead.
+fn load_kernel_file(path: &str) -> Result<LoadedKernel, Error> {
+    info!("Loading kernel from {}...", path);
+
+    let mut file = efi::File::open_boot_volume()
+        .and_then(|volume| volume.open(path, efi::file::FILE_MODE_READ))
+        .map_err(Error::KernelFileUnreadable)?;
+    let file_size = file.size().map_err(Error::KernelFileUnreadable)? as usize;
+
+    let mut header = [0u8; kernel_format::DETECT_SIZE];
+    file.read_exact(&mut header).map_err(Error::KernelFileUnreadable)?;
+    if compression::Format::detect(&header).is_some() {
+        let data = file.read_to_pages(efi::memory::LOADER_DATA, MAX_KERNEL_SIZE)
+            .map_err(Error::KernelFileUnreadable)?;
+        let result = load_kernel_image(data);
+        efi::free_pages(data);
+
+        return result;
     }
 
-    let buffer = {
-        if dynamic {
-            mercuros_uefi::Memory::allocate_pages(uefi, page_count)
-        } else {
-            mercuros_uefi::Memory::allocate_pages_at(uefi, virtual_base as u64, page_count)
+    let mut read = |offset: usize, destination: &mut [u8]| {
+        file.set_position(offset as u64)
+            .and_then(|_| file.read_exact(destination))
+            .map_err(Error::KernelFileUnreadable)
+    };
+
+    match kernel_format(&header) {
+        Some(KernelFormat::Elf) => {
+            let (headers, headers_size) = read_elf_headers(&header, &mut read)?;
+            let kernel_elf = unsafe {
+                elf::ElfFile::from_headers(&headers[..headers_size], file_size)
+            }?;
+
+            let result = load_elf(&kernel_elf, read);
+            efi::free_pages(headers);
+
+            result
+        },
+        Some(KernelFormat::LinuxImage) => {
+            let image = LinuxImage::from_header(&header)?;
+            linux_image::load(&image, file_size, read)
+        },
+        Some(KernelFormat::Pe) if firmware_pe_loader() => {
+            let device_path = efi::loaded_image()
+                .and_then(|loaded_image| efi::DevicePath::file(loaded_image.device_handle, path))
+                .map_err(Error::KernelFileUnreadable)?;
+            let data = file.read_to_pages(efi::memory::LOADER_DATA, MAX_KERNEL_SIZE)
+                .map_err(Error::KernelFileUnreadable)?;
+            let error = start_efi_stub(Some(&device_path), data);
+            efi::free_pages(data);
+
+            Err(error)
+        },
+        Some(KernelFormat::Pe) => {
+            let (headers, headers_size) = pe::read_headers(file_size, &mut read)?;
+            let kernel_pe = pe::PeFile::from_headers(&headers[..headers_size], file_size)?;
+
+            let result = pe::load(&kernel_pe, read);
+            efi::free_pages(headers);
+
+            result
+        },
+        None => Err(Error::InvalidKernelImage),
+    }
+}
+
+/// Read the ELF file header and program headers into newly allocated pages,
+/// returning them along with the size of the headers.
+fn read_elf_headers<F>(header: &[u8], read: &mut F) -> Result<(&'static mut [u8], usize), Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    let size = elf::ElfFile::headers_size(header)?;
+    if size > MAX_ELF_HEADERS_SIZE {
+        return Err(Error::InvalidElf(elf::ElfError::BufferOverflow));
+    }
+
+    let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, efi::memory::page_count(size))
+        .map_err(|_| Error::MemoryAllocationFailed)?;
+    read(0, &mut buffer[..size])?;
+
+    Ok((buffer, size))
+}
+
+/// Load the segments of `kernel_elf` into newly allocated memory, with their
+/// file data provided by `read(file_offset, destination)`, and relocate them.
+fn load_elf<F>(kernel_elf: &elf::ElfFile, mut read: F) -> Result<LoadedKernel, Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    let virtual_entry = kernel_elf.header().get_entry_point();
+    let relocatable = kernel_elf.header().is_relocatable();
+
+    // relocatable kernels go anywhere, static ones to their physical load
+    // address, unless it is in the higher half, which only works for
+    // Limine kernels
+    let mut layout = match relocatable {
+        true => plan_elf_layout(kernel_elf, elf::AddressKind::Virtual)?,
+        false => plan_elf_layout(kernel_elf, elf::AddressKind::Physical)?,
+    };
+    if layout.address_kind == elf::AddressKind::Physical && limine::is_higher_half(layout.base as u64) {
+        layout = plan_elf_layout(kernel_elf, elf::AddressKind::Virtual)?;
+    }
+
+    debug!(target: "elf", "Entry point (virtual address): {:#018X}", virtual_entry);
+    debug!(
+        target: "elf",
+        "Segment count: {}",
+        kernel_elf.header().get_program_header_info().entry_count,
+    );
+
+    let kernel_buffer = allocate_elf_memory(&layout)?;
+
+    let result = place_elf_image(kernel_elf, &layout, kernel_buffer, relocatable, virtual_entry, &mut read)
+        .and_then(|(entry_offset, limine_base)| {
+            let multiboot_header = match limine_base {
+                Some(_) => None,
+                None => find_multiboot_header(kernel_elf.file_size(), &mut read)?,
+            };
+            let multiboot = match multiboot_header {
+                Some(_) => Some(multiboot2::Kernel {
+                    elf_sections: load_elf_sections(kernel_elf, &layout, kernel_buffer, &mut read)?,
+                }),
+                None => None,
+            };
+            Ok((entry_offset, limine_base, multiboot))
+        });
+    let (entry_offset, limine_base, multiboot) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            efi::free_pages(kernel_buffer);
+            return Err(error);
+        },
+    };
+
+    let entry_point = kernel_buffer[entry_offset..].as_ptr() as *const core::ffi::c_void;
+    debug!(target: "elf", "Kernel entry point in memory: {:#018X}", entry_point as usize);
+
+    let image = MemoryRange {
+        address: kernel_buffer.as_ptr() as u64,
+        size: kernel_buffer.len() as u64,
+    };
+    let (entry_point, limine) = match limine_base {
+        Some(virtual_base) => {
+            let entry_point = virtual_base + entry_offset as u64;
+            let kernel = limine::Kernel::new(kernel_buffer, virtual_base, entry_point);
+            (entry_point as *const core::ffi::c_void, Some(kernel))
+        },
+        None => (entry_point, None),
+    };
+
+    Ok(LoadedKernel {
+        format: KernelFormat::Elf,
+        entry_point,
+        image,
+        multiboot,
+        limine,
+    })
+}
+
+/// Load the segments into `kernel_buffer` and relocate them. Returns the
+/// offset of the entry point in the image and, for Limine kernels, the
+/// virtual address the image runs at.
+fn place_elf_image<F>(
+    kernel_elf: &elf::ElfFile,
+    layout: &elf::Layout,
+    kernel_buffer: &mut [u8],
+    relocatable: bool,
+    virtual_entry: usize,
+    read: &mut F,
+) -> Result<(usize, Option<u64>), Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    layout.load_with(kernel_buffer, &mut *read)?;
+
+    // Limine requests decide where the image runs, so they are looked for
+    // in the loaded segments, before relocating them
+    let limine_base = match limine::contains_requests(kernel_buffer) {
+        true => Some(limine::virtual_base(kernel_elf, layout, relocatable)?),
+        false => None,
+    };
+    if let Some(virtual_base) = limine_base {
+        info!("Kernel uses the Limine boot protocol");
+        debug!(target: "elf", "Virtual base: {:#018x}", virtual_base);
+    } else if !relocatable && layout.address_kind == elf::AddressKind::Virtual {
+        error!(target: "elf", "Physical load address in the higher half, but no Limine requests");
+        return Err(Error::KernelRangeUnavailable);
+    }
+
+    // difference between where the image runs and where it is linked
+    let run_base = limine_base.unwrap_or(kernel_buffer.as_ptr() as u64);
+    let base_address = calculate_base_address(layout.base, run_base);
+
+    let relocation_table = if relocatable {
+        layout.relocation_table(kernel_buffer)?
+    } else {
+        None
+    };
+    // apply relocations
+    if let Some(relocations) = relocation_table.as_ref() {
+        debug!(target: "reloc", "Applying relocations");
+
+        let image = kernel_buffer.as_mut_ptr();
+        let image_size = kernel_buffer.len();
+        for rela in relocations {
+            trace!(
+                target: "reloc",
+                "RELA [{:#x}] {:#018x}, {:#018x}",
+                rela.info,
+                rela.offset,
+                rela.addend,
+            );
+
+            let image_offset = rela.offset.wrapping_sub(layout.base);
+            if image_offset.saturating_add(8) > image_size {
+                error!(target: "reloc", "Relocation outside of the image at {:#018x}", rela.offset);
+                return Err(Error::InvalidKernelImage);
+            }
+
+            match rela.info {
+                elf::dynamic::R_RISCV_RELATIVE => {
+                    let value = base_address.wrapping_add(rela.addend) as u64;
+                    unsafe { (image.add(image_offset) as *mut u64).write_unaligned(value); }
+                },
+                _ => {
+                    error!(target: "reloc", "Unsupported relocation type {:#x}", rela.info);
+                    return Err(Error::InvalidKernelImage);
+                },
+            }
         }
     };
 
-    match buffer {
-        Some(buffer) => Ok(buffer),
-        None => Err(Error::MemoryAllocationFailed),
+    synchronize_instruction_cache();
+
+    #[cfg(debug_assertions)]
+    verify_kernel_text(layout, kernel_buffer, base_address, relocation_table.as_ref(), read)?;
+
+    let entry_offset = layout.image_offset(virtual_entry)
+        .ok_or_else(|| {
+            error!(target: "elf", "Entry point {:#018x} outside of the image", virtual_entry);
+            Error::InvalidKernelImage
+        })?;
+
+    Ok((entry_offset, limine_base))
+}
+
+/// Search the start of the kernel file, provided by
+/// `read(file_offset, destination)`, for a Multiboot2 header.
+fn find_multiboot_header<F>(file_size: usize, read: &mut F) -> Result<Option<multiboot2::Header>, Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    let size = file_size.min(multiboot2::SEARCH_SIZE);
+    let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, efi::memory::page_count(size))
+        .map_err(|_| Error::MemoryAllocationFailed)?;
+
+    let result = read(0, &mut buffer[..size])
+        .and_then(|_| Ok(multiboot2::Header::find(&buffer[..size])?));
+    efi::free_pages(buffer);
+
+    let header = result?;
+    if let Some(header) = header.as_ref() {
+        info!("Kernel has a Multiboot2 header at offset {:#x}", header.offset);
     }
+
+    Ok(header)
 }
 
-fn calculate_base_address(
-    _uefi: &mut mercuros_uefi::Application,
-    virtual_base: usize,
-    buffer: &[u8],
-) -> i64 {
-    let physical_base = &buffer[0] as *const u8 as u64;
-    let base_address = physical_base as i64 - virtual_base as i64;
+/// Copy the section header table of a Multiboot2 kernel loaded into
+/// `image`, pointing the sections that are part of its segments there, and
+/// read the remaining sections with file data in after the table.
+fn load_elf_sections<F>(
+    kernel_elf: &elf::ElfFile,
+    layout: &elf::Layout,
+    image: &[u8],
+    read: &mut F,
+) -> Result<Option<multiboot2::ElfSections>, Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    let info = kernel_elf.header().get_section_header_info();
+    if info.entry_count == 0 {
+        return Ok(None);
+    }
 
-    #[cfg(feature = "debug_kernel")]
-    _uefi.write_fmt(format_args!("\r\nELF base address: {:#018X}\r\n", base_address));
+    let table_size = info.entry_count.checked_mul(info.entry_size)
+        .filter(|&size| size <= MAX_ELF_HEADERS_SIZE)
+        .ok_or(Error::InvalidElf(elf::ElfError::BufferOverflow))?;
+    if info.entry_size < elf::SectionHeader::SIZE || !kernel_elf.contains(info.offset, table_size) {
+        return Err(Error::InvalidElf(elf::ElfError::InvalidFormat));
+    }
 
-    base_address
+    // the table is read twice, first to size the section data
+    let table = efi::allocate_pages(efi::memory::LOADER_DATA, efi::memory::page_count(table_size))
+        .map_err(|_| Error::MemoryAllocationFailed)?;
+    let result = read(info.offset, &mut table[..table_size]).map(|_| {
+        table[..table_size].chunks_exact_mut(info.entry_size)
+            .filter_map(elf::SectionHeader::from_entry_mut)
+            .filter(|section| !section.is_allocated() && section.has_file_data())
+            .map(|section| align_section(section.get_size()))
+            .fold(0usize, usize::saturating_add)
+    });
+    efi::free_pages(table);
+    let data_size = result?;
+    if data_size > MAX_KERNEL_SIZE {
+        return Err(Error::InvalidElf(elf::ElfError::BufferOverflow));
+    }
+
+    let buffer = efi::allocate_pages(
+        efi::memory::LOADER_DATA,
+        efi::memory::page_count(table_size + data_size),
+    ).map_err(|_| Error::MemoryAllocationFailed)?;
+
+    let result = place_elf_sections(
+        kernel_elf,
+        layout,
+        image,
+        &mut buffer[..(table_size + data_size)],
+        table_size,
+        read,
+    );
+    if let Err(error) = result {
+        efi::free_pages(buffer);
+        return Err(error);
+    }
+
+    debug!(
+        target: "elf",
+        "Section headers: {}, non-loaded section data: {} bytes",
+        info.entry_count,
+        data_size,
+    );
+
+    Ok(Some(multiboot2::ElfSections {
+        buffer,
+        table_size,
+        entry_size: info.entry_size,
+        names_index: kernel_elf.header().get_section_names_index(),
+    }))
 }
 
-/// Copy ELF loadable segments into memory.
-fn copy_elf_memory(
-    _uefi: &mut mercuros_uefi::Application,
+/// Read the section header table into the start of `buffer` and the
+/// sections not loaded with the segments after it, updating the section
+/// addresses.
+fn place_elf_sections<F>(
     kernel_elf: &elf::ElfFile,
-    virtual_base: usize,
-    target_buffer: &mut [u8],
-) -> Result<(), Error> {
-    let program_headers = kernel_elf.program_headers()
-        .map_err(|_| Error::InvalidKernelImage)?;
-
-    for program_header in program_headers {
-        if program_header.get_type() != Some(elf::SegmentType::Load) {
+    layout: &elf::Layout,
+    image: &[u8],
+    buffer: &mut [u8],
+    table_size: usize,
+    read: &mut F,
+) -> Result<(), Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    let info = kernel_elf.header().get_section_header_info();
+    let (table, data) = buffer.split_at_mut(table_size);
+    read(info.offset, table)?;
+
+    let mut position = 0;
+    for section in table.chunks_exact_mut(info.entry_size).filter_map(elf::SectionHeader::from_entry_mut) {
+        if section.is_allocated() {
+            if let Some(offset) = layout.image_offset(section.get_address()) {
+                section.set_address(image[offset..].as_ptr() as u64);
+            }
+        } else if section.has_file_data() {
+            let size = section.get_size();
+            if !kernel_elf.contains(section.get_offset(), size) || position + size > data.len() {
+                return Err(Error::InvalidElf(elf::ElfError::BufferOverflow));
+            }
+
+            read(section.get_offset(), &mut data[position..(position + size)])?;
+            section.set_address(data[position..].as_ptr() as u64);
+            position += align_section(size);
+        }
+    }
+
+    Ok(())
+}
+
+/// Size of section data placed after the section header table, keeping
+/// the sections 8 byte aligned.
+fn align_section(size: usize) -> usize {
+    size.saturating_add(7) & !7
+}
+
+/// Load and prepare kernel from a PE image in memory.
+fn load_pe_image(pe_data: &[u8]) -> Result<LoadedKernel, Error> {
+    let kernel_pe = pe::PeFile::from_buffer(pe_data)?;
+    info!("Loading kernel...");
+
+    pe::load(&kernel_pe, |offset, destination| {
+        destination.copy_from_slice(&pe_data[offset..(offset + destination.len())]);
+        Ok(())
+    })
+}
+
+/// Read the modules listed in the comma separated `modules` option, each
+/// a path on the boot volume optionally followed by a string passed along
+/// with it, e.g. `\boot\initrd.img root=ram`.
+fn load_modules(modules: &mut [Option<Module>]) -> Result<usize, Error> {
+    let entries = match config::get("modules") {
+        Some(entries) => entries,
+        None => return Ok(0),
+    };
+
+    let mut count = 0;
+    for entry in entries.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        if count == modules.len() {
+            warn!("Too many modules, ignoring {}", entry);
             continue;
         }
 
-        let page_base = program_header.get_page_base();
-        let page_count = program_header.get_page_count();
-
-        #[cfg(feature = "debug_kernel")]
-        _uefi.write_fmt(format_args!(
-            "Copying {} page(s) from offset {:#018x} to {:#018x}\r\n",
-            page_count,
-            program_header.get_file_base(),
-            page_base
-        ));
-
-        kernel_elf.copy_segment_pages(
-            program_header,
-            &mut target_buffer[page_base..(page_base + page_count * 4096)]
-        )
-            .map_err(|err| core::convert::Into::<Error>::into(err))?;
-
-        let uninitialized = program_header.get_memory_size() - program_header.get_file_size();
-        if uninitialized > 0 {
-            // zero fill uninitialized data region
-            let offset = program_header.get_virtual_address() + program_header.get_file_size();
-            target_buffer[offset..(offset + uninitialized)].fill(0u8);
+        let (path, string) = match entry.find(char::is_whitespace) {
+            Some(index) => (&entry[..index], entry[index..].trim_start()),
+            None => (entry, ""),
+        };
+
+        let data = read_module(path).map_err(|status| {
+            error!("Unable to read module {}: {:#x}", path, status);
+            Error::ModuleUnreadable(status)
+        })?;
+        debug!("Module {}: {} bytes at {:#018X}", path, data.len(), data.as_ptr() as usize);
+
+        modules[count] = Some(Module { data, path, string });
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Read a module file into loader data pages below 4 GiB.
+fn read_module(path: &str) -> Result<&'static [u8], efi::Status> {
+    let mut file = efi::File::open_boot_volume()?
+        .open(path, efi::file::FILE_MODE_READ)?;
+    let size = file.size()? as usize;
+    if size > MAX_KERNEL_SIZE {
+        return Err(efi::status::BUFFER_TOO_SMALL);
+    }
+
+    let buffer = efi::allocate_pages_below(
+        efi::memory::LOADER_DATA,
+        MODULE_ADDRESS_LIMIT,
+        efi::memory::page_count(size.max(1)),
+    )?;
+    if let Err(status) = file.read_exact(&mut buffer[..size]) {
+        efi::free_pages(buffer);
+        return Err(status);
+    }
+
+    Ok(&buffer[..size])
+}
+
+/// Start an EFI stub kernel through the firmware's `LoadImage` and
+/// `StartImage`, passing the `cmdline` option as its load options. The
+/// kernel exits boot services itself, so it gets no Maia boot info. Only
+/// returns if the kernel does not start or exits.
+fn start_efi_stub(device_path: Option<&efi::DevicePath>, data: &[u8]) -> Error {
+    info!("Starting EFI stub kernel");
+    save_log_file();
+
+    match chainload::start(device_path, data, config::get("cmdline")) {
+        Ok(status) => Error::KernelExited(status),
+        Err(status) => Error::KernelNotStarted(status),
+    }
+}
+
+/// Make the loaded kernel code visible to instruction fetch, on every hart.
+pub fn synchronize_instruction_cache() {
+    unsafe { asm!("fence.i"); }
+    serial::sbi::remote_fence_i();
+}
+
+/// Check that the executable segments in memory match the ELF file,
+/// with relocated words holding their expected values.
+#[cfg(debug_assertions)]
+fn verify_kernel_text<F>(
+    layout: &elf::Layout,
+    image: &[u8],
+    base_address: i64,
+    relocation_table: Option<&elf::RelocationTable>,
+    read: &mut F,
+) -> Result<(), Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    for segment in layout.segments().iter().filter(|segment| segment.executable) {
+        let memory = &image[segment.image_offset..(segment.image_offset + segment.file_size)];
+
+        // the file data is read in chunks, so it need not be in memory
+        let mut chunk = [0u8; 512];
+        let mut expected = 0u64;
+        let mut position = 0;
+        while position < segment.file_size {
+            let length = chunk.len().min(segment.file_size - position);
+            read(segment.file_offset + position, &mut chunk[..length])?;
+            expected = expected.wrapping_add(text_checksum(&chunk[..length], position));
+            position += length;
         }
+
+        for rela in relocation_table.into_iter().flatten() {
+            let image_offset = rela.offset.wrapping_sub(layout.base);
+            let offset = match image_offset.checked_sub(segment.image_offset) {
+                Some(offset) if offset.saturating_add(8) <= segment.file_size => offset,
+                _ => continue,
+            };
+
+            let mut original = [0u8; 8];
+            read(segment.file_offset + offset, &mut original)?;
+            let value = base_address.wrapping_add(rela.addend) as u64;
+            expected = expected
+                .wrapping_sub(text_checksum(&original, offset))
+                .wrapping_add(text_checksum(&value.to_le_bytes(), offset));
+        }
+
+        let actual = text_checksum(memory, 0);
+        if actual != expected {
+            error!(
+                target: "elf",
+                "Kernel text at {:#018x} does not match the ELF file: checksum {:#018x}, expected {:#018x}",
+                memory.as_ptr() as usize,
+                actual,
+                expected,
+            );
+            return Err(Error::InvalidKernelImage);
+        }
+
+        debug!(target: "elf", "Kernel text at {:#018x} verified", memory.as_ptr() as usize);
     }
 
     Ok(())
 }
 
-#[cfg(feature = "debug_mmap")]
-fn debug_mmap(uefi: &mut mercuros_uefi::Application) -> Result<(), Error> {
-    use mercuros_uefi::api::boot_services::memory;
+/// Position weighted sum of `bytes`, found at byte `offset` of a segment.
+/// Being linear, the contribution of a range can be replaced.
+#[cfg(debug_assertions)]
+fn text_checksum(bytes: &[u8], offset: usize) -> u64 {
+    bytes.iter()
+        .enumerate()
+        .fold(0u64, |sum, (index, &byte)| {
+            sum.wrapping_add((byte as u64).wrapping_mul((offset + index + 1) as u64))
+        })
+}
 
-    let memory_map = mercuros_uefi::Memory::get_memory_map(uefi)
-        .map_err(|err| core::convert::Into::<Error>::into(err))?;
+fn plan_elf_layout(
+    kernel_elf: &elf::ElfFile,
+    address_kind: elf::AddressKind,
+) -> Result<elf::Layout, Error> {
+    let layout = elf::Layout::plan(kernel_elf, address_kind)?;
+
+    for segment in layout.segments() {
+        debug!(
+            target: "elf",
+            "Segment: offset {:#018x}, vaddr {:#018x}, paddr {:#018x}, filesz {:#018x}, memsz {:#018x}",
+            segment.file_offset,
+            segment.virtual_address,
+            segment.physical_address,
+            segment.file_size,
+            segment.memory_size,
+        );
+    }
+    debug!(
+        target: "elf",
+        "{} base: {:#018x}, {} page(s)",
+        match address_kind {
+            elf::AddressKind::Virtual => "Virtual",
+            elf::AddressKind::Physical => "Physical",
+        },
+        layout.base,
+        layout.page_count,
+    );
+
+    Ok(layout)
+}
+
+/// Allocate memory for the ELF file. Relocatable images are placed so that
+/// their segments keep the alignment requested in the ELF file, e.g. to
+/// allow the kernel to map itself with huge pages. Static images are placed
+/// at their physical load address.
+fn allocate_elf_memory(layout: &elf::Layout) -> Result<&'static mut [u8], Error> {
+    let base = layout.base;
+    let page_count = layout.page_count;
+
+    match layout.address_kind {
+        elf::AddressKind::Virtual => {
+            debug!(
+                target: "elf",
+                "Allocating {} page(s), aligned to {:#x}",
+                page_count,
+                layout.alignment,
+            );
+
+            efi::allocate_pages_aligned(
+                efi::memory::LOADER_CODE,
+                page_count,
+                layout.alignment,
+                base & (layout.alignment - 1),
+            )
+                .map_err(|_| Error::MemoryAllocationFailed)
+        },
+        elf::AddressKind::Physical => {
+            debug!(
+                target: "elf",
+                "Allocating {} page(s) at {:#018X}",
+                page_count,
+                base,
+            );
+
+            efi::allocate_pages_at(efi::memory::LOADER_CODE, base as u64, page_count)
+                .map_err(|status| {
+                    let start = base as u64;
+                    let end = start + layout.size() as u64;
+                    error!(
+                        target: "elf",
+                        "Unable to allocate kernel at {:#018x}-{:#018x}: {:#x}",
+                        start,
+                        end,
+                        status,
+                    );
+                    report_unavailable_range(start, end);
+
+                    Error::KernelRangeUnavailable
+                })
+        },
+    }
+}
+
+/// Explain why a physical range could not be allocated, from the parts of
+/// the UEFI memory map that overlap it.
+pub fn report_unavailable_range(start: u64, end: u64) {
+    let memory_map = match efi::MemoryMap::allocate() {
+        Ok(memory_map) => memory_map,
+        Err(_) => return,
+    };
 
-    mercuros_uefi::Console::write_string(uefi, "\r\nMemory Map:\r\n");
-    for descriptor in &memory_map {
-        uefi.write_fmt(format_args!(
-            "\r\n{:#018X} - {:#018X}: {}\r\n",
+    let descriptor_range = |descriptor: &efi::MemoryDescriptor| {
+        let descriptor_end = descriptor.physical_start
+            + descriptor.number_of_pages * efi::memory::PAGE_SIZE as u64;
+        (descriptor.physical_start, descriptor_end)
+    };
+
+    for descriptor in memory_map.iter() {
+        let (descriptor_start, descriptor_end) = descriptor_range(descriptor);
+        if descriptor_end <= start || descriptor_start >= end {
+            continue;
+        }
+
+        if descriptor.r#type != efi::memory::CONVENTIONAL_MEMORY {
+            error!(
+                target: "elf",
+                "{:#018x}-{:#018x} is {}",
+                descriptor_start.max(start),
+                descriptor_end.min(end),
+                efi::memory::type_name(descriptor.r#type),
+            );
+        }
+    }
+
+    // the descriptors are not necessarily sorted
+    let mut covered = start;
+    while covered < end {
+        let next = memory_map.iter()
+            .map(descriptor_range)
+            .filter(|&(descriptor_start, descriptor_end)| {
+                descriptor_start <= covered && descriptor_end > covered
+            })
+            .map(|(_, descriptor_end)| descriptor_end)
+            .max();
+
+        match next {
+            Some(next) => covered = next,
+            None => break,
+        }
+    }
+
+    // parts of the range not covered by any descriptor are not memory at all
+    if covered < end {
+        error!(target: "elf", "{:#018x} and beyond is not in the memory map", covered);
+    }
+
+    memory_map.free();
+}
+
+fn calculate_base_address(
+    link_base: usize,
+    run_base: u64,
+) -> i64 {
+    let base_address = run_base as i64 - link_base as i64;
+
+    debug!(target: "elf", "ELF base address: {:#018X}", base_address);
+
+    base_address
+}
+
+fn log_memory_map() -> Result<(), Error> {
+    use efi::memory;
+
+    let memory_map = efi::MemoryMap::allocate()
+        .map_err(|status| match status {
+            efi::status::OUT_OF_RESOURCES => Error::MemoryAllocationFailed,
+            _ => Error::MemoryMapUnavailable,
+        })?;
+
+    debug!(target: "mmap", "Memory Map:");
+    for descriptor in memory_map.iter() {
+        debug!(
+            target: "mmap",
+            "{:#018X} - {:#018X}: {}",
             descriptor.physical_start,
-            descriptor.physical_start + descriptor.number_of_pages * 4096,
-            match descriptor.r#type {
-                memory::EFI_RESERVED_MEMORY_TYPE => "EfiReservedMemoryType",
-                memory::EFI_LOADER_DATA => "EfiLoaderData",
-                memory::EFI_BOOT_SERVICES_CODE => "EfiBootServicesCode",
-                memory::EFI_BOOT_SERVICES_DATA => "EfiBootServicesData",
-                memory::EFI_CONVENTIONAL_MEMORY => "EfiConventionalMemory",
-                memory::EFI_UNUSABLE_MEMORY => "EfiUnusableMemory",
-                memory::EFI_MEMORY_MAPPED_IO => "EfiMemoryMappedIO",
-                _ => "",
-            },
-        ));
-        uefi.write_fmt(format_args!("type: {:#010x}\r\n", descriptor.r#type));
-        uefi.write_fmt(format_args!("virtual_start: {:#018x}\r\n", descriptor.virtual_start));
-        uefi.write_fmt(format_args!("attribute: {:#018x}\r\n", descriptor.attribute));
+            descriptor.physical_start + descriptor.number_of_pages * memory::PAGE_SIZE as u64,
+            memory::type_name(descriptor.r#type),
+        );
+        trace!(target: "mmap", "type: {:#010x}", descriptor.r#type);
+        trace!(target: "mmap", "virtual_start: {:#018x}", descriptor.virtual_start);
+        trace!(target: "mmap", "attribute: {:#018x}", descriptor.attribute);
     }
+    memory_map.free();
 
     Ok(())
 }
@@ -0,0 +1,80 @@
+//! Boot information handed over to the kernel.
+//!
+//! The structure is versioned: new fields are only ever appended, and
+//! `size` tells the kernel how much of the structure Maia filled in.
+
+use super::{efi, framebuffer::Framebuffer};
+
+pub const MAGIC: u64 = u64::from_le_bytes(*b"MAIABOOT");
+
+/// Version history:
+///
+///  1. `log_buffer`
+///  2. `framebuffer`
+///  3. `acpi_rsdp`, `smbios`, `smbios3`
+///  4. `memory_map`, `efi_memory_map`
+///  5. `kernel_stack`
+///  6. `entry_abi`, `boot_hart_id`
+pub const VERSION: u32 = 6;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct MemoryRange {
+    pub address: u64,
+    pub size: u64,
+}
+
+#[repr(C)]
+pub struct BootInfo {
+    pub magic: u64,
+    pub version: u32,
+    pub size: u32,
+    /// Boot log ring buffer, see `log_buffer::Header`.
+    /// Zero size if no log buffer is available.
+    pub log_buffer: MemoryRange,
+    /// Linear framebuffer set up by firmware. Zero address if none is available.
+    pub framebuffer: Framebuffer,
+    /// ACPI RSDP, or zero if the platform does not provide ACPI.
+    pub acpi_rsdp: u64,
+    /// SMBIOS 2.x entry point, or zero if not available.
+    pub smbios: u64,
+    /// SMBIOS 3.x entry point, or zero if not available.
+    pub smbios3: u64,
+    /// Physical memory map, an array of `memory_map::MemoryRegion`
+    /// sorted by base address.
+    pub memory_map: MemoryRange,
+    /// Final UEFI memory map, as passed to `ExitBootServices`.
+    pub efi_memory_map: EfiMemoryMap,
+    /// Stack the kernel is entered with; `sp` points to its end.
+    pub kernel_stack: MemoryRange,
+    /// Version of the register state contract at entry, see `entry`.
+    pub entry_abi: u32,
+    _reserved: u32,
+    /// Id of the hart the kernel is entered on, or all ones if unknown.
+    pub boot_hart_id: u64,
+}
+
+/// Raw UEFI memory descriptors, `descriptor_size` bytes each.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct EfiMemoryMap {
+    pub address: u64,
+    pub size: u64,
+    pub descriptor_size: u32,
+    pub descriptor_version: u32,
+}
+
+impl BootInfo {
+    /// Allocate zero initialized boot information in `EfiLoaderData` memory.
+    pub fn allocate() -> Result<&'static mut BootInfo, efi::Status> {
+        let page_count = efi::memory::page_count(core::mem::size_of::<BootInfo>());
+        let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, page_count)?;
+
+        let boot_info = unsafe { &mut *(buffer.as_mut_ptr() as *mut BootInfo) };
+        boot_info.magic = MAGIC;
+        boot_info.version = VERSION;
+        boot_info.size = core::mem::size_of::<BootInfo>() as u32;
+
+        Ok(boot_info)
+    }
+}
@@ -0,0 +1,75 @@
+//! Chainloading of other EFI applications, e.g. the UEFI shell or a
+//! vendor boot loader on the same volume.
+
+use super::efi;
+
+/// Size limit for EFI applications loaded from the boot volume.
+const MAX_APPLICATION_SIZE: usize = 64 * 1024 * 1024;
+
+/// Maximum length of the load options passed on, in characters.
+const MAX_LOAD_OPTIONS_LENGTH: usize = 1024;
+
+/// Run the EFI application at `path` on the boot volume, passing
+/// `load_options` to it, and return its exit status once it exits.
+pub fn chainload(path: &str, load_options: Option<&str>) -> Result<efi::Status, efi::Status> {
+    let device = efi::loaded_image()?.device_handle;
+    let device_path = efi::DevicePath::file(device, path)?;
+
+    let data = efi::File::open_volume(device)?
+        .open(path, efi::file::FILE_MODE_READ)?
+        .read_to_pages(efi::memory::LOADER_DATA, MAX_APPLICATION_SIZE)?;
+    let result = start(Some(&device_path), data, load_options);
+    efi::free_pages(data);
+
+    result
+}
+
+/// Start the EFI image in `data` through the firmware, identified by
+/// `device_path` if it comes from a file, and return its exit status once
+/// it exits.
+pub fn start(
+    device_path: Option<&efi::DevicePath>,
+    data: &[u8],
+    load_options: Option<&str>,
+) -> Result<efi::Status, efi::Status> {
+    let handle = efi::load_image(device_path, data)?;
+
+    // the options must stay in place until the image exits
+    let mut options = [0u16; MAX_LOAD_OPTIONS_LENGTH + 1];
+    if let Some(load_options) = load_options {
+        let result = encode_load_options(load_options, &mut options)
+            .and_then(|length| {
+                let loaded_image = efi::handle_protocol::<efi::LoadedImage>(
+                    handle,
+                    &efi::loaded_image::LOADED_IMAGE_PROTOCOL_GUID,
+                )?;
+                loaded_image.load_options = options.as_ptr() as *const core::ffi::c_void;
+                loaded_image.load_options_size = (2 * (length + 1)) as u32;
+                Ok(())
+            });
+
+        if let Err(status) = result {
+            efi::unload_image(handle);
+            return Err(status);
+        }
+    }
+
+    Ok(efi::start_image(handle))
+}
+
+/// Convert load options to a NUL terminated UCS-2 string, returning its
+/// length without the terminator.
+fn encode_load_options(load_options: &str, buffer: &mut [u16]) -> Result<usize, efi::Status> {
+    let mut length = 0;
+    for c in load_options.chars() {
+        if length + 1 >= buffer.len() || (c as u32) >= 0x10000 {
+            return Err(efi::status::INVALID_PARAMETER);
+        }
+
+        buffer[length] = c as u16;
+        length += 1;
+    }
+    buffer[length] = 0;
+
+    Ok(length)
+}
@@ -0,0 +1,359 @@
+//! gzip (RFC 1952) container around DEFLATE (RFC 1951) data.
+//!
+//! Only the first member of the file is decompressed. Its CRC-32 and size
+//! are checked against the trailer.
+
+use super::{copy_literals, copy_match, read_u16_le, read_u32_le, DecompressionError};
+
+const HEADER_SIZE: usize = 10;
+const TRAILER_SIZE: usize = 8;
+const METHOD_DEFLATE: u8 = 8;
+
+const FLAG_HCRC: u8 = 1 << 1;
+const FLAG_EXTRA: u8 = 1 << 2;
+const FLAG_NAME: u8 = 1 << 3;
+const FLAG_COMMENT: u8 = 1 << 4;
+
+const MAX_CODE_LENGTH: usize = 15;
+const LITERAL_LENGTH_CODES: usize = 288;
+const DISTANCE_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// Order in which code length code lengths are stored in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Size recorded in the trailer, the decompressed size modulo 2^32.
+pub fn decompressed_size(data: &[u8]) -> Option<usize> {
+    if data.len() < HEADER_SIZE + TRAILER_SIZE {
+        return None;
+    }
+    read_u32_le(data, data.len() - 4).ok().map(|size| size as usize)
+}
+
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize, DecompressionError> {
+    if input.len() < HEADER_SIZE || input[0..2] != [0x1f, 0x8b] {
+        return Err(DecompressionError::InvalidFormat);
+    }
+    if input[2] != METHOD_DEFLATE {
+        return Err(DecompressionError::Unsupported);
+    }
+
+    let flags = input[3];
+    let mut offset = HEADER_SIZE;
+    if flags & FLAG_EXTRA != 0 {
+        offset += 2 + read_u16_le(input, offset)? as usize;
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT].iter() {
+        if flags & flag != 0 {
+            let terminator = input.get(offset..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .ok_or(DecompressionError::InvalidFormat)?;
+            offset += terminator + 1;
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        offset += 2;
+    }
+    let deflate_data = input.get(offset..).ok_or(DecompressionError::InvalidFormat)?;
+
+    let (size, consumed) = inflate(deflate_data, output)?;
+
+    let trailer = offset + consumed;
+    let crc = read_u32_le(input, trailer)?;
+    let recorded_size = read_u32_le(input, trailer + 4)?;
+    if recorded_size != size as u32 {
+        return Err(DecompressionError::InvalidFormat);
+    }
+    if crc != crc32(&output[..size]) {
+        return Err(DecompressionError::ChecksumMismatch);
+    }
+
+    Ok(size)
+}
+
+/// Decompress a DEFLATE stream, returning the size of the output and the
+/// number of input bytes consumed.
+fn inflate(input: &[u8], output: &mut [u8]) -> Result<(usize, usize), DecompressionError> {
+    let mut bits = BitReader::new(input);
+    let mut position = 0;
+
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => position = inflate_stored(&mut bits, output, position)?,
+            1 => {
+                let (literal_lengths, distances) = fixed_codes()?;
+                position = inflate_block(&mut bits, output, position, &literal_lengths, &distances)?;
+            },
+            2 => {
+                let (literal_lengths, distances) = dynamic_codes(&mut bits)?;
+                position = inflate_block(&mut bits, output, position, &literal_lengths, &distances)?;
+            },
+            _ => return Err(DecompressionError::InvalidFormat),
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    Ok((position, bits.align_to_byte()))
+}
+
+fn inflate_stored(
+    bits: &mut BitReader,
+    output: &mut [u8],
+    position: usize,
+) -> Result<usize, DecompressionError> {
+    let offset = bits.align_to_byte();
+    let length = read_u16_le(bits.data, offset)?;
+    let complement = read_u16_le(bits.data, offset + 2)?;
+    if length != !complement {
+        return Err(DecompressionError::InvalidFormat);
+    }
+
+    let start = offset + 4;
+    let data = bits.data.get(start..(start + length as usize))
+        .ok_or(DecompressionError::InvalidFormat)?;
+    copy_literals(output, position, data)?;
+    bits.skip_to(start + data.len());
+
+    Ok(position + data.len())
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    output: &mut [u8],
+    mut position: usize,
+    literal_lengths: &Huffman,
+    distances: &Huffman,
+) -> Result<usize, DecompressionError> {
+    loop {
+        let symbol = literal_lengths.decode(bits)? as usize;
+        if symbol < 256 {
+            if position == output.len() {
+                return Err(DecompressionError::OutputOverflow);
+            }
+            output[position] = symbol as u8;
+            position += 1;
+        } else if symbol == 256 {
+            return Ok(position);
+        } else {
+            let code = symbol - 257;
+            if code >= LENGTH_BASE.len() {
+                return Err(DecompressionError::InvalidFormat);
+            }
+            let length = LENGTH_BASE[code] as usize + bits.read(LENGTH_EXTRA[code] as u32)? as usize;
+
+            let code = distances.decode(bits)? as usize;
+            if code >= DISTANCE_BASE.len() {
+                return Err(DecompressionError::InvalidFormat);
+            }
+            let distance = DISTANCE_BASE[code] as usize
+                + bits.read(DISTANCE_EXTRA[code] as u32)? as usize;
+
+            copy_match(output, position, distance, length)?;
+            position += length;
+        }
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), DecompressionError> {
+    let mut lengths = [0u8; LITERAL_LENGTH_CODES];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5u8; DISTANCE_CODES])?))
+}
+
+fn dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), DecompressionError> {
+    let literal_length_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+    if literal_length_count > 286 || distance_count > DISTANCE_CODES {
+        return Err(DecompressionError::InvalidFormat);
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        code_length_lengths[index] = bits.read(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths)?;
+
+    // literal/length and distance code lengths form one sequence, repeats
+    // may cross from one into the other
+    let mut lengths = [0u8; LITERAL_LENGTH_CODES + DISTANCE_CODES];
+    let count = literal_length_count + distance_count;
+    let mut index = 0;
+    while index < count {
+        let symbol = code_lengths.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(DecompressionError::InvalidFormat);
+                }
+                (lengths[index - 1], 3 + bits.read(2)? as usize)
+            },
+            17 => (0, 3 + bits.read(3)? as usize),
+            _ => (0, 11 + bits.read(7)? as usize),
+        };
+
+        if index + repeat > count {
+            return Err(DecompressionError::InvalidFormat);
+        }
+        lengths[index..(index + repeat)].fill(value);
+        index += repeat;
+    }
+
+    // the end of block code must be present
+    if lengths[256] == 0 {
+        return Err(DecompressionError::InvalidFormat);
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_length_count])?,
+        Huffman::new(&lengths[literal_length_count..count])?,
+    ))
+}
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// Symbols ordered by code.
+    symbols: [u16; LITERAL_LENGTH_CODES],
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, DecompressionError> {
+        let mut huffman = Huffman {
+            counts: [0; MAX_CODE_LENGTH + 1],
+            symbols: [0; LITERAL_LENGTH_CODES],
+        };
+
+        for &length in lengths {
+            huffman.counts[length as usize] += 1;
+        }
+        huffman.counts[0] = 0;
+
+        // reject over-subscribed codes, incomplete ones are allowed
+        let mut left: i32 = 1;
+        for length in 1..=MAX_CODE_LENGTH {
+            left = (left << 1) - huffman.counts[length] as i32;
+            if left < 0 {
+                return Err(DecompressionError::InvalidFormat);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 1];
+        for length in 1..MAX_CODE_LENGTH {
+            offsets[length + 1] = offsets[length] + huffman.counts[length];
+        }
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                huffman.symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Ok(huffman)
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, DecompressionError> {
+        // codes are stored most significant bit first
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=MAX_CODE_LENGTH {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(DecompressionError::InvalidFormat)
+    }
+}
+
+/// Reads bits least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Offset of the next byte to load into `buffer`.
+    offset: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, offset: 0, buffer: 0, count: 0 }
+    }
+
+    fn read(&mut self, count: u32) -> Result<u32, DecompressionError> {
+        while self.count < count {
+            let byte = *self.data.get(self.offset).ok_or(DecompressionError::InvalidFormat)?;
+            self.buffer |= (byte as u64) << self.count;
+            self.offset += 1;
+            self.count += 8;
+        }
+
+        let value = (self.buffer & ((1u64 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+
+        Ok(value)
+    }
+
+    /// Drop the bits left of the current byte, returning the offset of the
+    /// next byte.
+    fn align_to_byte(&mut self) -> usize {
+        self.skip_to(self.offset - (self.count / 8) as usize);
+        self.offset
+    }
+
+    fn skip_to(&mut self, offset: usize) {
+        self.offset = offset;
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (index, entry) in table.iter_mut().enumerate() {
+        let mut value = index as u32;
+        for _ in 0..8 {
+            value = if value & 1 != 0 { 0xedb88320 ^ (value >> 1) } else { value >> 1 };
+        }
+        *entry = value;
+    }
+
+    !data.iter().fold(!0u32, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,186 @@
+//! LZ4 frame format, and the legacy format produced by `lz4 -l` that Linux
+//! uses for compressed kernels.
+//!
+//! Block and content checksums are skipped, not verified.
+
+use super::{copy_literals, copy_match, read_u16_le, read_u32_le, read_u64_le, DecompressionError};
+
+const FRAME_MAGIC: u32 = 0x184d2204;
+const LEGACY_MAGIC: u32 = 0x184c2102;
+/// Skippable frames use magics 0x184d2a50 to 0x184d2a5f.
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xfffffff0;
+
+const VERSION_MASK: u8 = 0xc0;
+const VERSION: u8 = 0x40;
+const FLAG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLAG_CONTENT_SIZE: u8 = 1 << 3;
+const FLAG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLAG_DICTIONARY_ID: u8 = 1 << 0;
+
+/// Block size marking an uncompressed block.
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+
+/// Size of the content, if given in the header of the first frame.
+pub fn decompressed_size(data: &[u8]) -> Option<usize> {
+    if read_u32_le(data, 0).ok()? != FRAME_MAGIC {
+        return None;
+    }
+
+    let flags = *data.get(4)?;
+    if flags & FLAG_CONTENT_SIZE == 0 {
+        return None;
+    }
+    read_u64_le(data, 6).ok().map(|size| size as usize)
+}
+
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize, DecompressionError> {
+    let mut offset = 0;
+    let mut position = 0;
+
+    while offset < input.len() {
+        let magic = read_u32_le(input, offset)?;
+        offset += 4;
+
+        if magic == FRAME_MAGIC {
+            let (consumed, size) = decompress_frame(&input[offset..], &mut output[position..])?;
+            offset += consumed;
+            position += size;
+        } else if magic == LEGACY_MAGIC {
+            let (consumed, size) = decompress_legacy(&input[offset..], &mut output[position..])?;
+            offset += consumed;
+            position += size;
+        } else if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            offset += 4 + read_u32_le(input, offset)? as usize;
+        } else if position > 0 {
+            // trailing data after the compressed stream
+            break;
+        } else {
+            return Err(DecompressionError::InvalidFormat);
+        }
+    }
+
+    Ok(position)
+}
+
+/// Decompress one frame following its magic, returning the number of input
+/// bytes consumed and the size of the output.
+fn decompress_frame(input: &[u8], output: &mut [u8]) -> Result<(usize, usize), DecompressionError> {
+    let flags = *input.first().ok_or(DecompressionError::InvalidFormat)?;
+    if flags & VERSION_MASK != VERSION {
+        return Err(DecompressionError::Unsupported);
+    }
+    if flags & FLAG_DICTIONARY_ID != 0 {
+        return Err(DecompressionError::Unsupported);
+    }
+
+    // flags, block descriptor and header checksum
+    let mut offset = 3;
+    if flags & FLAG_CONTENT_SIZE != 0 {
+        offset += 8;
+    }
+
+    let mut position = 0;
+    loop {
+        let block_size = read_u32_le(input, offset)?;
+        offset += 4;
+        if block_size == 0 {
+            break;
+        }
+
+        let size = (block_size & !BLOCK_UNCOMPRESSED) as usize;
+        let block = input.get(offset..(offset + size)).ok_or(DecompressionError::InvalidFormat)?;
+        if block_size & BLOCK_UNCOMPRESSED != 0 {
+            copy_literals(output, position, block)?;
+            position += size;
+        } else {
+            position = decompress_block(block, output, position)?;
+        }
+
+        offset += size;
+        if flags & FLAG_BLOCK_CHECKSUM != 0 {
+            offset += 4;
+        }
+    }
+
+    if flags & FLAG_CONTENT_CHECKSUM != 0 {
+        offset += 4;
+    }
+    if offset > input.len() {
+        return Err(DecompressionError::InvalidFormat);
+    }
+
+    Ok((offset, position))
+}
+
+/// Decompress the blocks of a legacy stream following its magic. The stream
+/// has no end mark, it ends with the input or at the next magic.
+fn decompress_legacy(input: &[u8], output: &mut [u8]) -> Result<(usize, usize), DecompressionError> {
+    let mut offset = 0;
+    let mut position = 0;
+
+    while offset < input.len() {
+        let block_size = read_u32_le(input, offset)?;
+        if block_size == LEGACY_MAGIC || block_size == FRAME_MAGIC {
+            break;
+        }
+        offset += 4;
+
+        let block = input.get(offset..(offset + block_size as usize))
+            .ok_or(DecompressionError::InvalidFormat)?;
+        position = decompress_block(block, output, position)?;
+        offset += block.len();
+    }
+
+    Ok((offset, position))
+}
+
+/// Decompress a block into `output` at `position`, returning the position
+/// after the decompressed data. Matches may refer to earlier blocks.
+fn decompress_block(
+    block: &[u8],
+    output: &mut [u8],
+    mut position: usize,
+) -> Result<usize, DecompressionError> {
+    let mut offset = 0;
+
+    loop {
+        let token = *block.get(offset).ok_or(DecompressionError::InvalidFormat)?;
+        offset += 1;
+
+        let literal_length = read_length(block, &mut offset, (token >> 4) as usize)?;
+        let literals = block.get(offset..(offset + literal_length))
+            .ok_or(DecompressionError::InvalidFormat)?;
+        copy_literals(output, position, literals)?;
+        position += literal_length;
+        offset += literal_length;
+
+        // the last sequence has literals only
+        if offset == block.len() {
+            return Ok(position);
+        }
+
+        let distance = read_u16_le(block, offset)? as usize;
+        offset += 2;
+        let match_length = read_length(block, &mut offset, (token & 0xf) as usize)? + 4;
+        copy_match(output, position, distance, match_length)?;
+        position += match_length;
+    }
+}
+
+/// Read a length starting with the four bits from the token, extended by
+/// following bytes while they are all ones.
+fn read_length(block: &[u8], offset: &mut usize, mut length: usize) -> Result<usize, DecompressionError> {
+    if length == 0xf {
+        loop {
+            let byte = *block.get(*offset).ok_or(DecompressionError::InvalidFormat)?;
+            *offset += 1;
+            length += byte as usize;
+            if byte != 0xff {
+                break;
+            }
+        }
+    }
+
+    Ok(length)
+}
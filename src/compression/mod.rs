@@ -0,0 +1,153 @@
+//! Decompression of gzip, zstd and LZ4 compressed kernel images.
+//!
+//! Everything is decompressed in one go into a caller provided buffer,
+//! which must be large enough for the whole output. Back-references are
+//! resolved within that buffer, so no separate window is kept.
+
+mod gzip;
+mod lz4;
+mod zstd;
+
+#[derive(Debug)]
+pub enum DecompressionError {
+    InvalidFormat,
+    Unsupported,
+    /// The output buffer is too small for the decompressed data.
+    OutputOverflow,
+    ChecksumMismatch,
+}
+
+impl core::fmt::Display for DecompressionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            DecompressionError::InvalidFormat => write!(f, "invalid compressed data"),
+            DecompressionError::Unsupported => write!(f, "unsupported compression feature"),
+            DecompressionError::OutputOverflow => write!(f, "decompressed data too large"),
+            DecompressionError::ChecksumMismatch => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_MAGIC: &[u8] = &[0x04, 0x22, 0x4d, 0x18];
+/// Magic of the legacy LZ4 format, as used for Linux kernel images.
+const LZ4_LEGACY_MAGIC: &[u8] = &[0x02, 0x21, 0x4c, 0x18];
+
+/// Scratch memory needed by `decompress`, in bytes.
+pub const WORKSPACE_SIZE: usize = zstd::WORKSPACE_SIZE;
+
+impl Format {
+    /// Recognize the compression format by the magic bytes at the start
+    /// of `data`.
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        if data.starts_with(GZIP_MAGIC) {
+            Some(Format::Gzip)
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Some(Format::Zstd)
+        } else if data.starts_with(LZ4_MAGIC) || data.starts_with(LZ4_LEGACY_MAGIC) {
+            Some(Format::Lz4)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zstd => "zstd",
+            Format::Lz4 => "LZ4",
+        }
+    }
+
+    /// Size of the decompressed data, if `data` records it.
+    pub fn decompressed_size(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Format::Gzip => gzip::decompressed_size(data),
+            Format::Zstd => zstd::decompressed_size(data),
+            Format::Lz4 => lz4::decompressed_size(data),
+        }
+    }
+
+    /// Decompress `input` into `output`, using `workspace` of at least
+    /// `WORKSPACE_SIZE` bytes as scratch memory. Returns the size of the
+    /// decompressed data.
+    pub fn decompress(
+        &self,
+        input: &[u8],
+        output: &mut [u8],
+        workspace: &mut [u8],
+    ) -> Result<usize, DecompressionError> {
+        match self {
+            Format::Gzip => gzip::decompress(input, output),
+            Format::Zstd => zstd::decompress(input, output, workspace),
+            Format::Lz4 => lz4::decompress(input, output),
+        }
+    }
+}
+
+/// Append `length` bytes to `output` at `position`, copied from `distance`
+/// bytes back. The source may overlap the copy, repeating the bytes.
+fn copy_match(
+    output: &mut [u8],
+    position: usize,
+    distance: usize,
+    length: usize,
+) -> Result<(), DecompressionError> {
+    if distance == 0 || distance > position {
+        return Err(DecompressionError::InvalidFormat);
+    }
+    if length > output.len() - position {
+        return Err(DecompressionError::OutputOverflow);
+    }
+
+    let source = position - distance;
+    if distance >= length {
+        output.copy_within(source..(source + length), position);
+    } else {
+        for i in 0..length {
+            output[position + i] = output[source + i];
+        }
+    }
+
+    Ok(())
+}
+
+/// Append `data` to `output` at `position`.
+fn copy_literals(
+    output: &mut [u8],
+    position: usize,
+    data: &[u8],
+) -> Result<(), DecompressionError> {
+    if data.len() > output.len() - position {
+        return Err(DecompressionError::OutputOverflow);
+    }
+    output[position..(position + data.len())].copy_from_slice(data);
+
+    Ok(())
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> Result<u16, DecompressionError> {
+    data.get(offset..(offset + 2))
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(DecompressionError::InvalidFormat)
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, DecompressionError> {
+    data.get(offset..(offset + 4))
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(DecompressionError::InvalidFormat)
+}
+
+fn read_u64_le(data: &[u8], offset: usize) -> Result<u64, DecompressionError> {
+    let low = read_u32_le(data, offset)? as u64;
+    let high = read_u32_le(data, offset + 4)? as u64;
+    Ok(low | (high << 32))
+}
@@ -0,0 +1,925 @@
+//! Zstandard (RFC 8878) frames.
+//!
+//! Dictionaries are not supported, and the optional content checksum is
+//! skipped, not verified.
+
+use super::{copy_literals, copy_match, read_u16_le, read_u32_le, read_u64_le, DecompressionError};
+
+const MAGIC: u32 = 0xfd2fb528;
+/// Skippable frames use magics 0x184d2a50 to 0x184d2a5f.
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xfffffff0;
+
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Literals of a block are decoded into the workspace.
+pub const WORKSPACE_SIZE: usize = MAX_BLOCK_SIZE;
+
+const BLOCK_RAW: u32 = 0;
+const BLOCK_RLE: u32 = 1;
+const BLOCK_COMPRESSED: u32 = 2;
+
+const LITERALS_RAW: u8 = 0;
+const LITERALS_RLE: u8 = 1;
+const LITERALS_COMPRESSED: u8 = 2;
+
+const MODE_PREDEFINED: u8 = 0;
+const MODE_RLE: u8 = 1;
+const MODE_COMPRESSED: u8 = 2;
+
+const MAX_HUFFMAN_BITS: u32 = 11;
+const MAX_HUFFMAN_WEIGHTS_LOG: u32 = 6;
+const MAX_FSE_LOG: u32 = 9;
+const MAX_FSE_SYMBOLS: usize = 64;
+
+const MAX_LITERAL_LENGTH_CODE: usize = 35;
+const MAX_MATCH_LENGTH_CODE: usize = 52;
+const MAX_OFFSET_CODE: usize = 31;
+
+const LITERAL_LENGTH_LOG: u32 = 9;
+const MATCH_LENGTH_LOG: u32 = 9;
+const OFFSET_LOG: u32 = 8;
+
+const LITERAL_LENGTH_DEFAULT_LOG: u32 = 6;
+const LITERAL_LENGTH_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const MATCH_LENGTH_DEFAULT_LOG: u32 = 6;
+const MATCH_LENGTH_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1,
+    -1, -1, -1, -1, -1,
+];
+const OFFSET_DEFAULT_LOG: u32 = 5;
+const OFFSET_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+const LITERAL_LENGTH_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512, 1024, 2048, 4096,
+    8192, 16384, 32768, 65536,
+];
+const LITERAL_LENGTH_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15, 16,
+];
+const MATCH_LENGTH_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
+    19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+    35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051,
+    4099, 8195, 16387, 32771, 65539,
+];
+const MATCH_LENGTH_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+
+/// Content size from the header of the first frame, if recorded.
+pub fn decompressed_size(data: &[u8]) -> Option<usize> {
+    if read_u32_le(data, 0).ok()? != MAGIC {
+        return None;
+    }
+    FrameHeader::parse(&data[4..]).ok()?.content_size
+}
+
+pub fn decompress(
+    input: &[u8],
+    output: &mut [u8],
+    workspace: &mut [u8],
+) -> Result<usize, DecompressionError> {
+    if workspace.len() < WORKSPACE_SIZE {
+        return Err(DecompressionError::OutputOverflow);
+    }
+
+    let mut offset = 0;
+    let mut position = 0;
+    while offset < input.len() {
+        let magic = read_u32_le(input, offset)?;
+        offset += 4;
+
+        if magic == MAGIC {
+            let mut frame = Frame::new(&mut workspace[..WORKSPACE_SIZE]);
+            let (consumed, size) = frame.decompress(&input[offset..], &mut output[position..])?;
+            offset += consumed;
+            position += size;
+        } else if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            offset += 4 + read_u32_le(input, offset)? as usize;
+        } else if position > 0 {
+            // trailing data after the compressed stream
+            break;
+        } else {
+            return Err(DecompressionError::InvalidFormat);
+        }
+    }
+
+    Ok(position)
+}
+
+struct FrameHeader {
+    size: usize,
+    content_size: Option<usize>,
+    checksum: bool,
+}
+
+impl FrameHeader {
+    /// Parse the frame header following the magic.
+    fn parse(data: &[u8]) -> Result<FrameHeader, DecompressionError> {
+        let descriptor = *data.first().ok_or(DecompressionError::InvalidFormat)?;
+        let content_size_flag = descriptor >> 6;
+        let single_segment = descriptor & 0x20 != 0;
+        if descriptor & 0x08 != 0 {
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        let mut offset = 1;
+        if !single_segment {
+            // window descriptor, the whole output is the window here
+            offset += 1;
+        }
+
+        let dictionary_id = match descriptor & 0x3 {
+            0 => 0,
+            1 => *data.get(offset).ok_or(DecompressionError::InvalidFormat)? as u32,
+            2 => read_u16_le(data, offset)? as u32,
+            _ => read_u32_le(data, offset)?,
+        };
+        if dictionary_id != 0 {
+            return Err(DecompressionError::Unsupported);
+        }
+        offset += [0, 1, 2, 4][(descriptor & 0x3) as usize];
+
+        let (content_size, content_size_size) = match content_size_flag {
+            0 if single_segment => (
+                Some(*data.get(offset).ok_or(DecompressionError::InvalidFormat)? as u64),
+                1,
+            ),
+            0 => (None, 0),
+            1 => (Some(read_u16_le(data, offset)? as u64 + 256), 2),
+            2 => (Some(read_u32_le(data, offset)? as u64), 4),
+            _ => (Some(read_u64_le(data, offset)?), 8),
+        };
+
+        Ok(FrameHeader {
+            size: offset + content_size_size,
+            content_size: content_size.map(|size| size as usize),
+            checksum: descriptor & 0x04 != 0,
+        })
+    }
+}
+
+/// Decoding state carried from block to block within a frame.
+struct Frame<'a> {
+    literals: &'a mut [u8],
+    huffman: HuffmanTable,
+    literal_lengths: FseTable,
+    offsets: FseTable,
+    match_lengths: FseTable,
+    repeat_offsets: [usize; 3],
+}
+
+impl<'a> Frame<'a> {
+    fn new(literals: &'a mut [u8]) -> Frame<'a> {
+        Frame {
+            literals,
+            huffman: HuffmanTable::new(),
+            literal_lengths: FseTable::new(),
+            offsets: FseTable::new(),
+            match_lengths: FseTable::new(),
+            repeat_offsets: [1, 4, 8],
+        }
+    }
+
+    /// Decompress the frame following the magic, returning the number of
+    /// input bytes consumed and the size of the output.
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(usize, usize), DecompressionError> {
+        let header = FrameHeader::parse(input)?;
+        let mut offset = header.size;
+        let mut position = 0;
+
+        loop {
+            let block_header = input.get(offset..(offset + 3))
+                .map(|bytes| bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16)
+                .ok_or(DecompressionError::InvalidFormat)?;
+            offset += 3;
+
+            let last = block_header & 1 != 0;
+            let size = (block_header >> 3) as usize;
+            position = match (block_header >> 1) & 0x3 {
+                BLOCK_RAW => {
+                    let block = input.get(offset..(offset + size))
+                        .ok_or(DecompressionError::InvalidFormat)?;
+                    copy_literals(output, position, block)?;
+                    offset += size;
+                    position + size
+                },
+                BLOCK_RLE => {
+                    let byte = *input.get(offset).ok_or(DecompressionError::InvalidFormat)?;
+                    if size > output.len() - position {
+                        return Err(DecompressionError::OutputOverflow);
+                    }
+                    output[position..(position + size)].fill(byte);
+                    offset += 1;
+                    position + size
+                },
+                BLOCK_COMPRESSED => {
+                    if size > MAX_BLOCK_SIZE {
+                        return Err(DecompressionError::InvalidFormat);
+                    }
+                    let block = input.get(offset..(offset + size))
+                        .ok_or(DecompressionError::InvalidFormat)?;
+                    offset += size;
+                    self.decompress_block(block, output, position)?
+                },
+                _ => return Err(DecompressionError::InvalidFormat),
+            };
+
+            if last {
+                break;
+            }
+        }
+
+        if header.checksum {
+            offset += 4;
+        }
+        if offset > input.len() {
+            return Err(DecompressionError::InvalidFormat);
+        }
+        if header.content_size.map_or(false, |size| size != position) {
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        Ok((offset, position))
+    }
+
+    /// Decompress a compressed block into `output` at `position`, returning
+    /// the position after the decompressed data.
+    fn decompress_block(
+        &mut self,
+        block: &[u8],
+        output: &mut [u8],
+        position: usize,
+    ) -> Result<usize, DecompressionError> {
+        let (consumed, literal_count) = self.decode_literals(block)?;
+        self.execute_sequences(&block[consumed..], literal_count, output, position)
+    }
+
+    /// Decode the literals section into the literals buffer, returning the
+    /// size of the section and the number of literals.
+    fn decode_literals(&mut self, block: &[u8]) -> Result<(usize, usize), DecompressionError> {
+        let byte = |offset: usize| -> Result<usize, DecompressionError> {
+            block.get(offset).map(|&byte| byte as usize).ok_or(DecompressionError::InvalidFormat)
+        };
+
+        let first = byte(0)?;
+        let literals_type = (first & 0x3) as u8;
+        let size_format = (first >> 2) & 0x3;
+
+        if literals_type == LITERALS_RAW || literals_type == LITERALS_RLE {
+            let (header_size, count) = match size_format {
+                0 | 2 => (1, first >> 3),
+                1 => (2, (first >> 4) + (byte(1)? << 4)),
+                _ => (3, (first >> 4) + (byte(1)? << 4) + (byte(2)? << 12)),
+            };
+            if count > MAX_BLOCK_SIZE {
+                return Err(DecompressionError::InvalidFormat);
+            }
+
+            if literals_type == LITERALS_RAW {
+                let data = block.get(header_size..(header_size + count))
+                    .ok_or(DecompressionError::InvalidFormat)?;
+                self.literals[..count].copy_from_slice(data);
+                return Ok((header_size + count, count));
+            }
+
+            self.literals[..count].fill(byte(header_size)? as u8);
+            return Ok((header_size + 1, count));
+        }
+
+        let (header_size, size_bits, stream_count) = match size_format {
+            0 => (3, 10, 1),
+            1 => (3, 10, 4),
+            2 => (4, 14, 4),
+            _ => (5, 18, 4),
+        };
+        let mut header = 0u64;
+        for index in 0..header_size {
+            header |= (byte(index)? as u64) << (8 * index);
+        }
+        let mask = (1u64 << size_bits) - 1;
+        let count = ((header >> 4) & mask) as usize;
+        let compressed_size = ((header >> (4 + size_bits)) & mask) as usize;
+        if count > MAX_BLOCK_SIZE {
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        let mut data = block.get(header_size..(header_size + compressed_size))
+            .ok_or(DecompressionError::InvalidFormat)?;
+        if literals_type == LITERALS_COMPRESSED {
+            let consumed = self.huffman.read(data)?;
+            data = &data[consumed..];
+        } else if self.huffman.max_bits == 0 {
+            // treeless literals reuse the previous table
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        let literals = &mut self.literals[..count];
+        if stream_count == 1 {
+            self.huffman.decode_stream(data, literals)?;
+        } else {
+            let jump_table = data.get(..6).ok_or(DecompressionError::InvalidFormat)?;
+            let mut sizes = [0usize; 4];
+            for index in 0..3 {
+                sizes[index] = read_u16_le(jump_table, 2 * index)? as usize;
+            }
+            sizes[3] = (data.len() - 6)
+                .checked_sub(sizes[0] + sizes[1] + sizes[2])
+                .ok_or(DecompressionError::InvalidFormat)?;
+
+            let segment = (count + 3) / 4;
+            if 3 * segment > count {
+                return Err(DecompressionError::InvalidFormat);
+            }
+
+            let mut stream_offset = 6;
+            for (index, &size) in sizes.iter().enumerate() {
+                let start = index * segment;
+                let end = if index == 3 { count } else { start + segment };
+                self.huffman.decode_stream(
+                    &data[stream_offset..(stream_offset + size)],
+                    &mut literals[start..end],
+                )?;
+                stream_offset += size;
+            }
+        }
+
+        Ok((header_size + compressed_size, count))
+    }
+
+    /// Decode the sequences section and execute the sequences, copying
+    /// literals and matches to `output` at `position`.
+    fn execute_sequences(
+        &mut self,
+        section: &[u8],
+        literal_count: usize,
+        output: &mut [u8],
+        mut position: usize,
+    ) -> Result<usize, DecompressionError> {
+        let byte = |offset: usize| -> Result<usize, DecompressionError> {
+            section.get(offset).map(|&byte| byte as usize).ok_or(DecompressionError::InvalidFormat)
+        };
+
+        let literals = &self.literals[..literal_count];
+        let first = byte(0)?;
+        let (sequence_count, mut offset) = match first {
+            0 => {
+                copy_literals(output, position, literals)?;
+                return Ok(position + literal_count);
+            },
+            1..=127 => (first, 1),
+            128..=254 => (((first - 128) << 8) + byte(1)?, 2),
+            _ => (byte(1)? + (byte(2)? << 8) + 0x7f00, 3),
+        };
+
+        let modes = byte(offset)? as u8;
+        offset += 1;
+        if modes & 0x3 != 0 {
+            return Err(DecompressionError::InvalidFormat);
+        }
+        offset += self.literal_lengths.select(
+            modes >> 6,
+            &section[offset..],
+            &LITERAL_LENGTH_DEFAULT,
+            LITERAL_LENGTH_DEFAULT_LOG,
+            LITERAL_LENGTH_LOG,
+            MAX_LITERAL_LENGTH_CODE,
+        )?;
+        offset += self.offsets.select(
+            (modes >> 4) & 0x3,
+            &section[offset..],
+            &OFFSET_DEFAULT,
+            OFFSET_DEFAULT_LOG,
+            OFFSET_LOG,
+            MAX_OFFSET_CODE,
+        )?;
+        offset += self.match_lengths.select(
+            (modes >> 2) & 0x3,
+            &section[offset..],
+            &MATCH_LENGTH_DEFAULT,
+            MATCH_LENGTH_DEFAULT_LOG,
+            MATCH_LENGTH_LOG,
+            MAX_MATCH_LENGTH_CODE,
+        )?;
+
+        let mut bits = ReverseBitReader::new(&section[offset..])?;
+        let mut literal_length_state = bits.read(self.literal_lengths.accuracy_log) as usize;
+        let mut offset_state = bits.read(self.offsets.accuracy_log) as usize;
+        let mut match_length_state = bits.read(self.match_lengths.accuracy_log) as usize;
+
+        let mut literal_offset = 0;
+        for index in 0..sequence_count {
+            let literal_length_entry = self.literal_lengths.entries[literal_length_state];
+            let offset_entry = self.offsets.entries[offset_state];
+            let match_length_entry = self.match_lengths.entries[match_length_state];
+
+            let offset_code = offset_entry.symbol as u32;
+            let offset_value = (1usize << offset_code) + bits.read(offset_code) as usize;
+            let code = match_length_entry.symbol as usize;
+            let match_length = MATCH_LENGTH_BASE[code] as usize
+                + bits.read(MATCH_LENGTH_BITS[code] as u32) as usize;
+            let code = literal_length_entry.symbol as usize;
+            let literal_length = LITERAL_LENGTH_BASE[code] as usize
+                + bits.read(LITERAL_LENGTH_BITS[code] as u32) as usize;
+
+            let distance = resolve_offset(&mut self.repeat_offsets, offset_value, literal_length)?;
+
+            let sequence_literals = literals.get(literal_offset..(literal_offset + literal_length))
+                .ok_or(DecompressionError::InvalidFormat)?;
+            copy_literals(output, position, sequence_literals)?;
+            literal_offset += literal_length;
+            position += literal_length;
+
+            copy_match(output, position, distance, match_length)?;
+            position += match_length;
+
+            if index + 1 < sequence_count {
+                literal_length_state = literal_length_entry.next_state(&mut bits);
+                match_length_state = match_length_entry.next_state(&mut bits);
+                offset_state = offset_entry.next_state(&mut bits);
+            }
+        }
+
+        if !bits.is_finished() {
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        copy_literals(output, position, &literals[literal_offset..])?;
+        Ok(position + (literal_count - literal_offset))
+    }
+}
+
+/// Turn an offset value into a match distance, maintaining the repeated
+/// offsets.
+fn resolve_offset(
+    repeat_offsets: &mut [usize; 3],
+    offset_value: usize,
+    literal_length: usize,
+) -> Result<usize, DecompressionError> {
+    if offset_value > 3 {
+        let distance = offset_value - 3;
+        *repeat_offsets = [distance, repeat_offsets[0], repeat_offsets[1]];
+        return Ok(distance);
+    }
+
+    // without literals, the repeated offsets are shifted by one
+    let index = if literal_length == 0 { offset_value + 1 } else { offset_value };
+    let distance = match index {
+        1 => return Ok(repeat_offsets[0]),
+        2 => repeat_offsets[1],
+        3 => repeat_offsets[2],
+        _ => repeat_offsets[0].wrapping_sub(1),
+    };
+    if distance == 0 {
+        return Err(DecompressionError::InvalidFormat);
+    }
+
+    if index == 2 {
+        repeat_offsets[1] = repeat_offsets[0];
+    } else {
+        repeat_offsets[2] = repeat_offsets[1];
+        repeat_offsets[1] = repeat_offsets[0];
+    }
+    repeat_offsets[0] = distance;
+
+    Ok(distance)
+}
+
+#[derive(Clone, Copy, Default)]
+struct HuffmanEntry {
+    symbol: u8,
+    bits: u8,
+}
+
+/// Huffman decoding table indexed by the next `max_bits` bits.
+struct HuffmanTable {
+    entries: [HuffmanEntry; 1 << MAX_HUFFMAN_BITS],
+    /// Zero while no table has been read.
+    max_bits: u32,
+}
+
+impl HuffmanTable {
+    fn new() -> HuffmanTable {
+        HuffmanTable {
+            entries: [HuffmanEntry::default(); 1 << MAX_HUFFMAN_BITS],
+            max_bits: 0,
+        }
+    }
+
+    /// Read a Huffman tree description, returning its size.
+    fn read(&mut self, data: &[u8]) -> Result<usize, DecompressionError> {
+        let header = *data.first().ok_or(DecompressionError::InvalidFormat)? as usize;
+        let mut weights = [0u8; 256];
+
+        let (mut count, size) = if header >= 128 {
+            // four bit weights
+            let count = header - 127;
+            let size = 1 + (count + 1) / 2;
+            let packed = data.get(1..size).ok_or(DecompressionError::InvalidFormat)?;
+            for index in 0..count {
+                let byte = packed[index / 2];
+                weights[index] = if index % 2 == 0 { byte >> 4 } else { byte & 0xf };
+            }
+            (count, size)
+        } else {
+            let size = 1 + header;
+            let stream = data.get(1..size).ok_or(DecompressionError::InvalidFormat)?;
+            (decode_weights(stream, &mut weights)?, size)
+        };
+
+        // the weight of the last symbol is implied, completing the code
+        let total: u32 = weights[..count].iter()
+            .filter(|&&weight| weight > 0)
+            .map(|&weight| 1u32.checked_shl(weight as u32 - 1).unwrap_or(u32::MAX))
+            .fold(0, |total, value| total.saturating_add(value));
+        if total == 0 || count >= 256 {
+            return Err(DecompressionError::InvalidFormat);
+        }
+        let max_bits = highest_bit(total) + 1;
+        if max_bits > MAX_HUFFMAN_BITS {
+            return Err(DecompressionError::InvalidFormat);
+        }
+        let rest = (1 << max_bits) - total;
+        if !rest.is_power_of_two() {
+            return Err(DecompressionError::InvalidFormat);
+        }
+        weights[count] = (highest_bit(rest) + 1) as u8;
+        count += 1;
+
+        // codes are assigned by increasing weight, then symbol
+        let mut position = 0;
+        for weight in 1..=max_bits {
+            let bits = (max_bits + 1 - weight) as u8;
+            let length = 1 << (weight - 1);
+            for (symbol, _) in weights[..count].iter().enumerate().filter(|&(_, &w)| w as u32 == weight) {
+                for entry in self.entries[position..(position + length)].iter_mut() {
+                    *entry = HuffmanEntry { symbol: symbol as u8, bits };
+                }
+                position += length;
+            }
+        }
+        self.max_bits = max_bits;
+
+        Ok(size)
+    }
+
+    /// Decode a stream holding exactly `output.len()` symbols.
+    fn decode_stream(&self, stream: &[u8], output: &mut [u8]) -> Result<(), DecompressionError> {
+        let mut bits = ReverseBitReader::new(stream)?;
+        for byte in output.iter_mut() {
+            let entry = self.entries[bits.peek(self.max_bits) as usize];
+            bits.consume(entry.bits as u32);
+            *byte = entry.symbol;
+        }
+
+        if !bits.is_finished() {
+            return Err(DecompressionError::InvalidFormat);
+        }
+        Ok(())
+    }
+}
+
+/// Decode FSE compressed Huffman weights, returning their number.
+fn decode_weights(stream: &[u8], weights: &mut [u8; 256]) -> Result<usize, DecompressionError> {
+    let mut probabilities = [0i16; MAX_FSE_SYMBOLS];
+    let (size, accuracy_log) = read_distribution(stream, MAX_HUFFMAN_WEIGHTS_LOG, 12, &mut probabilities)?;
+    let mut table = FseTable::new();
+    table.build(&probabilities, accuracy_log)?;
+
+    // two interleaved states, until the stream is exhausted
+    let mut bits = ReverseBitReader::new(&stream[size..])?;
+    let mut states = [
+        bits.read(accuracy_log) as usize,
+        bits.read(accuracy_log) as usize,
+    ];
+    let mut count = 0;
+    loop {
+        for current in 0..2 {
+            if count >= 255 {
+                return Err(DecompressionError::InvalidFormat);
+            }
+            let entry = table.entries[states[current]];
+            weights[count] = entry.symbol;
+            count += 1;
+            states[current] = entry.next_state(&mut bits);
+
+            if bits.is_overflowed() {
+                weights[count] = table.entries[states[1 - current]].symbol;
+                return Ok(count + 1);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,
+    baseline: u16,
+}
+
+impl FseEntry {
+    fn next_state(&self, bits: &mut ReverseBitReader) -> usize {
+        self.baseline as usize + bits.read(self.bits as u32) as usize
+    }
+}
+
+struct FseTable {
+    entries: [FseEntry; 1 << MAX_FSE_LOG],
+    accuracy_log: u32,
+    /// Zero while no table has been set up.
+    size: usize,
+}
+
+impl FseTable {
+    fn new() -> FseTable {
+        FseTable {
+            entries: [FseEntry::default(); 1 << MAX_FSE_LOG],
+            accuracy_log: 0,
+            size: 0,
+        }
+    }
+
+    /// Set up the table as given by a sequence compression mode, returning
+    /// the number of bytes of `data` used.
+    fn select(
+        &mut self,
+        mode: u8,
+        data: &[u8],
+        default: &[i16],
+        default_log: u32,
+        max_log: u32,
+        max_symbol: usize,
+    ) -> Result<usize, DecompressionError> {
+        match mode {
+            MODE_PREDEFINED => {
+                self.build(default, default_log)?;
+                Ok(0)
+            },
+            MODE_RLE => {
+                let symbol = *data.first().ok_or(DecompressionError::InvalidFormat)?;
+                if symbol as usize > max_symbol {
+                    return Err(DecompressionError::InvalidFormat);
+                }
+                self.entries[0] = FseEntry { symbol, bits: 0, baseline: 0 };
+                self.accuracy_log = 0;
+                self.size = 1;
+                Ok(1)
+            },
+            MODE_COMPRESSED => {
+                let mut probabilities = [0i16; MAX_FSE_SYMBOLS];
+                let (size, accuracy_log) =
+                    read_distribution(data, max_log, max_symbol, &mut probabilities)?;
+                self.build(&probabilities[..=max_symbol], accuracy_log)?;
+                Ok(size)
+            },
+            _ => {
+                // repeat the table of the previous block
+                if self.size == 0 {
+                    return Err(DecompressionError::InvalidFormat);
+                }
+                Ok(0)
+            },
+        }
+    }
+
+    /// Build the decoding table for normalized `probabilities`, where -1
+    /// stands for "less than one".
+    fn build(&mut self, probabilities: &[i16], accuracy_log: u32) -> Result<(), DecompressionError> {
+        let size = 1usize << accuracy_log;
+        let mut next = [0u32; MAX_FSE_SYMBOLS];
+
+        // low probability symbols take the last cells
+        let mut high = size - 1;
+        for (symbol, &probability) in probabilities.iter().enumerate() {
+            if probability == -1 {
+                self.entries[high].symbol = symbol as u8;
+                high = high.wrapping_sub(1);
+                next[symbol] = 1;
+            } else {
+                next[symbol] = probability.max(0) as u32;
+            }
+        }
+
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (symbol, &probability) in probabilities.iter().enumerate() {
+            for _ in 0..probability.max(0) {
+                self.entries[position].symbol = symbol as u8;
+                loop {
+                    position = (position + step) & (size - 1);
+                    if position <= high {
+                        break;
+                    }
+                }
+            }
+        }
+        if position != 0 {
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        for entry in self.entries[..size].iter_mut() {
+            let state = next[entry.symbol as usize];
+            next[entry.symbol as usize] += 1;
+            let bits = accuracy_log - highest_bit(state);
+            entry.bits = bits as u8;
+            entry.baseline = ((state << bits) as usize - size) as u16;
+        }
+
+        self.accuracy_log = accuracy_log;
+        self.size = size;
+        Ok(())
+    }
+}
+
+/// Read normalized probabilities of an FSE table description, returning
+/// the size of the description and the accuracy log.
+fn read_distribution(
+    data: &[u8],
+    max_log: u32,
+    max_symbol: usize,
+    probabilities: &mut [i16; MAX_FSE_SYMBOLS],
+) -> Result<(usize, u32), DecompressionError> {
+    let mut bits = BitReader::new(data);
+    let accuracy_log = bits.read(4) as u32 + 5;
+    if accuracy_log > max_log {
+        return Err(DecompressionError::InvalidFormat);
+    }
+
+    let mut remaining: i32 = (1 << accuracy_log) + 1;
+    let mut threshold: i32 = 1 << accuracy_log;
+    let mut bit_count = accuracy_log + 1;
+    let mut symbol = 0;
+    while remaining > 1 {
+        if symbol > max_symbol {
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        // values below `max` use one bit less
+        let max = 2 * threshold - 1 - remaining;
+        let value = bits.peek(bit_count) as i32;
+        let count = if value & (threshold - 1) < max {
+            bits.consume(bit_count - 1);
+            value & (threshold - 1)
+        } else {
+            bits.consume(bit_count);
+            let value = value & (2 * threshold - 1);
+            if value >= threshold { value - max } else { value }
+        };
+
+        let probability = count - 1;
+        remaining -= probability.abs();
+        probabilities[symbol] = probability as i16;
+        symbol += 1;
+
+        if probability == 0 {
+            loop {
+                let repeat = bits.read(2) as usize;
+                if symbol + repeat > max_symbol + 1 {
+                    return Err(DecompressionError::InvalidFormat);
+                }
+                symbol += repeat;
+                if repeat != 3 {
+                    break;
+                }
+            }
+        }
+
+        while remaining < threshold {
+            bit_count -= 1;
+            threshold >>= 1;
+        }
+    }
+
+    let size = bits.consumed_bytes();
+    if remaining != 1 || size > data.len() {
+        return Err(DecompressionError::InvalidFormat);
+    }
+
+    Ok((size, accuracy_log))
+}
+
+fn highest_bit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+/// Load up to eight bytes from `offset` as a little endian value, padded
+/// with zeros past the end of `data`.
+fn load_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    if offset < data.len() {
+        let available = (data.len() - offset).min(8);
+        bytes[..available].copy_from_slice(&data[offset..(offset + available)]);
+    }
+    u64::from_le_bytes(bytes)
+}
+
+/// Reads bits least significant first, zeros past the end of the data.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, position: 0 }
+    }
+
+    fn peek(&self, count: u32) -> u64 {
+        (load_u64(self.data, self.position / 8) >> (self.position % 8)) & ((1 << count) - 1)
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.position += count as usize;
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.consume(count);
+        value
+    }
+
+    fn consumed_bytes(&self) -> usize {
+        (self.position + 7) / 8
+    }
+}
+
+/// Reads bits backwards from the end of a stream, most recently written
+/// first. The stream ends in a one bit marking where the data starts.
+struct ReverseBitReader<'a> {
+    data: &'a [u8],
+    /// Number of bits left, negative once reading past the start.
+    position: isize,
+}
+
+impl<'a> ReverseBitReader<'a> {
+    fn new(data: &'a [u8]) -> Result<ReverseBitReader<'a>, DecompressionError> {
+        let last = *data.last().ok_or(DecompressionError::InvalidFormat)?;
+        if last == 0 {
+            return Err(DecompressionError::InvalidFormat);
+        }
+
+        Ok(ReverseBitReader {
+            data,
+            position: ((data.len() - 1) * 8) as isize + highest_bit(last as u32) as isize,
+        })
+    }
+
+    /// The `count` bits below the current position, reading zeros past the
+    /// start of the stream.
+    fn peek(&self, count: u32) -> u64 {
+        if count == 0 {
+            return 0;
+        }
+
+        let start = self.position - count as isize;
+        if start >= 0 {
+            let start = start as usize;
+            (load_u64(self.data, start / 8) >> (start % 8)) & ((1 << count) - 1)
+        } else if self.position <= 0 {
+            0
+        } else {
+            let available = self.position as usize;
+            let value = load_u64(self.data, 0) & ((1 << available) - 1);
+            value << (count as usize - available)
+        }
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.position -= count as isize;
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.consume(count);
+        value
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position == 0
+    }
+
+    fn is_overflowed(&self) -> bool {
+        self.position < 0
+    }
+}
@@ -0,0 +1,190 @@
+//! Runtime configuration.
+//!
+//! Options are `key=value` pairs, read from a configuration file on the boot
+//! volume (one option per line, `#` starts a comment) and from the image load
+//! options (separated by whitespace). Load options take precedence over the
+//! configuration file, which allows overriding options from the UEFI shell.
+//!
+//! The configuration file may define boot entries as sections, each started
+//! by a `[name]` line. Options of the selected entry take precedence over
+//! the global options at the start of the file. The entry is selected with
+//! the `entry` option, falling back to the `default` option.
+
+use super::{efi, global::Global};
+
+const DEFAULT_CONFIG_PATH: &str = "\\EFI\\MercurOS\\maia.cfg";
+
+const MAX_CONFIG_SIZE: usize = 64 * 1024;
+const MAX_LOAD_OPTIONS_SIZE: usize = 1024;
+
+#[derive(Clone, Copy)]
+struct Config {
+    file: &'static str,
+    load_options: &'static str,
+    entry: Option<&'static str>,
+}
+
+static CONFIG: Global<Config> = Global::new(Config { file: "", load_options: "", entry: None });
+
+/// Read load options and the configuration file.
+///
+/// A missing configuration file is not an error.
+pub fn load() -> Result<(), efi::Status> {
+    let load_options = read_load_options()?;
+    CONFIG.set(Config { load_options, ..CONFIG.get() });
+
+    let path = get("config").unwrap_or(DEFAULT_CONFIG_PATH);
+    let file = match read_file(path) {
+        Ok(file) => file,
+        Err(efi::status::NOT_FOUND) => "",
+        Err(status) => return Err(status),
+    };
+    CONFIG.set(Config { file, ..CONFIG.get() });
+
+    let entry = get("entry").or_else(|| get("default"));
+    CONFIG.set(Config { entry, ..CONFIG.get() });
+
+    Ok(())
+}
+
+/// Name of the selected boot entry, if any.
+pub fn entry() -> Option<&'static str> {
+    CONFIG.get().entry
+}
+
+/// Select the boot entry whose options apply from now on, e.g. as chosen
+/// from the boot menu.
+pub fn select_entry(name: &'static str) {
+    CONFIG.set(Config { entry: Some(name), ..CONFIG.get() });
+}
+
+/// Names of all boot entries defined in the configuration file.
+pub fn entries() -> impl Iterator<Item = &'static str> {
+    CONFIG.get().file.lines().filter_map(section_name)
+}
+
+/// Look up the value of an option.
+pub fn get(key: &str) -> Option<&'static str> {
+    let config = CONFIG.get();
+
+    find(options(config.load_options.split_whitespace()), key)
+        .or_else(|| {
+            let entry = config.entry?;
+            find(options(section(config.file, Some(entry))), key)
+        })
+        .or_else(|| find(options(section(config.file, None)), key))
+}
+
+/// Look up a numeric option, in decimal or `0x` prefixed hexadecimal.
+pub fn get_usize(key: &str) -> Option<usize> {
+    parse_usize(get(key)?)
+}
+
+/// Look up a boolean option (`on`/`off`, `yes`/`no`, `true`/`false`, `1`/`0`).
+pub fn get_bool(key: &str) -> Option<bool> {
+    match get(key)? {
+        "on" | "yes" | "true" | "1" => Some(true),
+        "off" | "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_usize(value: &str) -> Option<usize> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        usize::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+fn find<'a, I>(mut options: I, key: &str) -> Option<&'a str>
+where
+    I: Iterator<Item = (&'a str, &'a str)>,
+{
+    options.find(|&(option, _)| option == key).map(|(_, value)| value)
+}
+
+/// Lines of the global section (`None`) or of the named section.
+fn section<'a>(file: &'a str, name: Option<&'a str>) -> impl Iterator<Item = &'a str> {
+    let mut current = None;
+    file.lines().filter(move |line| match section_name(line) {
+        Some(section) => {
+            current = Some(section);
+            false
+        },
+        None => current == name,
+    })
+}
+
+fn section_name(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.starts_with('[') && line.ends_with(']') {
+        Some(line[1..(line.len() - 1)].trim())
+    } else {
+        None
+    }
+}
+
+/// Split raw option strings into `(key, value)` pairs, skipping blank
+/// lines, comments and anything without an `=`.
+fn options<'a, I>(items: I) -> impl Iterator<Item = (&'a str, &'a str)>
+where
+    I: Iterator<Item = &'a str>,
+{
+    items.filter_map(|item| {
+        let item = item.trim();
+        if item.starts_with('#') {
+            return None;
+        }
+
+        let separator = item.find('=')?;
+        Some((item[..separator].trim(), item[(separator + 1)..].trim()))
+    })
+}
+
+/// Convert the UCS-2 load options to ASCII, in loader data pages.
+///
+/// Load options given by a boot manager entry may be arbitrary binary data;
+/// those are ignored.
+fn read_load_options() -> Result<&'static str, efi::Status> {
+    let raw = efi::loaded_image()?.load_options();
+    if raw.is_empty() {
+        return Ok("");
+    }
+
+    let page_count = efi::memory::page_count(MAX_LOAD_OPTIONS_SIZE);
+    let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, page_count)?;
+    let buffer = &mut buffer[..MAX_LOAD_OPTIONS_SIZE];
+
+    let mut length = 0;
+    for chunk in raw.chunks_exact(2) {
+        let c = u16::from_le_bytes([chunk[0], chunk[1]]);
+        if c == 0 {
+            break;
+        }
+
+        if c >= 0x80 || length == buffer.len() {
+            return Ok("");
+        }
+
+        let c = c as u8;
+        if !(c.is_ascii_graphic() || c.is_ascii_whitespace()) {
+            return Ok("");
+        }
+
+        buffer[length] = c;
+        length += 1;
+    }
+
+    Ok(core::str::from_utf8(&buffer[..length]).unwrap_or(""))
+}
+
+/// Read a text file from the boot volume into loader data pages.
+fn read_file(path: &str) -> Result<&'static str, efi::Status> {
+    let mut file = efi::File::open_boot_volume()?
+        .open(path, efi::file::FILE_MODE_READ)?;
+
+    let buffer = file.read_to_pages(efi::memory::LOADER_DATA, MAX_CONFIG_SIZE)?;
+
+    core::str::from_utf8(buffer).map_err(|_| efi::status::LOAD_ERROR)
+}
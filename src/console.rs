@@ -0,0 +1,175 @@
+//! Boot console, mirroring output to the firmware console and a serial port.
+//!
+//! The firmware console is only used while boot services are active, the
+//! serial port and framebuffer console (if attached) keep working until the
+//! kernel is entered. All output is also recorded in the boot log buffer
+//! handed to the kernel.
+//!
+//! While a boot splash is shown, nothing is written to the screen. The splash
+//! is hidden as soon as an error is reported.
+
+use super::{
+    efi,
+    framebuffer::{Color, Splash, TextConsole},
+    global::Global,
+    log_buffer::LogBuffer,
+    serial::Serial,
+};
+
+/// Emphasis of console output, shown as colors where the sink supports it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    Normal,
+    Warning,
+    Error,
+}
+
+struct Console {
+    serial: Option<Serial>,
+    framebuffer: Option<TextConsole>,
+    splash: Option<Splash>,
+    log_buffer: Option<LogBuffer>,
+}
+
+static CONSOLE: Global<Console> = Global::new(Console {
+    serial: None,
+    framebuffer: None,
+    splash: None,
+    log_buffer: None,
+});
+
+pub fn attach_serial(serial: Serial) {
+    CONSOLE.try_with(|console| console.serial = Some(serial));
+}
+
+/// Draw all further output to the framebuffer, starting with the output
+/// recorded in the boot log so far.
+pub fn attach_framebuffer(mut text_console: TextConsole) {
+    CONSOLE.try_with(|console| {
+        replay_log(&mut text_console, console.log_buffer.as_ref());
+        console.framebuffer = Some(text_console);
+    });
+}
+
+/// Take over the screen with a boot splash.
+pub fn show_splash(splash: Splash) {
+    CONSOLE.try_with(|console| console.splash = Some(splash));
+}
+
+/// Advance the progress bar of the boot splash, if one is shown.
+pub fn set_progress(percent: usize) {
+    CONSOLE.try_with(|console| {
+        if let Some(splash) = console.splash.as_mut() {
+            splash.set_progress(percent);
+        }
+    });
+}
+
+/// Remove the boot splash and return the screen to text output.
+pub fn hide_splash() {
+    CONSOLE.try_with(|console| {
+        let splash = match console.splash.take() {
+            Some(splash) => splash,
+            None => return,
+        };
+        splash.hide();
+
+        if let Some(con_out) = efi::con_out() {
+            con_out.clear_screen();
+        }
+        if let Some(text_console) = console.framebuffer.as_mut() {
+            replay_log(text_console, console.log_buffer.as_ref());
+        }
+    });
+}
+
+fn replay_log(text_console: &mut TextConsole, log_buffer: Option<&LogBuffer>) {
+    text_console.clear();
+
+    if let Some(log_buffer) = log_buffer {
+        let (first, second) = log_buffer.contents();
+        for &part in [first, second].iter() {
+            write_utf8_lossy(text_console, part);
+        }
+    }
+}
+
+/// The oldest part of a wrapped log may start in the middle of a character.
+fn write_utf8_lossy(text_console: &mut TextConsole, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        match core::str::from_utf8(bytes) {
+            Ok(s) => return text_console.write_str(s),
+            Err(error) => {
+                let (valid, rest) = bytes.split_at(error.valid_up_to());
+                text_console.write_str(unsafe { core::str::from_utf8_unchecked(valid) });
+                bytes = &rest[1..];
+            },
+        }
+    }
+}
+
+pub fn set_style(style: Style) {
+    if style == Style::Error {
+        hide_splash();
+    }
+
+    CONSOLE.try_with(|console| {
+        if let Some(text_console) = console.framebuffer.as_mut() {
+            let foreground = match style {
+                Style::Normal => Color::LIGHT_GRAY,
+                Style::Warning => Color::YELLOW,
+                Style::Error => Color::RED,
+            };
+            text_console.set_colors(foreground, Color::BLACK);
+        }
+    });
+}
+
+/// Record all further output in `log_buffer`.
+pub fn attach_log_buffer(log_buffer: LogBuffer) {
+    CONSOLE.try_with(|console| console.log_buffer = Some(log_buffer));
+}
+
+/// Run `f` with the boot log buffer, if one is attached. Console output
+/// from within `f` is dropped.
+pub fn with_log_buffer<R, F>(f: F) -> Option<R>
+where
+    F: FnOnce(&LogBuffer) -> R,
+{
+    CONSOLE.try_with(|console| console.log_buffer.as_ref().map(f)).flatten()
+}
+
+pub fn write_str(s: &str) {
+    CONSOLE.try_with(|console| {
+        let screen = console.splash.is_none();
+
+        if let (true, Some(con_out)) = (screen, efi::con_out()) {
+            con_out.write_str(s);
+        }
+
+        if let Some(serial) = console.serial.as_mut() {
+            let _ = core::fmt::Write::write_str(serial, s);
+        }
+
+        if let (true, Some(text_console)) = (screen, console.framebuffer.as_mut()) {
+            text_console.write_str(s);
+        }
+
+        if let Some(log_buffer) = console.log_buffer.as_mut() {
+            log_buffer.write(s.as_bytes());
+        }
+    });
+}
+
+pub fn write_fmt(args: core::fmt::Arguments) {
+    struct Writer;
+
+    impl core::fmt::Write for Writer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            write_str(s);
+            Ok(())
+        }
+    }
+
+    let _ = core::fmt::Write::write_fmt(&mut Writer, args);
+}
@@ -0,0 +1,77 @@
+use super::{Guid, Handle, MemoryDescriptor, Status, TableHeader};
+
+pub type Event = *mut core::ffi::c_void;
+
+pub const ALLOCATE_ANY_PAGES: u32 = 0;
+pub const ALLOCATE_MAX_ADDRESS: u32 = 1;
+pub const ALLOCATE_ADDRESS: u32 = 2;
+
+#[repr(C)]
+pub struct BootServices {
+    pub header: TableHeader,
+    raise_tpl: usize,
+    restore_tpl: usize,
+    pub allocate_pages: extern "efiapi" fn(u32, u32, usize, *mut u64) -> Status,
+    pub free_pages: extern "efiapi" fn(u64, usize) -> Status,
+    pub get_memory_map: extern "efiapi" fn(
+        *mut usize,
+        *mut MemoryDescriptor,
+        *mut usize,
+        *mut usize,
+        *mut u32,
+    ) -> Status,
+    pub allocate_pool: extern "efiapi" fn(u32, usize, *mut *mut u8) -> Status,
+    pub free_pool: extern "efiapi" fn(*mut u8) -> Status,
+    create_event: usize,
+    set_timer: usize,
+    pub wait_for_event: extern "efiapi" fn(usize, *const Event, *mut usize) -> Status,
+    signal_event: usize,
+    close_event: usize,
+    check_event: usize,
+    install_protocol_interface: usize,
+    reinstall_protocol_interface: usize,
+    uninstall_protocol_interface: usize,
+    pub handle_protocol: extern "efiapi" fn(
+        Handle,
+        *const Guid,
+        *mut *mut core::ffi::c_void,
+    ) -> Status,
+    _reserved: usize,
+    register_protocol_notify: usize,
+    locate_handle: usize,
+    locate_device_path: usize,
+    install_configuration_table: usize,
+    pub load_image: extern "efiapi" fn(
+        bool,
+        Handle,
+        *const core::ffi::c_void,
+        *const core::ffi::c_void,
+        usize,
+        *mut Handle,
+    ) -> Status,
+    pub start_image: extern "efiapi" fn(Handle, *mut usize, *mut *mut u16) -> Status,
+    exit: usize,
+    pub unload_image: extern "efiapi" fn(Handle) -> Status,
+    pub exit_boot_services: extern "efiapi" fn(Handle, usize) -> Status,
+    get_next_monotonic_count: usize,
+    pub stall: extern "efiapi" fn(usize) -> Status,
+    pub set_watchdog_timer: extern "efiapi" fn(usize, u64, usize, *const u16) -> Status,
+    connect_controller: usize,
+    disconnect_controller: usize,
+    open_protocol: usize,
+    close_protocol: usize,
+    open_protocol_information: usize,
+    protocols_per_handle: usize,
+    locate_handle_buffer: usize,
+    pub locate_protocol: extern "efiapi" fn(
+        *const Guid,
+        *const core::ffi::c_void,
+        *mut *mut core::ffi::c_void,
+    ) -> Status,
+    install_multiple_protocol_interfaces: usize,
+    uninstall_multiple_protocol_interfaces: usize,
+    calculate_crc32: usize,
+    copy_mem: usize,
+    set_mem: usize,
+    create_event_ex: usize,
+}
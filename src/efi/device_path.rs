@@ -0,0 +1,86 @@
+use super::{Guid, Handle, Status, status};
+
+pub const DEVICE_PATH_PROTOCOL_GUID: Guid = Guid(
+    0x09576e91, 0x6d3f, 0x11d2,
+    [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+);
+
+const TYPE_MEDIA: u8 = 0x04;
+const SUBTYPE_FILE_PATH: u8 = 0x04;
+const TYPE_END: u8 = 0x7f;
+const SUBTYPE_END_ENTIRE: u8 = 0xff;
+
+const NODE_HEADER_SIZE: usize = 4;
+const MAX_DEVICE_PATH_SIZE: usize = 1024;
+
+/// A device path built in place, ending in an end node.
+pub struct DevicePath {
+    buffer: [u8; MAX_DEVICE_PATH_SIZE],
+    length: usize,
+}
+
+impl DevicePath {
+    /// Device path of `path` on the volume of `device`: the device's own
+    /// path followed by a file path node.
+    ///
+    /// Both '/' and '\' are accepted as path separators.
+    pub fn file(device: Handle, path: &str) -> Result<DevicePath, Status> {
+        let device_path = super::handle_protocol::<u8>(device, &DEVICE_PATH_PROTOCOL_GUID)?;
+
+        let mut device_path_buffer = DevicePath {
+            buffer: [0u8; MAX_DEVICE_PATH_SIZE],
+            length: 0,
+        };
+
+        // copy the device path up to its end node
+        let mut node = device_path as *const u8;
+        loop {
+            let (node_type, length) = unsafe {
+                (*node, u16::from_le_bytes([*node.add(2), *node.add(3)]) as usize)
+            };
+            if node_type == TYPE_END || length < NODE_HEADER_SIZE {
+                break;
+            }
+
+            let bytes = unsafe { core::slice::from_raw_parts(node, length) };
+            device_path_buffer.push(bytes)?;
+            node = unsafe { node.add(length) };
+        }
+
+        let name_size = 2 * (path.chars().count() + 1);
+        let node_size = NODE_HEADER_SIZE + name_size;
+        if node_size > u16::MAX as usize {
+            return Err(status::INVALID_PARAMETER);
+        }
+        device_path_buffer.push(&[TYPE_MEDIA, SUBTYPE_FILE_PATH])?;
+        device_path_buffer.push(&(node_size as u16).to_le_bytes())?;
+        for c in path.chars().chain(core::iter::once('\0')) {
+            let c = match c {
+                '/' => '\\' as u16,
+                c if (c as u32) < 0x10000 => c as u16,
+                _ => return Err(status::INVALID_PARAMETER),
+            };
+            device_path_buffer.push(&c.to_le_bytes())?;
+        }
+
+        device_path_buffer.push(&[TYPE_END, SUBTYPE_END_ENTIRE, NODE_HEADER_SIZE as u8, 0])?;
+
+        Ok(device_path_buffer)
+    }
+
+    pub fn as_ptr(&self) -> *const core::ffi::c_void {
+        self.buffer.as_ptr() as *const core::ffi::c_void
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), Status> {
+        let end = self.length + bytes.len();
+        if end > MAX_DEVICE_PATH_SIZE {
+            return Err(status::BUFFER_TOO_SMALL);
+        }
+
+        self.buffer[self.length..end].copy_from_slice(bytes);
+        self.length = end;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use super::{memory::{self, MemoryType}, Guid, Handle, Status, status};
+
+pub const SIMPLE_FILE_SYSTEM_PROTOCOL_GUID: Guid = Guid(
+    0x964e5b22, 0x6459, 0x11d2,
+    [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+);
+
+pub const FILE_INFO_GUID: Guid = Guid(
+    0x09576e92, 0x6d3f, 0x11d2,
+    [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+);
+
+pub const FILE_MODE_READ: u64 = 0x0000_0000_0000_0001;
+pub const FILE_MODE_WRITE: u64 = 0x0000_0000_0000_0002;
+pub const FILE_MODE_CREATE: u64 = 0x8000_0000_0000_0000;
+
+const MAX_PATH_LENGTH: usize = 256;
+
+#[repr(C)]
+struct SimpleFileSystemProtocol {
+    revision: u64,
+    open_volume: extern "efiapi" fn(
+        *mut SimpleFileSystemProtocol,
+        *mut *mut FileProtocol,
+    ) -> Status,
+}
+
+#[repr(C)]
+struct FileProtocol {
+    revision: u64,
+    open: extern "efiapi" fn(
+        *mut FileProtocol,
+        *mut *mut FileProtocol,
+        *const u16,
+        u64,
+        u64,
+    ) -> Status,
+    close: extern "efiapi" fn(*mut FileProtocol) -> Status,
+    delete: extern "efiapi" fn(*mut FileProtocol) -> Status,
+    read: extern "efiapi" fn(*mut FileProtocol, *mut usize, *mut u8) -> Status,
+    write: extern "efiapi" fn(*mut FileProtocol, *mut usize, *const u8) -> Status,
+    get_position: extern "efiapi" fn(*mut FileProtocol, *mut u64) -> Status,
+    set_position: extern "efiapi" fn(*mut FileProtocol, u64) -> Status,
+    get_info: extern "efiapi" fn(*mut FileProtocol, *const Guid, *mut usize, *mut u8) -> Status,
+    set_info: extern "efiapi" fn(*mut FileProtocol, *const Guid, usize, *const u8) -> Status,
+    flush: extern "efiapi" fn(*mut FileProtocol) -> Status,
+}
+
+/// Leading fixed size part of `EFI_FILE_INFO`, followed by the file name.
+#[repr(C)]
+struct FileInfo {
+    size: u64,
+    file_size: u64,
+    physical_size: u64,
+    create_time: [u8; 16],
+    last_access_time: [u8; 16],
+    modification_time: [u8; 16],
+    attribute: u64,
+}
+
+/// An open file or directory. Closed when dropped.
+pub struct File {
+    raw: *mut FileProtocol,
+}
+
+impl File {
+    /// Open the root directory of the volume on the given device.
+    pub fn open_volume(device: Handle) -> Result<File, Status> {
+        let file_system = super::handle_protocol::<SimpleFileSystemProtocol>(
+            device,
+            &SIMPLE_FILE_SYSTEM_PROTOCOL_GUID,
+        )?;
+
+        let mut raw = core::ptr::null_mut();
+        status::to_result((file_system.open_volume)(file_system, &mut raw))?;
+
+        Ok(File { raw })
+    }
+
+    /// Open the root directory of the volume Maia was loaded from.
+    pub fn open_boot_volume() -> Result<File, Status> {
+        let loaded_image = super::loaded_image()?;
+        File::open_volume(loaded_image.device_handle)
+    }
+
+    /// Open a file relative to this directory.
+    ///
+    /// Both '/' and '\' are accepted as path separators.
+    pub fn open(&self, path: &str, mode: u64) -> Result<File, Status> {
+        let mut path_buffer = [0u16; MAX_PATH_LENGTH];
+        let mut length = 0;
+        for c in path.chars() {
+            // reserve space for the terminating NUL
+            if length + 1 >= MAX_PATH_LENGTH {
+                return Err(status::INVALID_PARAMETER);
+            }
+
+            path_buffer[length] = match c {
+                '/' => '\\' as u16,
+                c if (c as u32) < 0x10000 => c as u16,
+                _ => return Err(status::INVALID_PARAMETER),
+            };
+            length += 1;
+        }
+
+        let mut raw = core::ptr::null_mut();
+        status::to_result(unsafe {
+            ((*self.raw).open)(self.raw, &mut raw, &path_buffer[0], mode, 0)
+        })?;
+
+        Ok(File { raw })
+    }
+
+    /// Read from the current position, returning the number of bytes read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Status> {
+        let mut size = buffer.len();
+        status::to_result(unsafe {
+            ((*self.raw).read)(self.raw, &mut size, buffer.as_mut_ptr())
+        })?;
+
+        Ok(size)
+    }
+
+    /// Fill `buffer` completely, failing on a premature end of file.
+    pub fn read_exact(&mut self, mut buffer: &mut [u8]) -> Result<(), Status> {
+        while !buffer.is_empty() {
+            let count = self.read(buffer)?;
+            if count == 0 {
+                return Err(status::END_OF_FILE);
+            }
+            buffer = &mut buffer[count..];
+        }
+
+        Ok(())
+    }
+
+    /// Read the whole file into newly allocated pages of `memory_type`.
+    ///
+    /// Files larger than `max_size` are rejected with `BUFFER_TOO_SMALL`.
+    /// The returned slice is exactly as long as the file.
+    pub fn read_to_pages(
+        &mut self,
+        memory_type: MemoryType,
+        max_size: usize,
+    ) -> Result<&'static mut [u8], Status> {
+        let size = self.size()? as usize;
+        if size > max_size {
+            return Err(status::BUFFER_TOO_SMALL);
+        }
+        if size == 0 {
+            return Ok(&mut []);
+        }
+
+        self.set_position(0)?;
+        let buffer = super::allocate_pages(memory_type, memory::page_count(size))?;
+        self.read_exact(&mut buffer[..size])?;
+
+        Ok(&mut buffer[..size])
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, Status> {
+        let mut size = data.len();
+        status::to_result(unsafe {
+            ((*self.raw).write)(self.raw, &mut size, data.as_ptr())
+        })?;
+
+        Ok(size)
+    }
+
+    pub fn write_all(&mut self, mut data: &[u8]) -> Result<(), Status> {
+        while !data.is_empty() {
+            let count = self.write(data)?;
+            if count == 0 {
+                return Err(status::DEVICE_ERROR);
+            }
+            data = &data[count..];
+        }
+
+        Ok(())
+    }
+
+    pub fn position(&mut self) -> Result<u64, Status> {
+        let mut position = 0;
+        status::to_result(unsafe {
+            ((*self.raw).get_position)(self.raw, &mut position)
+        })?;
+
+        Ok(position)
+    }
+
+    /// Seek to `position`. `u64::MAX` moves to the end of the file.
+    pub fn set_position(&mut self, position: u64) -> Result<(), Status> {
+        status::to_result(unsafe {
+            ((*self.raw).set_position)(self.raw, position)
+        })
+    }
+
+    /// Read `EFI_FILE_INFO` into `buffer`.
+    ///
+    /// FileInfo is followed by a variable length file name,
+    /// so a u64 array is used to get suitable alignment.
+    fn info<'a>(&mut self, buffer: &'a mut [u64]) -> Result<&'a mut FileInfo, Status> {
+        let mut size = core::mem::size_of_val(buffer);
+
+        status::to_result(unsafe {
+            ((*self.raw).get_info)(
+                self.raw,
+                &FILE_INFO_GUID,
+                &mut size,
+                buffer.as_mut_ptr() as *mut u8,
+            )
+        })?;
+
+        Ok(unsafe { &mut *(buffer.as_mut_ptr() as *mut FileInfo) })
+    }
+
+    pub fn size(&mut self) -> Result<u64, Status> {
+        let mut buffer = [0u64; 64];
+        Ok(self.info(&mut buffer)?.file_size)
+    }
+
+    /// Rename the file within its directory.
+    pub fn rename(&mut self, name: &str) -> Result<(), Status> {
+        let mut buffer = [0u64; 64];
+        let info = self.info(&mut buffer)? as *mut FileInfo;
+
+        let name_offset = core::mem::size_of::<FileInfo>();
+        let name_capacity = (core::mem::size_of_val(&buffer) - name_offset) / 2;
+        if name.chars().count() >= name_capacity {
+            return Err(status::INVALID_PARAMETER);
+        }
+
+        let name_buffer = unsafe {
+            core::slice::from_raw_parts_mut(
+                (buffer.as_mut_ptr() as *mut u8).add(name_offset) as *mut u16,
+                name_capacity,
+            )
+        };
+
+        let mut length = 0;
+        for c in name.chars() {
+            if c as u32 >= 0x10000 {
+                return Err(status::INVALID_PARAMETER);
+            }
+            name_buffer[length] = c as u16;
+            length += 1;
+        }
+        name_buffer[length] = 0;
+
+        let size = name_offset + (length + 1) * 2;
+        unsafe { (*info).size = size as u64; }
+
+        status::to_result(unsafe {
+            ((*self.raw).set_info)(
+                self.raw,
+                &FILE_INFO_GUID,
+                size,
+                buffer.as_ptr() as *const u8,
+            )
+        })
+    }
+
+    pub fn flush(&mut self) -> Result<(), Status> {
+        status::to_result(unsafe { ((*self.raw).flush)(self.raw) })
+    }
+
+    /// Delete the file. The handle is closed even if deletion fails.
+    pub fn delete(self) -> Result<(), Status> {
+        let raw = self.raw;
+        core::mem::forget(self);
+
+        match unsafe { ((*raw).delete)(raw) } {
+            status::WARN_DELETE_FAILURE => Err(status::WARN_DELETE_FAILURE),
+            status => status::to_result(status),
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe { ((*self.raw).close)(self.raw); }
+    }
+}
@@ -0,0 +1,82 @@
+use super::{Guid, Status};
+
+pub const GRAPHICS_OUTPUT_PROTOCOL_GUID: Guid = Guid(
+    0x9042a9de, 0x23dc, 0x4a38,
+    [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a],
+);
+
+pub const PIXEL_RGB_RESERVED_8BIT: u32 = 0;
+pub const PIXEL_BGR_RESERVED_8BIT: u32 = 1;
+pub const PIXEL_BIT_MASK: u32 = 2;
+pub const PIXEL_BLT_ONLY: u32 = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PixelBitmask {
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub reserved_mask: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ModeInformation {
+    pub version: u32,
+    pub horizontal_resolution: u32,
+    pub vertical_resolution: u32,
+    pub pixel_format: u32,
+    pub pixel_information: PixelBitmask,
+    pub pixels_per_scan_line: u32,
+}
+
+#[repr(C)]
+pub struct Mode {
+    pub max_mode: u32,
+    pub mode: u32,
+    pub info: *const ModeInformation,
+    pub size_of_info: usize,
+    pub frame_buffer_base: u64,
+    pub frame_buffer_size: usize,
+}
+
+#[repr(C)]
+pub struct GraphicsOutput {
+    query_mode: extern "efiapi" fn(
+        *mut GraphicsOutput,
+        u32,
+        *mut usize,
+        *mut *const ModeInformation,
+    ) -> Status,
+    set_mode: extern "efiapi" fn(*mut GraphicsOutput, u32) -> Status,
+    blt: usize,
+    mode: *const Mode,
+}
+
+impl GraphicsOutput {
+    pub fn locate() -> Result<&'static mut GraphicsOutput, Status> {
+        super::locate_protocol::<GraphicsOutput>(&GRAPHICS_OUTPUT_PROTOCOL_GUID)
+    }
+
+    pub fn mode(&self) -> &Mode {
+        unsafe { &*self.mode }
+    }
+
+    pub fn mode_info(&self) -> ModeInformation {
+        unsafe { *self.mode().info }
+    }
+
+    pub fn query_mode(&mut self, mode: u32) -> Result<ModeInformation, Status> {
+        let mut size = 0;
+        let mut info = core::ptr::null();
+        super::status::to_result((self.query_mode)(self, mode, &mut size, &mut info))?;
+
+        // the buffer returned by QueryMode is pool memory owned by us,
+        // but leaking it is harmless
+        Ok(unsafe { *info })
+    }
+
+    pub fn set_mode(&mut self, mode: u32) -> Result<(), Status> {
+        super::status::to_result((self.set_mode)(self, mode))
+    }
+}
@@ -0,0 +1,39 @@
+use super::{Guid, Handle, SystemTable};
+
+pub const LOADED_IMAGE_PROTOCOL_GUID: Guid = Guid(
+    0x5b1b31a1, 0x9562, 0x11d2,
+    [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+);
+
+#[repr(C)]
+pub struct LoadedImage {
+    pub revision: u32,
+    pub parent_handle: Handle,
+    pub system_table: *mut SystemTable,
+    pub device_handle: Handle,
+    pub file_path: *const core::ffi::c_void,
+    _reserved: *mut core::ffi::c_void,
+    pub load_options_size: u32,
+    pub load_options: *const core::ffi::c_void,
+    pub image_base: *mut core::ffi::c_void,
+    pub image_size: u64,
+    pub image_code_type: u32,
+    pub image_data_type: u32,
+    unload: usize,
+}
+
+impl LoadedImage {
+    /// Load options as passed by the boot manager or shell.
+    pub fn load_options(&self) -> &[u8] {
+        if self.load_options.is_null() {
+            return &[];
+        }
+
+        unsafe {
+            core::slice::from_raw_parts(
+                self.load_options as *const u8,
+                self.load_options_size as usize,
+            )
+        }
+    }
+}
@@ -0,0 +1,206 @@
+pub type MemoryType = u32;
+
+pub const RESERVED_MEMORY_TYPE: MemoryType = 0;
+pub const LOADER_CODE: MemoryType = 1;
+pub const LOADER_DATA: MemoryType = 2;
+pub const BOOT_SERVICES_CODE: MemoryType = 3;
+pub const BOOT_SERVICES_DATA: MemoryType = 4;
+pub const RUNTIME_SERVICES_CODE: MemoryType = 5;
+pub const RUNTIME_SERVICES_DATA: MemoryType = 6;
+pub const CONVENTIONAL_MEMORY: MemoryType = 7;
+pub const UNUSABLE_MEMORY: MemoryType = 8;
+pub const ACPI_RECLAIM_MEMORY: MemoryType = 9;
+pub const ACPI_MEMORY_NVS: MemoryType = 10;
+pub const MEMORY_MAPPED_IO: MemoryType = 11;
+pub const MEMORY_MAPPED_IO_PORT_SPACE: MemoryType = 12;
+pub const PAL_CODE: MemoryType = 13;
+pub const PERSISTENT_MEMORY: MemoryType = 14;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// Descriptors a memory map buffer has room for beyond the current map, as
+/// the allocation itself (and any later ones) may split existing entries.
+const HEADROOM: usize = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MemoryDescriptor {
+    pub r#type: MemoryType,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub number_of_pages: u64,
+    pub attribute: u64,
+}
+
+pub fn type_name(memory_type: MemoryType) -> &'static str {
+    match memory_type {
+        RESERVED_MEMORY_TYPE => "EfiReservedMemoryType",
+        LOADER_CODE => "EfiLoaderCode",
+        LOADER_DATA => "EfiLoaderData",
+        BOOT_SERVICES_CODE => "EfiBootServicesCode",
+        BOOT_SERVICES_DATA => "EfiBootServicesData",
+        RUNTIME_SERVICES_CODE => "EfiRuntimeServicesCode",
+        RUNTIME_SERVICES_DATA => "EfiRuntimeServicesData",
+        CONVENTIONAL_MEMORY => "EfiConventionalMemory",
+        UNUSABLE_MEMORY => "EfiUnusableMemory",
+        ACPI_RECLAIM_MEMORY => "EfiACPIReclaimMemory",
+        ACPI_MEMORY_NVS => "EfiACPIMemoryNVS",
+        MEMORY_MAPPED_IO => "EfiMemoryMappedIO",
+        MEMORY_MAPPED_IO_PORT_SPACE => "EfiMemoryMappedIOPortSpace",
+        PAL_CODE => "EfiPalCode",
+        PERSISTENT_MEMORY => "EfiPersistentMemory",
+        _ => "unknown memory type",
+    }
+}
+
+pub fn page_count(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) / PAGE_SIZE
+}
+
+/// Firmware memory map, fetched into a buffer owned by Maia.
+///
+/// Once allocated, the map can be refreshed without any further
+/// allocations, which would invalidate the map key again.
+pub struct MemoryMap {
+    buffer: &'static mut [u8],
+    size: usize,
+    key: usize,
+    descriptor_size: usize,
+    descriptor_version: u32,
+}
+
+impl MemoryMap {
+    /// Allocate a buffer for the memory map, with some headroom, and fetch
+    /// it.
+    pub fn allocate() -> Result<MemoryMap, super::Status> {
+        let (size, descriptor_size, descriptor_version) = query_size()?;
+        let buffer = super::allocate_pages(LOADER_DATA, buffer_page_count(size, descriptor_size))?;
+
+        let mut memory_map = MemoryMap {
+            buffer,
+            size: 0,
+            key: 0,
+            descriptor_size,
+            descriptor_version,
+        };
+        memory_map.refresh()?;
+
+        Ok(memory_map)
+    }
+
+    /// Number of descriptors a map allocated now would have room for.
+    pub fn planned_capacity() -> Result<usize, super::Status> {
+        let (size, descriptor_size, _) = query_size()?;
+        Ok(buffer_page_count(size, descriptor_size) * PAGE_SIZE / descriptor_size.max(48))
+    }
+
+    /// Fetch the current memory map into the existing buffer.
+    pub fn refresh(&mut self) -> Result<(), super::Status> {
+        let boot_services = super::boot_services().ok_or(super::status::UNSUPPORTED)?;
+        self.fetch(boot_services)
+    }
+
+    /// Also usable after a failed `ExitBootServices`, when only memory
+    /// services may still be called. If the map has outgrown the buffer,
+    /// the buffer is replaced by a larger one and the map fetched again.
+    pub(super) fn fetch(&mut self, boot_services: &super::BootServices) -> Result<(), super::Status> {
+        loop {
+            let mut size = self.buffer.len();
+            let status = (boot_services.get_memory_map)(
+                &mut size,
+                self.buffer.as_mut_ptr() as *mut MemoryDescriptor,
+                &mut self.key,
+                &mut self.descriptor_size,
+                &mut self.descriptor_version,
+            );
+            if status != super::status::BUFFER_TOO_SMALL {
+                super::status::to_result(status)?;
+                self.size = size;
+
+                return Ok(());
+            }
+
+            self.grow(boot_services, size + HEADROOM * self.descriptor_size)?;
+        }
+    }
+
+    /// Replace the buffer by one of at least `size` bytes. Calls boot
+    /// services directly, as the wrappers refuse to once exiting them has
+    /// been attempted.
+    fn grow(&mut self, boot_services: &super::BootServices, size: usize) -> Result<(), super::Status> {
+        let mut address = 0u64;
+        super::status::to_result((boot_services.allocate_pages)(
+            super::boot_services::ALLOCATE_ANY_PAGES,
+            LOADER_DATA,
+            page_count(size),
+            &mut address,
+        ))?;
+
+        (boot_services.free_pages)(self.buffer.as_ptr() as u64, page_count(self.buffer.len()));
+        self.buffer = unsafe {
+            core::slice::from_raw_parts_mut(address as *mut u8, page_count(size) * PAGE_SIZE)
+        };
+
+        Ok(())
+    }
+
+    /// Release the buffer, for maps that are not handed to the kernel.
+    pub fn free(self) {
+        super::free_pages(self.buffer);
+    }
+
+    /// Location of the buffer, as `(address, size)`.
+    pub fn allocation(&self) -> (u64, u64) {
+        (self.buffer.as_ptr() as u64, self.buffer.len() as u64)
+    }
+
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    pub fn descriptor_size(&self) -> usize {
+        self.descriptor_size
+    }
+
+    pub fn descriptor_version(&self) -> u32 {
+        self.descriptor_version
+    }
+
+    /// The raw descriptor array, `descriptor_size` bytes per entry.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.size]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryDescriptor> {
+        self.as_bytes()
+            .chunks_exact(self.descriptor_size)
+            .map(|entry| unsafe { &*(entry.as_ptr() as *const MemoryDescriptor) })
+    }
+}
+
+/// Size of the current memory map and of its descriptors, along with the
+/// descriptor version.
+fn query_size() -> Result<(usize, usize, u32), super::Status> {
+    let boot_services = super::boot_services().ok_or(super::status::UNSUPPORTED)?;
+
+    let mut size = 0;
+    let mut key = 0;
+    let mut descriptor_size = 0;
+    let mut descriptor_version = 0;
+    let status = (boot_services.get_memory_map)(
+        &mut size,
+        core::ptr::null_mut(),
+        &mut key,
+        &mut descriptor_size,
+        &mut descriptor_version,
+    );
+    if status != super::status::BUFFER_TOO_SMALL {
+        super::status::to_result(status)?;
+    }
+
+    Ok((size, descriptor_size, descriptor_version))
+}
+
+fn buffer_page_count(size: usize, descriptor_size: usize) -> usize {
+    page_count(size + HEADROOM * descriptor_size.max(48))
+}
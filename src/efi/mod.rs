@@ -0,0 +1,371 @@
+//! Raw UEFI table and protocol definitions.
+//!
+//! `mercuros_uefi` covers the services the boot flow has always needed.
+//! Anything that must keep working without holding the `Application`
+//! (e.g. console output from a global logger) goes through these bindings.
+
+pub mod configuration_table;
+pub mod device_path;
+pub mod file;
+pub mod graphics_output;
+pub mod loaded_image;
+pub mod memory;
+pub mod riscv_boot;
+pub mod status;
+pub mod text_input;
+
+mod boot_services;
+mod runtime_services;
+mod system_table;
+mod text_output;
+
+pub use self::{
+    boot_services::BootServices,
+    configuration_table::ConfigurationTable,
+    device_path::DevicePath,
+    file::File,
+    graphics_output::GraphicsOutput,
+    loaded_image::LoadedImage,
+    memory::{MemoryDescriptor, MemoryMap, MemoryType},
+    riscv_boot::RiscvBoot,
+    runtime_services::RuntimeServices,
+    system_table::{SystemTable, TableHeader},
+    text_input::SimpleTextInput,
+    text_output::SimpleTextOutput,
+};
+
+use super::global::Global;
+
+pub type Handle = *mut core::ffi::c_void;
+pub type Status = usize;
+
+/// Number of attempts at `ExitBootServices` before giving up.
+const EXIT_BOOT_SERVICES_ATTEMPTS: usize = 8;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq)]
+pub struct Guid(pub u32, pub u16, pub u16, pub [u8; 8]);
+
+#[derive(Clone, Copy)]
+struct Firmware {
+    image_handle: Handle,
+    system_table: *const SystemTable,
+    boot_services_active: bool,
+}
+
+static FIRMWARE: Global<Firmware> = Global::new(Firmware {
+    image_handle: core::ptr::null_mut(),
+    system_table: core::ptr::null(),
+    boot_services_active: false,
+});
+
+/// Unsafe: `system_table` must point to the firmware provided system table.
+pub unsafe fn init(image_handle: Handle, system_table: *const SystemTable) {
+    FIRMWARE.set(Firmware {
+        image_handle,
+        system_table,
+        boot_services_active: !system_table.is_null(),
+    });
+}
+
+pub fn image_handle() -> Handle {
+    FIRMWARE.get().image_handle
+}
+
+pub fn system_table() -> Option<&'static SystemTable> {
+    unsafe { FIRMWARE.get().system_table.as_ref() }
+}
+
+pub fn boot_services_active() -> bool {
+    FIRMWARE.get().boot_services_active
+}
+
+/// Exit boot services, fetching the final memory map into `memory_map`.
+///
+/// `ExitBootServices` fails with `EFI_INVALID_PARAMETER` if the memory map
+/// changed since it was fetched, e.g. because a firmware event allocated
+/// memory. The map is then fetched again and the call retried, a bounded
+/// number of times. `prepare` is called with each fetched map and must
+/// neither allocate nor use any other boot service.
+///
+/// No output is attempted through the firmware console once this is
+/// called, even if exiting fails.
+pub fn exit_boot_services<F>(memory_map: &mut MemoryMap, mut prepare: F) -> Result<(), Status>
+where
+    F: FnMut(&MemoryMap) -> Result<(), Status>,
+{
+    let boot_services = boot_services().ok_or(status::UNSUPPORTED)?;
+    FIRMWARE.set(Firmware { boot_services_active: false, ..FIRMWARE.get() });
+
+    let mut result = Err(status::INVALID_PARAMETER);
+    for _ in 0..EXIT_BOOT_SERVICES_ATTEMPTS {
+        memory_map.fetch(boot_services)?;
+        prepare(memory_map)?;
+
+        result = status::to_result((boot_services.exit_boot_services)(
+            image_handle(),
+            memory_map.key(),
+        ));
+        if result != Err(status::INVALID_PARAMETER) {
+            break;
+        }
+    }
+
+    result
+}
+
+pub fn boot_services() -> Option<&'static BootServices> {
+    if !boot_services_active() {
+        return None;
+    }
+
+    system_table().and_then(|system_table| unsafe {
+        system_table.boot_services.as_ref()
+    })
+}
+
+pub fn runtime_services() -> Option<&'static RuntimeServices> {
+    system_table().and_then(|system_table| unsafe {
+        system_table.runtime_services.as_ref()
+    })
+}
+
+/// Firmware console output, if boot services are still available.
+pub fn con_out() -> Option<&'static SimpleTextOutput> {
+    if !boot_services_active() {
+        return None;
+    }
+
+    system_table().and_then(|system_table| unsafe {
+        system_table.con_out.as_ref()
+    })
+}
+
+/// Firmware console input, if boot services are still available.
+pub fn con_in() -> Option<&'static SimpleTextInput> {
+    if !boot_services_active() {
+        return None;
+    }
+
+    system_table().and_then(|system_table| unsafe {
+        system_table.con_in.as_ref()
+    })
+}
+
+/// Busy wait for the given number of microseconds.
+pub fn stall(microseconds: usize) {
+    if let Some(boot_services) = boot_services() {
+        (boot_services.stall)(microseconds);
+    }
+}
+
+/// Stop the watchdog timer armed by the boot manager, which would
+/// otherwise reset the system while waiting for input.
+pub fn disable_watchdog() {
+    if let Some(boot_services) = boot_services() {
+        (boot_services.set_watchdog_timer)(0, 0, 0, core::ptr::null());
+    }
+}
+
+/// Load an EFI image from `source`, identified by `device_path` if given.
+pub fn load_image(device_path: Option<&DevicePath>, source: &[u8]) -> Result<Handle, Status> {
+    let boot_services = boot_services().ok_or(status::UNSUPPORTED)?;
+
+    let mut handle = core::ptr::null_mut();
+    status::to_result((boot_services.load_image)(
+        false,
+        image_handle(),
+        device_path.map_or(core::ptr::null(), DevicePath::as_ptr),
+        source.as_ptr() as *const core::ffi::c_void,
+        source.len(),
+        &mut handle,
+    ))?;
+
+    Ok(handle)
+}
+
+/// Run a loaded image until it exits, returning its exit status.
+/// Applications are unloaded by the firmware once they exit.
+pub fn start_image(handle: Handle) -> Status {
+    match boot_services() {
+        Some(boot_services) => (boot_services.start_image)(
+            handle,
+            core::ptr::null_mut(),
+            core::ptr::null_mut(),
+        ),
+        None => status::UNSUPPORTED,
+    }
+}
+
+/// Unload an image that was loaded but not started.
+pub fn unload_image(handle: Handle) {
+    if let Some(boot_services) = boot_services() {
+        (boot_services.unload_image)(handle);
+    }
+}
+
+/// Cold reset through the runtime services. Only returns if they are
+/// unavailable.
+pub fn reset_system(status: Status) {
+    if let Some(runtime_services) = runtime_services() {
+        (runtime_services.reset_system)(
+            runtime_services::RESET_COLD,
+            status,
+            0,
+            core::ptr::null(),
+        );
+    }
+}
+
+/// Look up a vendor table in the EFI configuration table.
+pub fn configuration_table(guid: &Guid) -> Option<*const core::ffi::c_void> {
+    let system_table = system_table()?;
+    if system_table.configuration_table.is_null() {
+        return None;
+    }
+
+    let tables = unsafe {
+        core::slice::from_raw_parts(
+            system_table.configuration_table,
+            system_table.number_of_table_entries,
+        )
+    };
+
+    tables.iter()
+        .find(|table| table.vendor_guid == *guid)
+        .map(|table| table.vendor_table)
+}
+
+pub fn handle_protocol<T>(handle: Handle, guid: &Guid) -> Result<&'static mut T, Status> {
+    let boot_services = boot_services().ok_or(status::UNSUPPORTED)?;
+
+    let mut interface = core::ptr::null_mut();
+    status::to_result((boot_services.handle_protocol)(handle, guid, &mut interface))?;
+
+    unsafe { (interface as *mut T).as_mut() }.ok_or(status::NOT_FOUND)
+}
+
+pub fn locate_protocol<T>(guid: &Guid) -> Result<&'static mut T, Status> {
+    let boot_services = boot_services().ok_or(status::UNSUPPORTED)?;
+
+    let mut interface = core::ptr::null_mut();
+    status::to_result((boot_services.locate_protocol)(guid, core::ptr::null(), &mut interface))?;
+
+    unsafe { (interface as *mut T).as_mut() }.ok_or(status::NOT_FOUND)
+}
+
+pub fn loaded_image() -> Result<&'static LoadedImage, Status> {
+    handle_protocol::<LoadedImage>(image_handle(), &loaded_image::LOADED_IMAGE_PROTOCOL_GUID)
+        .map(|loaded_image| &*loaded_image)
+}
+
+/// Allocate zeroed pages of the given memory type anywhere in memory.
+pub fn allocate_pages(
+    memory_type: MemoryType,
+    page_count: usize,
+) -> Result<&'static mut [u8], Status> {
+    let boot_services = boot_services().ok_or(status::UNSUPPORTED)?;
+
+    let mut address = 0u64;
+    status::to_result((boot_services.allocate_pages)(
+        boot_services::ALLOCATE_ANY_PAGES,
+        memory_type,
+        page_count,
+        &mut address,
+    ))?;
+
+    let buffer = unsafe {
+        core::slice::from_raw_parts_mut(address as *mut u8, page_count * memory::PAGE_SIZE)
+    };
+    buffer.fill(0u8);
+
+    Ok(buffer)
+}
+
+/// Allocate zeroed pages ending at or below `max_address`.
+pub fn allocate_pages_below(
+    memory_type: MemoryType,
+    max_address: u64,
+    page_count: usize,
+) -> Result<&'static mut [u8], Status> {
+    let boot_services = boot_services().ok_or(status::UNSUPPORTED)?;
+
+    let mut address = max_address;
+    status::to_result((boot_services.allocate_pages)(
+        boot_services::ALLOCATE_MAX_ADDRESS,
+        memory_type,
+        page_count,
+        &mut address,
+    ))?;
+
+    let buffer = unsafe {
+        core::slice::from_raw_parts_mut(address as *mut u8, page_count * memory::PAGE_SIZE)
+    };
+    buffer.fill(0u8);
+
+    Ok(buffer)
+}
+
+/// Allocate zeroed pages at a fixed physical address.
+pub fn allocate_pages_at(
+    memory_type: MemoryType,
+    address: u64,
+    page_count: usize,
+) -> Result<&'static mut [u8], Status> {
+    let boot_services = boot_services().ok_or(status::UNSUPPORTED)?;
+
+    let mut address = address;
+    status::to_result((boot_services.allocate_pages)(
+        boot_services::ALLOCATE_ADDRESS,
+        memory_type,
+        page_count,
+        &mut address,
+    ))?;
+
+    let buffer = unsafe {
+        core::slice::from_raw_parts_mut(address as *mut u8, page_count * memory::PAGE_SIZE)
+    };
+    buffer.fill(0u8);
+
+    Ok(buffer)
+}
+
+/// Allocate zeroed pages starting `offset` bytes past a multiple of
+/// `alignment`, a power of two of at least the page size.
+pub fn allocate_pages_aligned(
+    memory_type: MemoryType,
+    page_count: usize,
+    alignment: usize,
+    offset: usize,
+) -> Result<&'static mut [u8], Status> {
+    if alignment <= memory::PAGE_SIZE && offset % memory::PAGE_SIZE == 0 {
+        return allocate_pages(memory_type, page_count);
+    }
+
+    // over-allocate and give back what is not needed on either side
+    let slack = alignment / memory::PAGE_SIZE - 1;
+    let buffer = allocate_pages(memory_type, page_count + slack)?;
+
+    let address = buffer.as_ptr() as usize;
+    let head = offset.wrapping_sub(address) & (alignment - 1);
+    let (head, rest) = buffer.split_at_mut(head);
+    let (pages, tail) = rest.split_at_mut(page_count * memory::PAGE_SIZE);
+
+    if !head.is_empty() {
+        free_pages(head);
+    }
+    if !tail.is_empty() {
+        free_pages(tail);
+    }
+
+    Ok(pages)
+}
+
+pub fn free_pages(buffer: &'static mut [u8]) {
+    if let Some(boot_services) = boot_services() {
+        (boot_services.free_pages)(
+            buffer.as_ptr() as u64,
+            memory::page_count(buffer.len()),
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use super::{Guid, Status};
+
+pub const RISCV_EFI_BOOT_PROTOCOL_GUID: Guid = Guid(
+    0xccd15fec, 0x6f73, 0x4eec,
+    [0x83, 0x95, 0x3e, 0x69, 0xe4, 0xb9, 0x40, 0xbf],
+);
+
+#[repr(C)]
+pub struct RiscvBoot {
+    pub revision: u64,
+    get_boot_hartid: extern "efiapi" fn(*mut RiscvBoot, *mut usize) -> Status,
+}
+
+impl RiscvBoot {
+    pub fn locate() -> Result<&'static mut RiscvBoot, Status> {
+        super::locate_protocol::<RiscvBoot>(&RISCV_EFI_BOOT_PROTOCOL_GUID)
+    }
+
+    /// Id of the hart Maia runs on.
+    pub fn boot_hart_id(&mut self) -> Result<usize, Status> {
+        let mut hart_id = 0;
+        super::status::to_result((self.get_boot_hartid)(self, &mut hart_id))?;
+
+        Ok(hart_id)
+    }
+}
@@ -0,0 +1,19 @@
+use super::{Status, TableHeader};
+
+pub const RESET_COLD: u32 = 0;
+
+#[repr(C)]
+pub struct RuntimeServices {
+    pub header: TableHeader,
+    get_time: usize,
+    set_time: usize,
+    get_wakeup_time: usize,
+    set_wakeup_time: usize,
+    set_virtual_address_map: usize,
+    convert_pointer: usize,
+    get_variable: usize,
+    get_next_variable_name: usize,
+    set_variable: usize,
+    get_next_high_monotonic_count: usize,
+    pub reset_system: extern "efiapi" fn(u32, Status, usize, *const core::ffi::c_void) -> !,
+}
@@ -0,0 +1,32 @@
+use super::Status;
+
+const ERROR_BIT: usize = 1 << (core::mem::size_of::<usize>() * 8 - 1);
+
+pub const SUCCESS: Status = 0;
+
+pub const WARN_DELETE_FAILURE: Status = 2;
+
+pub const LOAD_ERROR: Status = ERROR_BIT | 1;
+pub const INVALID_PARAMETER: Status = ERROR_BIT | 2;
+pub const UNSUPPORTED: Status = ERROR_BIT | 3;
+pub const BUFFER_TOO_SMALL: Status = ERROR_BIT | 5;
+pub const NOT_READY: Status = ERROR_BIT | 6;
+pub const DEVICE_ERROR: Status = ERROR_BIT | 7;
+pub const OUT_OF_RESOURCES: Status = ERROR_BIT | 9;
+pub const VOLUME_FULL: Status = ERROR_BIT | 11;
+pub const NOT_FOUND: Status = ERROR_BIT | 14;
+pub const ABORTED: Status = ERROR_BIT | 21;
+pub const END_OF_FILE: Status = ERROR_BIT | 31;
+
+pub fn is_error(status: Status) -> bool {
+    status & ERROR_BIT != 0
+}
+
+/// Treat warnings as success.
+pub fn to_result(status: Status) -> Result<(), Status> {
+    if is_error(status) {
+        Err(status)
+    } else {
+        Ok(())
+    }
+}
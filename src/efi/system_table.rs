@@ -0,0 +1,29 @@
+use super::{
+    BootServices, ConfigurationTable, Handle, RuntimeServices, SimpleTextInput, SimpleTextOutput,
+};
+
+#[repr(C)]
+pub struct TableHeader {
+    pub signature: u64,
+    pub revision: u32,
+    pub header_size: u32,
+    pub crc32: u32,
+    _reserved: u32,
+}
+
+#[repr(C)]
+pub struct SystemTable {
+    pub header: TableHeader,
+    pub firmware_vendor: *const u16,
+    pub firmware_revision: u32,
+    pub console_in_handle: Handle,
+    pub con_in: *mut SimpleTextInput,
+    pub console_out_handle: Handle,
+    pub con_out: *mut SimpleTextOutput,
+    pub standard_error_handle: Handle,
+    pub std_err: *mut SimpleTextOutput,
+    pub runtime_services: *mut RuntimeServices,
+    pub boot_services: *mut BootServices,
+    pub number_of_table_entries: usize,
+    pub configuration_table: *const ConfigurationTable,
+}
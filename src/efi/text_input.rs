@@ -0,0 +1,40 @@
+use super::{boot_services::Event, Status};
+
+pub const SCAN_UP: u16 = 0x01;
+pub const SCAN_DOWN: u16 = 0x02;
+
+pub const CHAR_CARRIAGE_RETURN: u16 = 0x0d;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct InputKey {
+    pub scan_code: u16,
+    pub unicode_char: u16,
+}
+
+#[repr(C)]
+pub struct SimpleTextInput {
+    reset: extern "efiapi" fn(*mut SimpleTextInput, bool) -> Status,
+    read_key_stroke: extern "efiapi" fn(*mut SimpleTextInput, *mut InputKey) -> Status,
+    wait_for_key: Event,
+}
+
+impl SimpleTextInput {
+    /// Discard pending key strokes.
+    pub fn reset(&self) {
+        (self.reset)(self.as_ptr(), false);
+    }
+
+    /// Next pending key stroke, `NOT_READY` if there is none.
+    pub fn read_key(&self) -> Result<InputKey, Status> {
+        let mut key = InputKey::default();
+        super::status::to_result((self.read_key_stroke)(self.as_ptr(), &mut key))?;
+
+        Ok(key)
+    }
+
+    /// See `SimpleTextOutput::as_ptr`.
+    fn as_ptr(&self) -> *mut SimpleTextInput {
+        self as *const SimpleTextInput as *mut SimpleTextInput
+    }
+}
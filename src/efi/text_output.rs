@@ -0,0 +1,57 @@
+use super::Status;
+
+#[repr(C)]
+pub struct SimpleTextOutput {
+    reset: extern "efiapi" fn(*mut SimpleTextOutput, bool) -> Status,
+    output_string: extern "efiapi" fn(*mut SimpleTextOutput, *const u16) -> Status,
+    test_string: extern "efiapi" fn(*mut SimpleTextOutput, *const u16) -> Status,
+    query_mode: usize,
+    set_mode: usize,
+    set_attribute: extern "efiapi" fn(*mut SimpleTextOutput, usize) -> Status,
+    clear_screen: extern "efiapi" fn(*mut SimpleTextOutput) -> Status,
+    set_cursor_position: usize,
+    enable_cursor: usize,
+    mode: *mut core::ffi::c_void,
+}
+
+impl SimpleTextOutput {
+    pub fn clear_screen(&self) {
+        (self.clear_screen)(self.as_ptr());
+    }
+
+    /// Write a string, converting it to UCS-2 in fixed size chunks.
+    ///
+    /// Characters outside the Basic Multilingual Plane are replaced by '?'.
+    pub fn write_str(&self, s: &str) {
+        const CHUNK_SIZE: usize = 64;
+
+        let mut buffer = [0u16; CHUNK_SIZE + 1];
+        let mut length = 0;
+
+        for c in s.chars() {
+            let c = c as u32;
+            buffer[length] = if c < 0x10000 { c as u16 } else { '?' as u16 };
+            length += 1;
+
+            if length == CHUNK_SIZE {
+                self.output(&mut buffer, length);
+                length = 0;
+            }
+        }
+
+        if length > 0 {
+            self.output(&mut buffer, length);
+        }
+    }
+
+    fn output(&self, buffer: &mut [u16], length: usize) {
+        buffer[length] = 0;
+        (self.output_string)(self.as_ptr(), &buffer[0]);
+    }
+
+    /// The firmware takes the protocol as a mutable pointer, but keeps
+    /// any state of its own behind it.
+    fn as_ptr(&self) -> *mut SimpleTextOutput {
+        self as *const SimpleTextOutput as *mut SimpleTextOutput
+    }
+}
@@ -66,6 +66,15 @@ pub struct RelocationTable {
 }
 
 impl RelocationTable {
+    /// Whether the table lies entirely within `buffer`.
+    pub fn is_within(&self, buffer: &[u8]) -> bool {
+        let start = buffer.as_ptr() as usize;
+        let address = self.address as usize;
+
+        address >= start
+            && matches!(address.checked_add(self.size), Some(end) if end <= start + buffer.len())
+    }
+
     #[inline(always)]
     pub fn fold_inner<'a, B, F>(&'a self, init: B, mut f: F) -> B
     where
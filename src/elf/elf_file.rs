@@ -1,16 +1,27 @@
 use super::{
-    header, Dynamic, ElfError, Header, RelocationTable,
-    program_header::{ProgramHeader, SegmentType},
+    header, ElfError, Header,
+    program_header::ProgramHeader,
     util::raw_cast,
 };
 
 pub struct ElfFile<'a> {
     raw_buffer: &'a [u8],
+    /// Size of the whole file, of which `raw_buffer` may only be the start.
+    file_size: usize,
 }
 
 impl <'a> ElfFile<'a> {
     /// Unsafe: Appropriate memory alignment must be ensured by caller
     pub unsafe fn from_buffer(buffer: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        ElfFile::from_headers(buffer, buffer.len())
+    }
+
+    /// Use the start of a file of `file_size` bytes, containing at least the
+    /// file header and program headers (see `headers_size`). Segment data
+    /// is not available through `data`, but must be read separately.
+    ///
+    /// Unsafe: Appropriate memory alignment must be ensured by caller
+    pub unsafe fn from_headers(buffer: &'a [u8], file_size: usize) -> Result<ElfFile<'a>, ElfError> {
         if let Some(header) = raw_cast::<Header>(&buffer[..]) {
             if header.valid_magic() != true {
                 return Err(ElfError::InvalidFormat);
@@ -24,12 +35,36 @@ impl <'a> ElfFile<'a> {
                 return Err(ElfError::IncompatibleMachine);
             }
 
-            Ok(ElfFile { raw_buffer: buffer })
+            // the program headers must lie within the file as well
+            let table_info = header.get_program_header_info();
+            match table_end(&table_info) {
+                Some(end) if end <= file_size => (),
+                _ => return Err(ElfError::BufferOverflow),
+            }
+
+            let elf_file = ElfFile { raw_buffer: buffer, file_size };
+            elf_file.program_headers()?;
+
+            Ok(elf_file)
         } else {
             Err(ElfError::InvalidFormat)
         }
     }
 
+    /// Size of the file header and program headers, given at least the
+    /// file header.
+    pub fn headers_size(buffer: &[u8]) -> Result<usize, ElfError> {
+        let header = unsafe { raw_cast::<Header>(buffer) }.ok_or(ElfError::InvalidFormat)?;
+        if !header.valid_magic() {
+            return Err(ElfError::InvalidFormat);
+        }
+
+        let table_end = table_end(&header.get_program_header_info())
+            .ok_or(ElfError::InvalidFormat)?;
+
+        Ok(table_end.max(core::mem::size_of::<Header>()))
+    }
+
     pub fn header(&self) -> &Header {
         // `from_buffer` already checked that we have a valid Header,
         // so this should never fail.
@@ -43,65 +78,31 @@ impl <'a> ElfFile<'a> {
         )
     }
 
-    pub fn copy_segment_pages(
-        &self,
-        program_header: &ProgramHeader,
-        target: &mut [u8],
-    ) -> Result<(), ElfError> {
-        let start = program_header.get_file_base();
-        let size = program_header.get_page_count() * 4096;
-
-        if target.len() < size {
-            return Err(ElfError::BufferOverflow);
+    /// `size` bytes of the file starting at `offset`.
+    pub fn data(&self, offset: usize, size: usize) -> Result<&'a [u8], ElfError> {
+        match offset.checked_add(size) {
+            Some(end) if end <= self.raw_buffer.len() => Ok(&self.raw_buffer[offset..end]),
+            _ => Err(ElfError::BufferOverflow),
         }
-
-        if start + size <= self.raw_buffer.len() {
-            target.copy_from_slice(&self.raw_buffer[start..(start + size)]);
-        } else {
-            let (target, padding) = target.split_at_mut(self.raw_buffer.len() - start);
-            target.copy_from_slice(&self.raw_buffer[start..self.raw_buffer.len()]);
-            padding.fill(0u8);
-        }
-
-        Ok(())
     }
 
-    pub fn segment_data(
-        &self,
-        program_header: &ProgramHeader,
-    ) -> Result<&'a [u8], ElfError> {
-        let start = program_header.get_offset();
-        let end = start + program_header.get_file_size();
-
-        if end <= self.raw_buffer.len() {
-            Ok(&self.raw_buffer[start..end])
-        } else {
-            Err(ElfError::BufferOverflow)
-        }
+    /// Size of the whole file.
+    pub fn file_size(&self) -> usize {
+        self.file_size
     }
 
-    // FIXME: Technically this breaks lifetime guarantees with the returned
-    // RelocationTable, since it should be constrained to the lifetime of
-    // the `raw_buffer` of this ElfFile.
-    pub fn relocation_table(&self) -> Result<Option<RelocationTable>, ElfError> {
-        for program_header in self.program_headers()? {
-            if program_header.get_type() == Some(SegmentType::Dynamic) {
-                // FIXME: Constrain dynamic tag array size to dynamic segment buffer size
-                let relocation_table = unsafe {
-                    Dynamic::find_relocations_inner(
-                        &self.raw_buffer[0] as *const u8 as *const core::ffi::c_void,
-                        &self.segment_data(program_header)?[0] as *const u8 as *const Dynamic,
-                    )?
-                };
-
-                return Ok(relocation_table);
-            }
-        }
-
-        Ok(None)
+    /// Whether the file extends over `size` bytes starting at `offset`.
+    pub fn contains(&self, offset: usize, size: usize) -> bool {
+        matches!(offset.checked_add(size), Some(end) if end <= self.file_size)
     }
 }
 
+/// End of the program header table, unless it overflows.
+fn table_end(table_info: &header::TableInfo) -> Option<usize> {
+    table_info.entry_count.checked_mul(table_info.entry_size)
+        .and_then(|size| size.checked_add(table_info.offset))
+}
+
 pub struct ProgramHeaderIterator<'a> {
     table_info: header::TableInfo,
     raw_buffer: &'a [u8],
@@ -119,9 +120,9 @@ impl<'a> ProgramHeaderIterator<'a> {
         }
 
         // check buffer size
-        let table_end = table_info.offset + table_info.entry_count * table_info.entry_size;
-        if table_end > buffer.len() {
-            return Err(ElfError::InvalidFormat);
+        match table_end(&table_info) {
+            Some(end) if end <= buffer.len() => (),
+            _ => return Err(ElfError::BufferOverflow),
         }
 
         Ok(ProgramHeaderIterator {
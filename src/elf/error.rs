@@ -2,4 +2,27 @@ pub enum ElfError {
     InvalidFormat,
     IncompatibleMachine,
     BufferOverflow,
+    OverlappingSegments,
+    TooManySegments,
+}
+
+impl core::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ElfError::InvalidFormat =>
+                    "invalid ELF format",
+                ElfError::IncompatibleMachine =>
+                    "incompatible machine type",
+                ElfError::BufferOverflow =>
+                    "data out of bounds",
+                ElfError::OverlappingSegments =>
+                    "overlapping segments",
+                ElfError::TooManySegments =>
+                    "too many segments",
+            }
+        )
+    }
 }
@@ -1,21 +1,23 @@
-static MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+pub static MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+
+const ET_DYN: u16 = 3;
 
 #[repr(packed)]
 pub struct Header {
     identity: IdentityHeader,
-    _type: u16,
+    r#type: u16,
     machine: u16,
     _version: u32,
     entry: u64,
     phoff: u64,
-    _shoff: u64,
+    shoff: u64,
     _flags: u32,
     _ehsize: u16,
     phentsize: u16,
     phnum: u16,
-    _shentsize: u16,
-    _shnum: u16,
-    _shstrndx: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
 }
 
 #[repr(packed)]
@@ -41,6 +43,11 @@ impl Header {
         core::convert::TryInto::<ElfMachine>::try_into(self.machine).ok()
     }
 
+    /// Position independent (`ET_DYN`) images can be loaded anywhere.
+    pub fn is_relocatable(&self) -> bool {
+        self.r#type == ET_DYN
+    }
+
     pub fn get_entry_point(&self) -> usize {
         self.entry as usize
     }
@@ -52,6 +59,19 @@ impl Header {
             entry_count: self.phnum as usize,
         }
     }
+
+    pub fn get_section_header_info(&self) -> TableInfo {
+        TableInfo {
+            offset: self.shoff as usize,
+            entry_size: self.shentsize as usize,
+            entry_count: self.shnum as usize,
+        }
+    }
+
+    /// Index of the section holding the section names.
+    pub fn get_section_names_index(&self) -> usize {
+        self.shstrndx as usize
+    }
 }
 
 #[derive(PartialEq)]
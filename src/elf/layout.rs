@@ -0,0 +1,222 @@
+use super::{Dynamic, ElfError, ElfFile, RelocationTable, SegmentType};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// Upper limit of loadable segments in an image.
+const MAX_SEGMENTS: usize = 16;
+
+/// Largest segment alignment honored.
+const MAX_ALIGNMENT: usize = 1 << 30;
+
+/// Which segment addresses determine the placement of the image.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AddressKind {
+    /// `p_vaddr`, for images that are relocated to wherever they are loaded.
+    Virtual,
+    /// `p_paddr`, for images that must be loaded at a fixed physical address.
+    Physical,
+}
+
+/// Where a loadable segment goes within the loaded image.
+#[derive(Clone, Copy, Default)]
+pub struct SegmentPlacement {
+    pub virtual_address: usize,
+    pub physical_address: usize,
+    /// Offset of the segment from the start of the image.
+    pub image_offset: usize,
+    pub file_offset: usize,
+    pub file_size: usize,
+    pub memory_size: usize,
+    pub executable: bool,
+}
+
+impl SegmentPlacement {
+    fn address(&self, kind: AddressKind) -> usize {
+        match kind {
+            AddressKind::Virtual => self.virtual_address,
+            AddressKind::Physical => self.physical_address,
+        }
+    }
+}
+
+/// Memory layout of all loadable segments, regardless of the order they
+/// are listed in. The image spans whole pages from the lowest segment page
+/// to the end of the highest one, including any gaps in between.
+pub struct Layout {
+    pub address_kind: AddressKind,
+    /// Page aligned address of the start of the image, virtual or physical
+    /// as given by `address_kind`.
+    pub base: usize,
+    pub page_count: usize,
+    /// Largest alignment requested by a segment, at least a page. The
+    /// image must be placed at an address congruent to `base` modulo this
+    /// alignment.
+    pub alignment: usize,
+    /// Virtual address of the dynamic section, if any.
+    dynamic: Option<usize>,
+    segments: [SegmentPlacement; MAX_SEGMENTS],
+    segment_count: usize,
+}
+
+impl Layout {
+    /// Plan the layout of the PT_LOAD segments of `elf_file`, by the
+    /// addresses of the given kind.
+    ///
+    /// Segments may share pages, but must not overlap in memory.
+    pub fn plan(elf_file: &ElfFile, address_kind: AddressKind) -> Result<Layout, ElfError> {
+        let mut layout = Layout {
+            address_kind,
+            base: 0,
+            page_count: 0,
+            alignment: PAGE_SIZE,
+            dynamic: None,
+            segments: [SegmentPlacement::default(); MAX_SEGMENTS],
+            segment_count: 0,
+        };
+
+        let mut start = usize::MAX;
+        let mut end = 0;
+        for program_header in elf_file.program_headers()? {
+            if program_header.get_type() == Some(SegmentType::Dynamic) {
+                layout.dynamic = Some(program_header.get_virtual_address());
+            }
+
+            if program_header.get_type() != Some(SegmentType::Load)
+                || program_header.get_memory_size() == 0
+            {
+                continue;
+            }
+
+            let segment = SegmentPlacement {
+                virtual_address: program_header.get_virtual_address(),
+                physical_address: program_header.get_physical_address(),
+                image_offset: 0,
+                file_offset: program_header.get_offset(),
+                file_size: program_header.get_file_size(),
+                memory_size: program_header.get_memory_size(),
+                executable: program_header.is_executable(),
+            };
+
+            if segment.file_size > segment.memory_size {
+                return Err(ElfError::InvalidFormat);
+            }
+
+            // zero and one both mean no alignment constraint
+            let alignment = program_header.get_alignment();
+            if alignment > 1 {
+                if !alignment.is_power_of_two() || alignment > MAX_ALIGNMENT {
+                    return Err(ElfError::InvalidFormat);
+                }
+                layout.alignment = layout.alignment.max(alignment);
+            }
+
+            // the file data must be present
+            if !elf_file.contains(segment.file_offset, segment.file_size) {
+                return Err(ElfError::BufferOverflow);
+            }
+
+            let address = segment.address(address_kind);
+            let segment_end = address.checked_add(segment.memory_size)
+                .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+                .ok_or(ElfError::InvalidFormat)?
+                & !(PAGE_SIZE - 1);
+
+            let overlaps = layout.segments().iter().any(|other| {
+                let other_address = other.address(address_kind);
+                address < other_address + other.memory_size
+                    && other_address < address + segment.memory_size
+            });
+            if overlaps {
+                return Err(ElfError::OverlappingSegments);
+            }
+
+            if layout.segment_count == MAX_SEGMENTS {
+                return Err(ElfError::TooManySegments);
+            }
+            layout.segments[layout.segment_count] = segment;
+            layout.segment_count += 1;
+
+            start = start.min(address & !(PAGE_SIZE - 1));
+            end = end.max(segment_end);
+        }
+
+        if layout.segment_count == 0 {
+            return Err(ElfError::InvalidFormat);
+        }
+
+        layout.base = start;
+        layout.page_count = (end - start) / PAGE_SIZE;
+        for segment in layout.segments[..layout.segment_count].iter_mut() {
+            segment.image_offset = segment.address(address_kind) - start;
+        }
+
+        Ok(layout)
+    }
+
+    pub fn segments(&self) -> &[SegmentPlacement] {
+        &self.segments[..self.segment_count]
+    }
+
+    pub fn size(&self) -> usize {
+        self.page_count * PAGE_SIZE
+    }
+
+    /// Offset within the image of the byte at `virtual_address`, if it is
+    /// part of a segment.
+    pub fn image_offset(&self, virtual_address: usize) -> Option<usize> {
+        self.segments()
+            .iter()
+            .find(|segment| {
+                virtual_address >= segment.virtual_address
+                    && virtual_address - segment.virtual_address < segment.memory_size
+            })
+            .map(|segment| segment.image_offset + (virtual_address - segment.virtual_address))
+    }
+
+    /// Fill `image` with the segments, their file data read into place by
+    /// `read(file_offset, destination)`. Everything not backed by file data,
+    /// including gaps between segments, is zeroed.
+    pub fn load_with<E, F>(&self, image: &mut [u8], mut read: F) -> Result<(), E>
+    where
+        E: From<ElfError>,
+        F: FnMut(usize, &mut [u8]) -> Result<(), E>,
+    {
+        if image.len() < self.size() {
+            return Err(ElfError::BufferOverflow.into());
+        }
+
+        image.fill(0u8);
+        for segment in self.segments() {
+            read(
+                segment.file_offset,
+                &mut image[segment.image_offset..(segment.image_offset + segment.file_size)],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Relocations of the image loaded into `image`, found through its
+    /// dynamic section.
+    pub fn relocation_table(&self, image: &[u8]) -> Result<Option<RelocationTable>, ElfError> {
+        let dynamic = match self.dynamic {
+            Some(dynamic) => dynamic,
+            None => return Ok(None),
+        };
+        let offset = self.image_offset(dynamic).ok_or(ElfError::InvalidFormat)?;
+        if offset >= image.len() {
+            return Err(ElfError::BufferOverflow);
+        }
+
+        // addresses in the dynamic section are virtual, relative to `base`
+        let bias = image.as_ptr().wrapping_sub(self.base) as *const core::ffi::c_void;
+        let relocation_table = unsafe {
+            Dynamic::find_relocations_inner(bias, image[offset..].as_ptr() as *const Dynamic)?
+        };
+
+        match relocation_table {
+            Some(table) if !table.is_within(image) => Err(ElfError::BufferOverflow),
+            relocation_table => Ok(relocation_table),
+        }
+    }
+}
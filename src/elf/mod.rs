@@ -3,7 +3,9 @@ pub mod dynamic;
 mod elf_file;
 mod error;
 mod header;
+mod layout;
 mod program_header;
+mod section_header;
 mod util;
 
 use header::Header;
@@ -12,6 +14,8 @@ pub use self::{
     dynamic::{Dynamic, RelocationTable},
     elf_file::ElfFile,
     error::ElfError,
-    header::{ElfClass, ElfMachine},
+    header::{ElfClass, ElfMachine, MAGIC},
+    layout::{AddressKind, Layout},
     program_header::{ProgramHeader, SegmentType},
+    section_header::SectionHeader,
 };
@@ -1,10 +1,13 @@
+/// Segment is executable.
+pub const PF_X: u32 = 0x1;
+
 #[repr(packed)]
 pub struct ProgramHeader {
     r#type: u32,
-    _flags: u32,
+    flags: u32,
     offset: u64,
     vaddr: u64,
-    _paddr: u64,
+    paddr: u64,
     filesz: u64,
     memsz: u64,
     align: u64,
@@ -15,6 +18,14 @@ impl ProgramHeader {
         core::convert::TryInto::<SegmentType>::try_into(self.r#type).ok()
     }
 
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.get_flags() & PF_X != 0
+    }
+
     pub fn get_offset(&self) -> usize {
         self.offset as usize
     }
@@ -23,6 +34,10 @@ impl ProgramHeader {
         self.vaddr as usize
     }
 
+    pub fn get_physical_address(&self) -> usize {
+        self.paddr as usize
+    }
+
     pub fn get_file_size(&self) -> usize {
         self.filesz as usize
     }
@@ -34,35 +49,6 @@ impl ProgramHeader {
     pub fn get_alignment(&self) -> usize {
         self.align as usize
     }
-
-    pub fn get_page_base(&self) -> usize {
-        self.get_virtual_address() & !(self.get_alignment() - 1)
-    }
-
-    pub fn get_file_base(&self) -> usize {
-        self.get_offset() & !(self.get_alignment() - 1)
-    }
-
-    pub fn get_page_count(&self) -> usize {
-        let size = self.get_memory_size();
-        let mut page_count = size / 4096;
-
-        // round up
-        if size & 0xFFF > 0 {
-            page_count += 1;
-        }
-
-        page_count
-    }
-
-    pub fn address_in_segment(&self, virtual_address: usize) -> bool {
-        if virtual_address >= self.get_virtual_address() {
-            if virtual_address < self.get_virtual_address() + self.get_memory_size() {
-                return true;
-            }
-        }
-        return false;
-    }
 }
 
 #[derive(PartialEq)]
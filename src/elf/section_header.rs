@@ -0,0 +1,57 @@
+use super::util::raw_cast_mut;
+
+/// Section occupies memory during execution.
+const SHF_ALLOC: u64 = 0x2;
+/// Section occupies no space in the file, like `.bss`.
+const SHT_NOBITS: u32 = 8;
+
+#[repr(packed)]
+pub struct SectionHeader {
+    _name: u32,
+    r#type: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    _link: u32,
+    _info: u32,
+    _addralign: u64,
+    _entsize: u64,
+}
+
+impl SectionHeader {
+    pub const SIZE: usize = core::mem::size_of::<SectionHeader>();
+
+    /// The section header at the start of an entry of the section header
+    /// table, to be modified in place.
+    pub fn from_entry_mut(entry: &mut [u8]) -> Option<&mut SectionHeader> {
+        // packed, so any alignment will do
+        unsafe { raw_cast_mut::<SectionHeader>(entry) }
+    }
+
+    /// Sections part of the loaded segments.
+    pub fn is_allocated(&self) -> bool {
+        self.flags & SHF_ALLOC != 0
+    }
+
+    /// Sections with contents in the file.
+    pub fn has_file_data(&self) -> bool {
+        self.r#type != SHT_NOBITS && self.size > 0
+    }
+
+    pub fn get_address(&self) -> usize {
+        self.addr as usize
+    }
+
+    pub fn set_address(&mut self, address: u64) {
+        self.addr = address;
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset as usize
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size as usize
+    }
+}
@@ -12,3 +12,17 @@ where
 
     Some(& *raw_ptr)
 }
+
+pub unsafe fn raw_cast_mut<'a, T>(buffer: &'a mut [u8]) -> Option<&'a mut T>
+where
+    T: Sized
+{
+    let target_size = core::mem::size_of::<T>();
+    if target_size > buffer.len() {
+        return None;
+    }
+
+    let raw_ptr = buffer as *mut [u8] as *mut T;
+
+    Some(&mut *raw_ptr)
+}
@@ -0,0 +1,170 @@
+//! Kernel entry.
+//!
+//! The kernel is entered through the trampoline in
+//! `arch/riscv/riscv64-entry.S`, with the machine state described by entry
+//! ABI version 2:
+//!
+//!  - supervisor mode, `satp` as left by firmware (usually bare)
+//!  - `sstatus.SIE` clear, `sie` and `sip` zero
+//!  - `stvec` pointing to a Maia handler that reports the trap on the serial
+//!    console and halts; the kernel is expected to install its own
+//!  - `a0`: device tree blob, or zero on ACPI-only platforms
+//!  - `a1`: UEFI memory map, a `mercuros_uefi::MemoryMap`
+//!  - `a2`: boot information, see `boot_info::BootInfo`
+//!  - `a3`: `multiboot2::BOOTLOADER_MAGIC` for kernels with a Multiboot2
+//!    header, zero otherwise
+//!  - `a4`: Multiboot2 information structure, or zero
+//!  - `sp`: end of the kernel stack, 16 byte aligned
+//!  - `tp`: boot hart id, or all ones if unknown
+//!  - `t0`: the entry point itself
+//!  - `ra`, `gp` and all other general purpose registers zero
+//!  - instruction cache synchronized with the loaded kernel (`fence.i`)
+//!    and address translation caches flushed (`sfence.vma`)
+//!
+//! The version is reported in the `entry_abi` field of the boot information.
+//! Version 2 added the Multiboot2 arguments in `a3` and `a4`.
+//!
+//! Linux Images are entered the same way, except for the arguments: `a0`
+//! holds the boot hart id and `a1` the device tree, as Linux expects.
+//!
+//! Limine kernels are entered as the Limine protocol specifies instead,
+//! through a separate trampoline that switches to the kernel's page tables:
+//! `sp` points into the higher half direct map and all other general purpose
+//! registers but `t0` are zero, see `limine`.
+
+use super::{boot_info::BootInfo, multiboot2};
+
+pub const ABI_VERSION: u32 = 2;
+
+extern "C" {
+    fn maia_enter_kernel(
+        dtb: *const u8,
+        memory_map: *const core::ffi::c_void,
+        boot_info: *const BootInfo,
+        entry_point: *const core::ffi::c_void,
+        stack_top: u64,
+        boot_hart_id: u64,
+        multiboot_magic: u64,
+        multiboot_info: u64,
+    ) -> !;
+
+    fn maia_enter_limine(entry_point: u64, stack_top: u64, satp: u64) -> !;
+    fn maia_limine_ap_entry();
+
+    static maia_limine_text_start: u8;
+    static maia_limine_text_end: u8;
+}
+
+/// Enter the kernel with the Maia entry ABI, passing the Multiboot2
+/// information structure if one was built.
+///
+/// Unsafe: boot services must have been exited, and everything passed
+/// must stay valid once Maia is gone.
+pub unsafe fn enter_kernel(
+    entry_point: *const core::ffi::c_void,
+    dtb: *const u8,
+    memory_map: *const core::ffi::c_void,
+    boot_info: &BootInfo,
+    multiboot_info: Option<u64>,
+) -> ! {
+    let stack_top = boot_info.kernel_stack.address + boot_info.kernel_stack.size;
+    let (multiboot_magic, multiboot_info) = match multiboot_info {
+        Some(address) => (multiboot2::BOOTLOADER_MAGIC as u64, address),
+        None => (0, 0),
+    };
+
+    maia_enter_kernel(
+        dtb,
+        memory_map,
+        boot_info,
+        entry_point,
+        stack_top,
+        boot_info.boot_hart_id,
+        multiboot_magic,
+        multiboot_info,
+    )
+}
+
+/// Enter a Linux Image with the Linux boot ABI.
+///
+/// Unsafe: as for `enter_kernel`; the device tree must be valid.
+pub unsafe fn enter_linux(
+    entry_point: *const core::ffi::c_void,
+    dtb: *const u8,
+    boot_info: &BootInfo,
+) -> ! {
+    let stack_top = boot_info.kernel_stack.address + boot_info.kernel_stack.size;
+
+    maia_enter_kernel(
+        boot_info.boot_hart_id as *const u8,
+        dtb as *const core::ffi::c_void,
+        core::ptr::null(),
+        entry_point,
+        stack_top,
+        boot_info.boot_hart_id,
+        0,
+        0,
+    )
+}
+
+/// Enter a Limine kernel at its virtual `entry_point`, with the stack at
+/// the virtual `stack_top`, after switching to the page tables `satp`
+/// selects.
+///
+/// Unsafe: as for `enter_kernel`; the page tables must map the kernel, the
+/// stack and the trampoline, see `limine_trampoline`.
+pub unsafe fn enter_limine(entry_point: u64, stack_top: u64, satp: u64) -> ! {
+    maia_enter_limine(entry_point, stack_top, satp)
+}
+
+/// Physical range of the code that switches to the page tables of a
+/// Limine kernel, which must be identity mapped in them.
+pub fn limine_trampoline() -> (u64, u64) {
+    unsafe {
+        (
+            &maia_limine_text_start as *const u8 as u64,
+            &maia_limine_text_end as *const u8 as u64,
+        )
+    }
+}
+
+/// Physical address where harts started for Limine kernels begin, waiting
+/// for the kernel to send them on. The hart gets its parking block in `a1`,
+/// see `arch/riscv/riscv64-entry.S`.
+pub fn limine_ap_entry() -> u64 {
+    maia_limine_ap_entry as unsafe extern "C" fn() as usize as u64
+}
+
+/// Called from the trap vector installed by the trampoline, for traps
+/// taken before the kernel sets up its own handling.
+#[no_mangle]
+extern "C" fn maia_kernel_fault(cause: usize, epc: usize, value: usize) {
+    error!(
+        "Kernel trap before trap setup: {} (scause={:#x} sepc={:#018x} stval={:#x})",
+        trap_name(cause), cause, epc, value,
+    );
+}
+
+fn trap_name(cause: usize) -> &'static str {
+    // the most significant bit is set for interrupts
+    if (cause as isize) < 0 {
+        return "Interrupt";
+    }
+
+    match cause {
+        0 => "Instruction address misaligned",
+        1 => "Instruction access fault",
+        2 => "Illegal instruction",
+        3 => "Breakpoint",
+        4 => "Load address misaligned",
+        5 => "Load access fault",
+        6 => "Store/AMO address misaligned",
+        7 => "Store/AMO access fault",
+        8 => "Environment call from U-mode",
+        9 => "Environment call from S-mode",
+        12 => "Instruction page fault",
+        13 => "Load page fault",
+        15 => "Store/AMO page fault",
+        _ => "Unknown exception",
+    }
+}
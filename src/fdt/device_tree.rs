@@ -0,0 +1,237 @@
+use super::{
+    FdtError, Header, Node,
+    header::{COMPATIBLE_VERSION, MAGIC},
+    token::{self, Token},
+};
+
+/// Read-only view of a flattened device tree blob.
+#[derive(Clone, Copy)]
+pub struct DeviceTree<'a> {
+    raw_buffer: &'a [u8],
+}
+
+impl<'a> DeviceTree<'a> {
+    pub fn from_buffer(buffer: &'a [u8]) -> Result<DeviceTree<'a>, FdtError> {
+        if buffer.len() < core::mem::size_of::<Header>() {
+            return Err(FdtError::BufferOverflow);
+        }
+
+        // the header is accessed in place, so it must be 4 byte aligned
+        if buffer.as_ptr() as usize & 0x3 > 0 {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        let device_tree = DeviceTree { raw_buffer: buffer };
+        let header = device_tree.header();
+
+        if !header.valid_magic() {
+            return Err(FdtError::InvalidMagic);
+        }
+
+        if header.get_last_compatible_version() > COMPATIBLE_VERSION {
+            return Err(FdtError::UnsupportedVersion);
+        }
+
+        let total_size = header.get_total_size();
+        if total_size > buffer.len() {
+            return Err(FdtError::BufferOverflow);
+        }
+
+        let struct_end = header.get_struct_offset() + header.get_struct_size();
+        let strings_end = header.get_strings_offset() + header.get_strings_size();
+        if struct_end > total_size || strings_end > total_size {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        // the reservation block holds at least the terminating entry
+        if header.get_memory_reservation_offset() + 16 > total_size {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        Ok(DeviceTree { raw_buffer: &buffer[..total_size] })
+    }
+
+    /// Unsafe: `address` must point to readable memory holding a complete
+    /// device tree blob that stays valid for the rest of the boot process.
+    pub unsafe fn from_address(
+        address: *const u8,
+    ) -> Result<DeviceTree<'static>, FdtError> {
+        if address.is_null() {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        let magic = u32::from_be((address as *const u32).read());
+        if magic != MAGIC {
+            return Err(FdtError::InvalidMagic);
+        }
+
+        let total_size = u32::from_be((address as *const u32).add(1).read()) as usize;
+        DeviceTree::from_buffer(core::slice::from_raw_parts(address, total_size))
+    }
+
+    pub fn header(&self) -> &'a Header {
+        // `from_buffer` checks size and alignment of the header
+        unsafe { &*(self.raw_buffer.as_ptr() as *const Header) }
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw_buffer
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.raw_buffer.len()
+    }
+
+    pub(super) fn structure(&self) -> &'a [u8] {
+        let header = self.header();
+        let start = header.get_struct_offset();
+        &self.raw_buffer[start..(start + header.get_struct_size())]
+    }
+
+    pub(super) fn string(&self, offset: usize) -> Option<&'a str> {
+        let header = self.header();
+        if offset >= header.get_strings_size() {
+            return None;
+        }
+
+        let start = header.get_strings_offset() + offset;
+        let end = header.get_strings_offset() + header.get_strings_size();
+        let bytes = &self.raw_buffer[start..end];
+        let length = bytes.iter().position(|&c| c == 0)?;
+
+        core::str::from_utf8(&bytes[..length]).ok()
+    }
+
+    pub fn root(&self) -> Result<Node<'a>, FdtError> {
+        match token::next_token(self.structure(), 0)? {
+            (Token::BeginNode(_), body_offset) =>
+                Ok(Node::new(*self, 0, "", body_offset, 2, 1)),
+            _ =>
+                Err(FdtError::InvalidFormat),
+        }
+    }
+
+    /// Node at `offset` in the structure block, as returned by `Node::offset`.
+    ///
+    /// The cell sizes of the parent bus are not known here, so `reg` of
+    /// the returned node is decoded using the default cell sizes.
+    pub fn node_at(&self, offset: usize) -> Result<Node<'a>, FdtError> {
+        match token::next_token(self.structure(), offset)? {
+            (Token::BeginNode(name), body_offset) => Ok(Node::new(
+                *self,
+                offset,
+                core::str::from_utf8(name).map_err(|_| FdtError::InvalidFormat)?,
+                body_offset,
+                2,
+                1,
+            )),
+            _ =>
+                Err(FdtError::InvalidFormat),
+        }
+    }
+
+    /// Look up a node by its absolute path.
+    ///
+    /// Path components without a unit address match any unit address.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        if !path.starts_with('/') {
+            return None;
+        }
+
+        let mut node = self.root().ok()?;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.child(component)?;
+        }
+
+        Some(node)
+    }
+
+    /// Look up a node by absolute path or by an alias defined in `/aliases`.
+    pub fn resolve(&self, path: &str) -> Option<Node<'a>> {
+        if path.starts_with('/') {
+            return self.find_node(path);
+        }
+
+        let (alias, rest) = match path.find('/') {
+            Some(index) => (&path[..index], &path[index..]),
+            None => (path, ""),
+        };
+
+        let aliased = self.find_node("/aliases")?
+            .property(alias)?
+            .as_str()?;
+
+        let mut node = self.find_node(aliased)?;
+        for component in rest.split('/').filter(|c| !c.is_empty()) {
+            node = node.child(component)?;
+        }
+
+        Some(node)
+    }
+
+    /// Absolute path of the node at `offset`, written into `buffer`.
+    pub fn node_path<'b>(&self, offset: usize, buffer: &'b mut [u8]) -> Option<&'b str> {
+        const MAX_DEPTH: usize = 32;
+
+        let structure = self.structure();
+        let mut lengths = [0usize; MAX_DEPTH];
+        let mut depth = 0;
+        let mut length = 0;
+        let mut current = 0;
+
+        loop {
+            let (token, next_offset) = token::next_token(structure, current).ok()?;
+            match token {
+                Token::BeginNode(name) => {
+                    if depth == MAX_DEPTH {
+                        return None;
+                    }
+                    lengths[depth] = length;
+                    depth += 1;
+
+                    // the root node has an empty name
+                    if depth > 1 {
+                        let end = length + 1 + name.len();
+                        if end > buffer.len() {
+                            return None;
+                        }
+                        buffer[length] = b'/';
+                        buffer[(length + 1)..end].copy_from_slice(name);
+                        length = end;
+                    }
+
+                    if current == offset {
+                        if length == 0 {
+                            *buffer.get_mut(0)? = b'/';
+                            length = 1;
+                        }
+                        return core::str::from_utf8(&buffer[..length]).ok();
+                    }
+                },
+                Token::EndNode => {
+                    depth = depth.checked_sub(1)?;
+                    length = lengths[depth];
+                },
+                Token::Property { .. } => (),
+                Token::End => return None,
+            }
+            current = next_offset;
+        }
+    }
+
+    pub fn chosen(&self) -> Option<Node<'a>> {
+        self.find_node("/chosen")
+    }
+
+    pub fn find_by_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        fn search<'a>(node: Node<'a>, phandle: u32) -> Option<Node<'a>> {
+            if node.phandle() == Some(phandle) {
+                return Some(node);
+            }
+
+            node.children().find_map(|child| search(child, phandle))
+        }
+
+        search(self.root().ok()?, phandle)
+    }
+}
@@ -0,0 +1,8 @@
+#[derive(Debug)]
+pub enum FdtError {
+    InvalidMagic,
+    UnsupportedVersion,
+    InvalidFormat,
+    BufferOverflow,
+    NotFound,
+}
@@ -0,0 +1,61 @@
+pub const MAGIC: u32 = 0xd00d_feed;
+
+/// Oldest blob version whose layout we understand.
+pub const COMPATIBLE_VERSION: u32 = 16;
+
+/// Flattened device tree header. All fields are stored big-endian.
+#[repr(C)]
+pub struct Header {
+    magic: u32,
+    total_size: u32,
+    off_dt_struct: u32,
+    off_dt_strings: u32,
+    off_mem_rsvmap: u32,
+    version: u32,
+    last_comp_version: u32,
+    boot_cpuid_phys: u32,
+    size_dt_strings: u32,
+    size_dt_struct: u32,
+}
+
+impl Header {
+    pub fn valid_magic(&self) -> bool {
+        u32::from_be(self.magic) == MAGIC
+    }
+
+    pub fn get_total_size(&self) -> usize {
+        u32::from_be(self.total_size) as usize
+    }
+
+    pub fn get_struct_offset(&self) -> usize {
+        u32::from_be(self.off_dt_struct) as usize
+    }
+
+    pub fn get_struct_size(&self) -> usize {
+        u32::from_be(self.size_dt_struct) as usize
+    }
+
+    pub fn get_strings_offset(&self) -> usize {
+        u32::from_be(self.off_dt_strings) as usize
+    }
+
+    pub fn get_strings_size(&self) -> usize {
+        u32::from_be(self.size_dt_strings) as usize
+    }
+
+    pub fn get_memory_reservation_offset(&self) -> usize {
+        u32::from_be(self.off_mem_rsvmap) as usize
+    }
+
+    pub fn get_version(&self) -> u32 {
+        u32::from_be(self.version)
+    }
+
+    pub fn get_last_compatible_version(&self) -> u32 {
+        u32::from_be(self.last_comp_version)
+    }
+
+    pub fn get_boot_cpu(&self) -> u32 {
+        u32::from_be(self.boot_cpuid_phys)
+    }
+}
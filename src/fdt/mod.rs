@@ -0,0 +1,18 @@
+mod device_tree;
+mod error;
+mod header;
+mod node;
+mod overlay;
+mod property;
+mod token;
+mod writer;
+
+pub use self::{
+    device_tree::DeviceTree,
+    error::FdtError,
+    header::Header,
+    node::{Node, NodeIterator, RegIterator},
+    overlay::apply_overlay,
+    property::{Property, PropertyIterator, StringListIterator},
+    writer::DeviceTreeWriter,
+};
@@ -0,0 +1,210 @@
+use super::{
+    DeviceTree, Property, PropertyIterator,
+    token::{self, Token},
+};
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    tree: DeviceTree<'a>,
+    offset: usize,
+    name: &'a str,
+    body_offset: usize,
+    // cell sizes of the parent bus, used to decode `reg`
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Node<'a> {
+    pub(super) fn new(
+        tree: DeviceTree<'a>,
+        offset: usize,
+        name: &'a str,
+        body_offset: usize,
+        address_cells: u32,
+        size_cells: u32,
+    ) -> Node<'a> {
+        Node { tree, offset, name, body_offset, address_cells, size_cells }
+    }
+
+    /// Full node name, including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Node name without the unit address.
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    /// Offset of the node's `FDT_BEGIN_NODE` token in the structure block.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn tree(&self) -> DeviceTree<'a> {
+        self.tree
+    }
+
+    pub fn properties(&self) -> PropertyIterator<'a> {
+        PropertyIterator::new(self.tree, self.body_offset)
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name() == name)
+    }
+
+    pub fn children(&self) -> NodeIterator<'a> {
+        NodeIterator {
+            tree: self.tree,
+            next_offset: Some(self.body_offset),
+            address_cells: self.property("#address-cells")
+                .and_then(|p| p.as_u32())
+                .unwrap_or(2),
+            size_cells: self.property("#size-cells")
+                .and_then(|p| p.as_u32())
+                .unwrap_or(1),
+        }
+    }
+
+    /// Find a direct child by name. A name without unit address
+    /// matches the first child with the same base name.
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        let match_base = !name.contains('@');
+        self.children().find(|child| {
+            child.name() == name || (match_base && child.base_name() == name)
+        })
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|p| p.as_u32())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        match self.property("compatible") {
+            Some(property) => property.string_list().any(|c| c == compatible),
+            None => false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status").and_then(|p| p.as_str()) {
+            Some(status) => status == "okay" || status == "ok",
+            None => true,
+        }
+    }
+
+    /// Decode the `reg` property as `(address, size)` pairs.
+    pub fn reg(&self) -> Option<RegIterator<'a>> {
+        if self.address_cells > 2 || self.size_cells > 2 {
+            return None;
+        }
+        if self.address_cells + self.size_cells == 0 {
+            return None;
+        }
+
+        Some(RegIterator {
+            value: self.property("reg")?.value(),
+            address_cells: self.address_cells as usize,
+            size_cells: self.size_cells as usize,
+        })
+    }
+}
+
+pub struct NodeIterator<'a> {
+    tree: DeviceTree<'a>,
+    next_offset: Option<usize>,
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> NodeIterator<'a> {
+    fn skip_subtree(structure: &[u8], mut offset: usize) -> Option<usize> {
+        let mut depth = 1;
+        while depth > 0 {
+            let (token, next_offset) = token::next_token(structure, offset).ok()?;
+            match token {
+                Token::BeginNode(_) => depth += 1,
+                Token::EndNode => depth -= 1,
+                Token::End => return None,
+                Token::Property { .. } => (),
+            }
+            offset = next_offset;
+        }
+
+        Some(offset)
+    }
+}
+
+impl<'a> core::iter::Iterator for NodeIterator<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structure = self.tree.structure();
+
+        loop {
+            let offset = self.next_offset?;
+            let (token, body_offset) = match token::next_token(structure, offset) {
+                Ok(result) => result,
+                Err(_) => {
+                    self.next_offset = None;
+                    return None;
+                },
+            };
+
+            match token {
+                Token::Property { .. } => {
+                    self.next_offset = Some(body_offset);
+                },
+                Token::BeginNode(name) => {
+                    self.next_offset = NodeIterator::skip_subtree(structure, body_offset);
+
+                    let name = core::str::from_utf8(name).unwrap_or("");
+                    return Some(Node::new(
+                        self.tree,
+                        offset,
+                        name,
+                        body_offset,
+                        self.address_cells,
+                        self.size_cells,
+                    ));
+                },
+                Token::EndNode | Token::End => {
+                    self.next_offset = None;
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+pub struct RegIterator<'a> {
+    value: &'a [u8],
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> RegIterator<'a> {
+    fn read_cells(&mut self, count: usize) -> Option<u64> {
+        let mut result = 0u64;
+        for i in 0..count {
+            let cell = token::read_u32(self.value, i * 4).ok()?;
+            result = (result << 32) | cell as u64;
+        }
+
+        self.value = &self.value[(count * 4)..];
+        Some(result)
+    }
+}
+
+impl<'a> core::iter::Iterator for RegIterator<'a> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let address = self.read_cells(self.address_cells)?;
+        let size = self.read_cells(self.size_cells)?;
+
+        Some((address, size))
+    }
+}
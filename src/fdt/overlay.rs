@@ -0,0 +1,318 @@
+//! Device tree overlay application.
+//!
+//! Overlays are compiled with `dtc -@` and consist of fragments, each
+//! naming a node of the base tree through `target` (a phandle) or
+//! `target-path`, and carrying the content to merge in an `__overlay__`
+//! subnode. References from the overlay into the base tree are listed in
+//! `__fixups__` and resolved through the labels in the base tree's
+//! `__symbols__`; references within the overlay are listed in
+//! `__local_fixups__` and adjusted as the overlay's phandles are renumbered
+//! above those of the base tree.
+
+use super::{
+    DeviceTree, DeviceTreeWriter, FdtError, Node,
+    token,
+};
+
+const MAX_PATH_LENGTH: usize = 256;
+
+/// A phandle value in an overlay property that needs to be adjusted.
+enum Reference<'a> {
+    /// Phandle of a node within the overlay, to be renumbered.
+    Local,
+    /// Phandle of the base tree node with the given label.
+    Label(&'a str),
+}
+
+struct Overlay<'a> {
+    tree: DeviceTree<'a>,
+    fixups: Option<Node<'a>>,
+    local_fixups: Option<Node<'a>>,
+    /// Offset added to all phandles defined in the overlay.
+    delta: u32,
+}
+
+/// Merge `overlay` into the device tree held by `writer`.
+///
+/// On failure, the device tree may have been partially modified.
+pub fn apply_overlay(writer: &mut DeviceTreeWriter, overlay: &DeviceTree) -> Result<(), FdtError> {
+    let root = overlay.root()?;
+    let fixups = exact_child(&root, "__fixups__");
+
+    // labelled base nodes without a phandle get one before renumbering
+    if let Some(fixups) = fixups {
+        for property in fixups.properties() {
+            let (offset, phandle) = label_node(&writer.as_device_tree(), property.name())?;
+            if phandle.is_none() {
+                let phandle = max_phandle(&writer.as_device_tree())? + 1;
+                writer.set_property_u32(offset, "phandle", phandle)?;
+            }
+        }
+    }
+
+    let overlay = Overlay {
+        tree: *overlay,
+        fixups,
+        local_fixups: exact_child(&root, "__local_fixups__"),
+        delta: max_phandle(&writer.as_device_tree())?,
+    };
+
+    for fragment in root.children() {
+        let content = match exact_child(&fragment, "__overlay__") {
+            Some(content) => content,
+            None => continue,
+        };
+
+        let local_fixups = overlay.local_fixups
+            .and_then(|local_fixups| exact_child(&local_fixups, fragment.name()));
+        let target = overlay.target(writer, &fragment, local_fixups)?;
+
+        overlay.merge_node(
+            writer,
+            target,
+            &content,
+            local_fixups.and_then(|local_fixups| exact_child(&local_fixups, "__overlay__")),
+        )?;
+    }
+
+    overlay.update_symbols(writer, &root)
+}
+
+impl<'a> Overlay<'a> {
+    /// Offset of the base tree node a fragment applies to.
+    fn target(
+        &self,
+        writer: &DeviceTreeWriter,
+        fragment: &Node<'a>,
+        local_fixups: Option<Node<'a>>,
+    ) -> Result<usize, FdtError> {
+        let base = writer.as_device_tree();
+
+        if let Some(property) = fragment.property("target") {
+            let mut phandle = property.as_u32().ok_or(FdtError::InvalidFormat)?;
+            self.references(fragment, local_fixups, "target", |offset, reference| {
+                if offset == 0 {
+                    phandle = match reference {
+                        Reference::Local => phandle + self.delta,
+                        Reference::Label(label) => label_phandle(&base, label)?,
+                    };
+                }
+                Ok(())
+            })?;
+
+            return base.find_by_phandle(phandle)
+                .map(|node| node.offset())
+                .ok_or(FdtError::NotFound);
+        }
+
+        let path = fragment.property("target-path")
+            .and_then(|property| property.as_str())
+            .ok_or(FdtError::InvalidFormat)?;
+        base.resolve(path)
+            .map(|node| node.offset())
+            .ok_or(FdtError::NotFound)
+    }
+
+    /// Recursively merge the properties and subnodes of `node` into the base
+    /// tree node at `target`. `local_fixups` mirrors `node` in `__local_fixups__`.
+    fn merge_node(
+        &self,
+        writer: &mut DeviceTreeWriter,
+        target: usize,
+        node: &Node<'a>,
+        local_fixups: Option<Node<'a>>,
+    ) -> Result<(), FdtError> {
+        for property in node.properties() {
+            let name = property.name();
+            writer.set_property(target, name, property.value())?;
+
+            let is_phandle = name == "phandle" || name == "linux,phandle";
+            if is_phandle {
+                adjust_cell(writer, target, name, 0, |phandle| phandle + self.delta)?;
+            }
+
+            self.references(node, local_fixups, name, |offset, reference| {
+                match reference {
+                    Reference::Local =>
+                        adjust_cell(writer, target, name, offset, |phandle| phandle + self.delta),
+                    Reference::Label(label) => {
+                        let phandle = label_phandle(&writer.as_device_tree(), label)?;
+                        adjust_cell(writer, target, name, offset, |_| phandle)
+                    },
+                }
+            })?;
+        }
+
+        for child in node.children() {
+            let child_target = writer.add_subnode(target, child.name())?;
+            self.merge_node(
+                writer,
+                child_target,
+                &child,
+                local_fixups.and_then(|local_fixups| exact_child(&local_fixups, child.name())),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Call `f` with the offset of each phandle reference in property `name` of `node`.
+    fn references<F>(
+        &self,
+        node: &Node<'a>,
+        local_fixups: Option<Node<'a>>,
+        name: &str,
+        mut f: F,
+    ) -> Result<(), FdtError>
+    where
+        F: FnMut(usize, Reference<'a>) -> Result<(), FdtError>,
+    {
+        if let Some(offsets) = local_fixups.and_then(|local_fixups| local_fixups.property(name)) {
+            let offsets = offsets.value();
+            for index in 0..(offsets.len() / 4) {
+                f(token::read_u32(offsets, index * 4)? as usize, Reference::Local)?;
+            }
+        }
+
+        let fixups = match self.fixups {
+            Some(fixups) => fixups,
+            None => return Ok(()),
+        };
+
+        // entries are formatted as `<path>:<property>:<offset>`
+        for label in fixups.properties() {
+            for entry in label.string_list() {
+                let mut parts = entry.rsplitn(3, ':');
+                let offset = parts.next().and_then(|offset| offset.parse().ok());
+                let property = parts.next();
+                let path = parts.next();
+
+                let (offset, property, path) = match (offset, property, path) {
+                    (Some(offset), Some(property), Some(path)) => (offset, property, path),
+                    _ => return Err(FdtError::InvalidFormat),
+                };
+
+                if property != name {
+                    continue;
+                }
+
+                let fixup_node = self.tree.find_node(path).ok_or(FdtError::NotFound)?;
+                if fixup_node.offset() == node.offset() {
+                    f(offset, Reference::Label(label.name()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the overlay's labels to the base tree's `__symbols__`, with paths
+    /// rewritten from the fragment to its target.
+    fn update_symbols(&self, writer: &mut DeviceTreeWriter, root: &Node<'a>) -> Result<(), FdtError> {
+        let symbols = match exact_child(root, "__symbols__") {
+            Some(symbols) => symbols,
+            None => return Ok(()),
+        };
+
+        for symbol in symbols.properties() {
+            let path = symbol.as_str().ok_or(FdtError::InvalidFormat)?;
+
+            // `/<fragment>/__overlay__/<rest>`
+            let mut parts = path.trim_start_matches('/').splitn(3, '/');
+            let fragment_name = parts.next().unwrap_or("");
+            if parts.next() != Some("__overlay__") {
+                continue;
+            }
+            let rest = parts.next().unwrap_or("");
+
+            let fragment = exact_child(root, fragment_name).ok_or(FdtError::NotFound)?;
+            let local_fixups = self.local_fixups
+                .and_then(|local_fixups| exact_child(&local_fixups, fragment_name));
+            let target = self.target(writer, &fragment, local_fixups)?;
+
+            let mut buffer = [0u8; MAX_PATH_LENGTH];
+            let length = {
+                let base = writer.as_device_tree();
+                let target_path = base.node_path(target, &mut buffer)
+                    .ok_or(FdtError::BufferOverflow)?;
+                target_path.len()
+            };
+
+            let length = append_path(&mut buffer, length, rest)?;
+            let symbols_offset = writer.find_or_add_node("/__symbols__")?;
+            let target_path = core::str::from_utf8(&buffer[..length])
+                .map_err(|_| FdtError::InvalidFormat)?;
+            writer.set_property_str(symbols_offset, symbol.name(), target_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Offset and phandle of the base tree node with the given label.
+fn label_node(base: &DeviceTree, label: &str) -> Result<(usize, Option<u32>), FdtError> {
+    let node = base.find_node("/__symbols__")
+        .and_then(|symbols| symbols.property(label))
+        .and_then(|path| path.as_str())
+        .and_then(|path| base.find_node(path))
+        .ok_or(FdtError::NotFound)?;
+
+    Ok((node.offset(), node.phandle()))
+}
+
+fn label_phandle(base: &DeviceTree, label: &str) -> Result<u32, FdtError> {
+    label_node(base, label)?.1.ok_or(FdtError::NotFound)
+}
+
+/// Replace the cell at byte `offset` of a base tree property.
+fn adjust_cell<F>(
+    writer: &mut DeviceTreeWriter,
+    node_offset: usize,
+    name: &str,
+    offset: usize,
+    f: F,
+) -> Result<(), FdtError>
+where
+    F: FnOnce(u32) -> u32,
+{
+    let value = writer.property_value_mut(node_offset, name)?
+        .ok_or(FdtError::NotFound)?;
+    let cell = f(token::read_u32(value, offset)?);
+    token::write_u32(value, offset, cell);
+
+    Ok(())
+}
+
+fn max_phandle(tree: &DeviceTree) -> Result<u32, FdtError> {
+    fn search(node: Node) -> u32 {
+        let own = node.phandle().filter(|&phandle| phandle != u32::MAX).unwrap_or(0);
+        node.children().map(search).fold(own, u32::max)
+    }
+
+    Ok(search(tree.root()?))
+}
+
+/// Child with exactly the given name, including the unit address.
+fn exact_child<'a>(node: &Node<'a>, name: &str) -> Option<Node<'a>> {
+    node.children().find(|child| child.name() == name)
+}
+
+fn append_path(buffer: &mut [u8], length: usize, rest: &str) -> Result<usize, FdtError> {
+    if rest.is_empty() {
+        return Ok(length);
+    }
+
+    // the root path already ends in a separator
+    let separator = if length == 1 { 0 } else { 1 };
+    let end = length + separator + rest.len();
+    if end > buffer.len() {
+        return Err(FdtError::BufferOverflow);
+    }
+
+    if separator == 1 {
+        buffer[length] = b'/';
+    }
+    buffer[(length + separator)..end].copy_from_slice(rest.as_bytes());
+
+    Ok(end)
+}
@@ -0,0 +1,98 @@
+use super::{
+    DeviceTree,
+    token::{self, Token},
+};
+
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    name: &'a str,
+    value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// Interpret the value as a single NUL terminated string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        let length = self.value.iter().position(|&c| c == 0)?;
+        core::str::from_utf8(&self.value[..length]).ok()
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.len() != 4 {
+            return None;
+        }
+
+        token::read_u32(self.value, 0).ok()
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => self.as_u32().map(|value| value as u64),
+            8 => {
+                let high = token::read_u32(self.value, 0).ok()? as u64;
+                let low = token::read_u32(self.value, 4).ok()? as u64;
+                Some((high << 32) | low)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn string_list(&self) -> StringListIterator<'a> {
+        StringListIterator { remaining: self.value }
+    }
+}
+
+pub struct PropertyIterator<'a> {
+    tree: DeviceTree<'a>,
+    next_offset: Option<usize>,
+}
+
+impl<'a> PropertyIterator<'a> {
+    pub(super) fn new(tree: DeviceTree<'a>, offset: usize) -> Self {
+        PropertyIterator { tree, next_offset: Some(offset) }
+    }
+}
+
+impl<'a> core::iter::Iterator for PropertyIterator<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.next_offset?;
+        self.next_offset = None;
+
+        // properties always precede subnodes
+        match token::next_token(self.tree.structure(), offset) {
+            Ok((Token::Property { name_offset, value }, next_offset)) => {
+                self.next_offset = Some(next_offset);
+                Some(Property {
+                    name: self.tree.string(name_offset)?,
+                    value,
+                })
+            },
+            _ => None,
+        }
+    }
+}
+
+pub struct StringListIterator<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> core::iter::Iterator for StringListIterator<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let length = self.remaining.iter().position(|&c| c == 0)?;
+        let string = &self.remaining[..length];
+        self.remaining = &self.remaining[(length + 1)..];
+
+        core::str::from_utf8(string).ok()
+    }
+}
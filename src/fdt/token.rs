@@ -0,0 +1,80 @@
+use super::FdtError;
+
+pub const FDT_BEGIN_NODE: u32 = 0x1;
+pub const FDT_END_NODE: u32 = 0x2;
+pub const FDT_PROP: u32 = 0x3;
+pub const FDT_NOP: u32 = 0x4;
+pub const FDT_END: u32 = 0x9;
+
+pub enum Token<'a> {
+    BeginNode(&'a [u8]),
+    EndNode,
+    Property {
+        name_offset: usize,
+        value: &'a [u8],
+    },
+    End,
+}
+
+pub fn read_u32(buffer: &[u8], offset: usize) -> Result<u32, FdtError> {
+    if offset + 4 > buffer.len() {
+        return Err(FdtError::BufferOverflow);
+    }
+
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buffer[offset..(offset + 4)]);
+    Ok(u32::from_be_bytes(bytes))
+}
+
+pub fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Read the token at `offset` in the structure block, skipping NOPs.
+///
+/// Returns the token along with the offset of the following token.
+pub fn next_token<'a>(
+    structure: &'a [u8],
+    mut offset: usize,
+) -> Result<(Token<'a>, usize), FdtError> {
+    loop {
+        let token = read_u32(structure, offset)?;
+        offset += 4;
+
+        match token {
+            FDT_NOP => continue,
+            FDT_BEGIN_NODE => {
+                let name_length = structure[offset..].iter()
+                    .position(|&c| c == 0)
+                    .ok_or(FdtError::InvalidFormat)?;
+                let name = &structure[offset..(offset + name_length)];
+
+                return Ok((Token::BeginNode(name), align(offset + name_length + 1)));
+            },
+            FDT_END_NODE => return Ok((Token::EndNode, offset)),
+            FDT_PROP => {
+                let length = read_u32(structure, offset)? as usize;
+                let name_offset = read_u32(structure, offset + 4)? as usize;
+                let value_start = offset + 8;
+
+                if value_start + length > structure.len() {
+                    return Err(FdtError::BufferOverflow);
+                }
+
+                return Ok((
+                    Token::Property {
+                        name_offset,
+                        value: &structure[value_start..(value_start + length)],
+                    },
+                    align(value_start + length),
+                ));
+            },
+            FDT_END => return Ok((Token::End, offset)),
+            _ => return Err(FdtError::InvalidFormat),
+        }
+    }
+}
+
+pub fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..(offset + 4)].copy_from_slice(&value.to_be_bytes());
+}
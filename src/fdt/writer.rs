@@ -0,0 +1,447 @@
+use super::{
+    DeviceTree, FdtError,
+    header::{COMPATIBLE_VERSION, MAGIC},
+    token::{self, Token, FDT_BEGIN_NODE, FDT_END_NODE, FDT_PROP},
+};
+
+// byte offsets of header fields
+const TOTAL_SIZE: usize = 4;
+const OFF_DT_STRUCT: usize = 8;
+const OFF_DT_STRINGS: usize = 12;
+const OFF_MEM_RSVMAP: usize = 16;
+const VERSION: usize = 20;
+const LAST_COMP_VERSION: usize = 24;
+const BOOT_CPUID_PHYS: usize = 28;
+const SIZE_DT_STRINGS: usize = 32;
+const SIZE_DT_STRUCT: usize = 36;
+
+const HEADER_SIZE: usize = 40;
+const WRITTEN_VERSION: u32 = 17;
+
+/// Editable device tree blob.
+///
+/// The blob is kept in canonical layout (header, memory reservations,
+/// structure block, strings block) at the start of the buffer, with the
+/// rest of the buffer available for growth. Node offsets are offsets into
+/// the structure block, as returned by `Node::offset`; any edit may move
+/// nodes following the edited location.
+pub struct DeviceTreeWriter<'a> {
+    buffer: &'a mut [u8],
+}
+
+impl<'a> DeviceTreeWriter<'a> {
+    /// Copy `source` into `buffer`.
+    pub fn new(source: &DeviceTree, buffer: &'a mut [u8]) -> Result<Self, FdtError> {
+        let header = source.header();
+        let raw = source.as_bytes();
+
+        // memory reservation entries are terminated by an all zero entry
+        let reservations_start = header.get_memory_reservation_offset();
+        let mut reservations_end = reservations_start;
+        loop {
+            if reservations_end + 16 > raw.len() {
+                return Err(FdtError::InvalidFormat);
+            }
+            reservations_end += 16;
+            if raw[(reservations_end - 16)..reservations_end].iter().all(|&b| b == 0) {
+                break;
+            }
+        }
+
+        let reservations = &raw[reservations_start..reservations_end];
+        let structure = source.structure();
+        let strings_start = header.get_strings_offset();
+        let strings = &raw[strings_start..(strings_start + header.get_strings_size())];
+
+        let reservations_offset = HEADER_SIZE;
+        let struct_offset = token::align(reservations_offset + reservations.len());
+        let strings_offset = struct_offset + structure.len();
+        let total_size = strings_offset + strings.len();
+
+        if total_size > buffer.len() {
+            return Err(FdtError::BufferOverflow);
+        }
+
+        buffer[..total_size].fill(0u8);
+        buffer[reservations_offset..(reservations_offset + reservations.len())]
+            .copy_from_slice(reservations);
+        buffer[struct_offset..strings_offset].copy_from_slice(structure);
+        buffer[strings_offset..total_size].copy_from_slice(strings);
+
+        token::write_u32(buffer, 0, MAGIC);
+        token::write_u32(buffer, TOTAL_SIZE, total_size as u32);
+        token::write_u32(buffer, OFF_DT_STRUCT, struct_offset as u32);
+        token::write_u32(buffer, OFF_DT_STRINGS, strings_offset as u32);
+        token::write_u32(buffer, OFF_MEM_RSVMAP, reservations_offset as u32);
+        token::write_u32(buffer, VERSION, WRITTEN_VERSION);
+        token::write_u32(buffer, LAST_COMP_VERSION, COMPATIBLE_VERSION);
+        token::write_u32(buffer, BOOT_CPUID_PHYS, header.get_boot_cpu());
+        token::write_u32(buffer, SIZE_DT_STRINGS, strings.len() as u32);
+        token::write_u32(buffer, SIZE_DT_STRUCT, structure.len() as u32);
+
+        Ok(DeviceTreeWriter { buffer })
+    }
+
+    pub fn as_device_tree(&self) -> DeviceTree<'_> {
+        // the header was validated (or written) by `new`
+        DeviceTree::from_buffer(&self.buffer[..])
+            .expect("device tree writer holds an invalid blob")
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.buffer.as_ptr()
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.header_field(TOTAL_SIZE)
+    }
+
+    pub fn node_offset(&self, path: &str) -> Option<usize> {
+        self.as_device_tree().find_node(path).map(|node| node.offset())
+    }
+
+    /// Look up a node by path, creating any missing nodes along the way.
+    pub fn find_or_add_node(&mut self, path: &str) -> Result<usize, FdtError> {
+        if !path.starts_with('/') {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        let mut offset = 0;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            offset = self.add_subnode(offset, component)?;
+        }
+
+        Ok(offset)
+    }
+
+    /// Add a child node, or return the offset of an existing child with the same name.
+    pub fn add_subnode(&mut self, parent_offset: usize, name: &str) -> Result<usize, FdtError> {
+        let existing = self.node(parent_offset)?
+            .children()
+            .find(|child| child.name() == name)
+            .map(|child| child.offset());
+        if let Some(offset) = existing {
+            return Ok(offset);
+        }
+
+        // new nodes are inserted after the properties of the parent
+        let offset = self.end_of_properties(parent_offset)?;
+        let name_size = token::align(name.len() + 1);
+        let size = 4 + name_size + 4;
+
+        self.splice_struct(offset, 0, size)?;
+
+        let structure = self.structure_mut();
+        token::write_u32(structure, offset, FDT_BEGIN_NODE);
+        structure[(offset + 4)..(offset + 4 + name_size)].fill(0u8);
+        structure[(offset + 4)..(offset + 4 + name.len())].copy_from_slice(name.as_bytes());
+        token::write_u32(structure, offset + 4 + name_size, FDT_END_NODE);
+
+        Ok(offset)
+    }
+
+    /// Remove a node including all of its children.
+    pub fn delete_node(&mut self, node_offset: usize) -> Result<(), FdtError> {
+        if node_offset == 0 {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        let end = self.end_of_node(node_offset)?;
+        self.splice_struct(node_offset, end - node_offset, 0)
+    }
+
+    pub fn set_property(
+        &mut self,
+        node_offset: usize,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), FdtError> {
+        self.set_property_with(node_offset, name, value.len(), |buffer| {
+            buffer.copy_from_slice(value);
+        })
+    }
+
+    pub fn set_property_u32(
+        &mut self,
+        node_offset: usize,
+        name: &str,
+        value: u32,
+    ) -> Result<(), FdtError> {
+        self.set_property(node_offset, name, &value.to_be_bytes())
+    }
+
+    pub fn set_property_str(
+        &mut self,
+        node_offset: usize,
+        name: &str,
+        value: &str,
+    ) -> Result<(), FdtError> {
+        self.set_property_with(node_offset, name, value.len() + 1, |buffer| {
+            buffer[..value.len()].copy_from_slice(value.as_bytes());
+            buffer[value.len()] = 0;
+        })
+    }
+
+    /// Set a property to a list of cells, each value occupying `cells` cells.
+    pub fn set_property_cells(
+        &mut self,
+        node_offset: usize,
+        name: &str,
+        values: &[(u64, u32)],
+    ) -> Result<(), FdtError> {
+        let size = values.iter().map(|&(_, cells)| cells as usize * 4).sum();
+
+        self.set_property_with(node_offset, name, size, |buffer| {
+            let mut offset = 0;
+            for &(value, cells) in values {
+                for cell in (0..cells).rev() {
+                    let part = if cell < 2 { (value >> (cell * 32)) as u32 } else { 0 };
+                    token::write_u32(buffer, offset, part);
+                    offset += 4;
+                }
+            }
+        })
+    }
+
+    /// Set a property whose value is written by `fill` into a zeroed buffer of `size` bytes.
+    pub fn set_property_with<F>(
+        &mut self,
+        node_offset: usize,
+        name: &str,
+        size: usize,
+        fill: F,
+    ) -> Result<(), FdtError>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let (offset, old_size) = match self.find_property(node_offset, name)? {
+            Some((offset, end)) => (offset, end - offset),
+            None => (self.end_of_properties(node_offset)?, 0),
+        };
+        let name_offset = self.add_string(name)?;
+        let new_size = 12 + token::align(size);
+
+        self.splice_struct(offset, old_size, new_size)?;
+
+        let structure = self.structure_mut();
+        token::write_u32(structure, offset, FDT_PROP);
+        token::write_u32(structure, offset + 4, size as u32);
+        token::write_u32(structure, offset + 8, name_offset as u32);
+        structure[(offset + 12)..(offset + new_size)].fill(0u8);
+        fill(&mut structure[(offset + 12)..(offset + 12 + size)]);
+
+        Ok(())
+    }
+
+    /// Value of an existing property, for editing in place.
+    pub fn property_value_mut(
+        &mut self,
+        node_offset: usize,
+        name: &str,
+    ) -> Result<Option<&mut [u8]>, FdtError> {
+        let offset = match self.find_property(node_offset, name)? {
+            Some((offset, _)) => offset,
+            None => return Ok(None),
+        };
+
+        let structure = self.structure_mut();
+        let size = token::read_u32(structure, offset + 4)? as usize;
+        Ok(Some(&mut structure[(offset + 12)..(offset + 12 + size)]))
+    }
+
+    pub fn delete_property(&mut self, node_offset: usize, name: &str) -> Result<(), FdtError> {
+        if let Some((offset, end)) = self.find_property(node_offset, name)? {
+            self.splice_struct(offset, end - offset, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Append a memory reservation entry.
+    pub fn add_memory_reservation(&mut self, address: u64, size: u64) -> Result<(), FdtError> {
+        let struct_offset = self.header_field(OFF_DT_STRUCT);
+        let mut offset = self.header_field(OFF_MEM_RSVMAP);
+        while offset + 16 <= struct_offset
+            && self.buffer[offset..(offset + 16)].iter().any(|&b| b != 0)
+        {
+            offset += 16;
+        }
+
+        // make room for one more entry in front of the structure block
+        self.splice(offset, 0, 16)?;
+        self.set_header_field(OFF_DT_STRUCT, struct_offset + 16);
+        self.set_header_field(OFF_DT_STRINGS, self.header_field(OFF_DT_STRINGS) + 16);
+
+        self.buffer[offset..(offset + 8)].copy_from_slice(&address.to_be_bytes());
+        self.buffer[(offset + 8)..(offset + 16)].copy_from_slice(&size.to_be_bytes());
+
+        Ok(())
+    }
+
+    fn node(&self, node_offset: usize) -> Result<super::Node<'_>, FdtError> {
+        self.as_device_tree().node_at(node_offset)
+    }
+
+    fn header_field(&self, field: usize) -> usize {
+        token::read_u32(self.buffer, field).unwrap_or(0) as usize
+    }
+
+    fn set_header_field(&mut self, field: usize, value: usize) {
+        token::write_u32(self.buffer, field, value as u32);
+    }
+
+    fn structure(&self) -> &[u8] {
+        let start = self.header_field(OFF_DT_STRUCT);
+        &self.buffer[start..(start + self.header_field(SIZE_DT_STRUCT))]
+    }
+
+    fn structure_mut(&mut self) -> &mut [u8] {
+        let start = self.header_field(OFF_DT_STRUCT);
+        let end = start + self.header_field(SIZE_DT_STRUCT);
+        &mut self.buffer[start..end]
+    }
+
+    /// Offset of the `(start, end)` of a property token in a node.
+    fn find_property(
+        &self,
+        node_offset: usize,
+        name: &str,
+    ) -> Result<Option<(usize, usize)>, FdtError> {
+        let tree = self.as_device_tree();
+        let structure = self.structure();
+
+        let mut offset = self.body_offset(node_offset)?;
+        loop {
+            match token::next_token(structure, offset)? {
+                (Token::Property { name_offset, .. }, next_offset) => {
+                    if tree.string(name_offset) == Some(name) {
+                        return Ok(Some((self.skip_nops(offset)?, next_offset)));
+                    }
+                    offset = next_offset;
+                },
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Skip NOP tokens at `offset` to find the actual start of a token.
+    fn skip_nops(&self, mut offset: usize) -> Result<usize, FdtError> {
+        let structure = self.structure();
+        while token::read_u32(structure, offset)? == token::FDT_NOP {
+            offset += 4;
+        }
+
+        Ok(offset)
+    }
+
+    fn body_offset(&self, node_offset: usize) -> Result<usize, FdtError> {
+        match token::next_token(self.structure(), node_offset)? {
+            (Token::BeginNode(_), body_offset) => Ok(body_offset),
+            _ => Err(FdtError::InvalidFormat),
+        }
+    }
+
+    /// Offset of the first token after the properties of a node.
+    fn end_of_properties(&self, node_offset: usize) -> Result<usize, FdtError> {
+        let structure = self.structure();
+
+        let mut offset = self.body_offset(node_offset)?;
+        loop {
+            match token::next_token(structure, offset)? {
+                (Token::Property { .. }, next_offset) => offset = next_offset,
+                _ => return Ok(offset),
+            }
+        }
+    }
+
+    /// Offset of the first token after the `FDT_END_NODE` of a node.
+    fn end_of_node(&self, node_offset: usize) -> Result<usize, FdtError> {
+        let structure = self.structure();
+
+        let mut depth = 0;
+        let mut offset = node_offset;
+        loop {
+            let (token, next_offset) = token::next_token(structure, offset)?;
+            match token {
+                Token::BeginNode(_) => depth += 1,
+                Token::EndNode => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(next_offset);
+                    }
+                },
+                Token::End => return Err(FdtError::InvalidFormat),
+                Token::Property { .. } => (),
+            }
+            offset = next_offset;
+        }
+    }
+
+    /// Offset of `name` in the strings block, appending it if necessary.
+    fn add_string(&mut self, name: &str) -> Result<usize, FdtError> {
+        let strings_offset = self.header_field(OFF_DT_STRINGS);
+        let strings_size = self.header_field(SIZE_DT_STRINGS);
+        let strings = &self.buffer[strings_offset..(strings_offset + strings_size)];
+
+        let mut offset = 0;
+        for string in strings.split(|&c| c == 0) {
+            if string == name.as_bytes() {
+                return Ok(offset);
+            }
+            offset += string.len() + 1;
+        }
+
+        // the strings block is always last, so it can be extended in place
+        let total_size = self.total_size();
+        let size = name.len() + 1;
+        if total_size + size > self.buffer.len() {
+            return Err(FdtError::BufferOverflow);
+        }
+
+        self.buffer[total_size..(total_size + name.len())].copy_from_slice(name.as_bytes());
+        self.buffer[total_size + name.len()] = 0;
+        self.set_header_field(SIZE_DT_STRINGS, strings_size + size);
+        self.set_header_field(TOTAL_SIZE, total_size + size);
+
+        Ok(strings_size)
+    }
+
+    /// Replace `old_size` bytes at `offset` in the structure block with
+    /// `new_size` bytes, moving everything behind it.
+    fn splice_struct(
+        &mut self,
+        offset: usize,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<(), FdtError> {
+        let struct_offset = self.header_field(OFF_DT_STRUCT);
+        let struct_size = self.header_field(SIZE_DT_STRUCT);
+        if offset + old_size > struct_size {
+            return Err(FdtError::BufferOverflow);
+        }
+
+        self.splice(struct_offset + offset, old_size, new_size)?;
+
+        self.set_header_field(SIZE_DT_STRUCT, struct_size + new_size - old_size);
+        self.set_header_field(
+            OFF_DT_STRINGS,
+            self.header_field(OFF_DT_STRINGS) + new_size - old_size,
+        );
+
+        Ok(())
+    }
+
+    /// Resize a region of the blob, updating the total size.
+    fn splice(&mut self, start: usize, old_size: usize, new_size: usize) -> Result<(), FdtError> {
+        let total_size = self.total_size();
+        let new_total_size = total_size + new_size - old_size;
+        if new_total_size > self.buffer.len() {
+            return Err(FdtError::BufferOverflow);
+        }
+
+        self.buffer.copy_within((start + old_size)..total_size, start + new_size);
+        self.set_header_field(TOTAL_SIZE, new_total_size);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+//! Platform description tables published in the EFI configuration table.
+//!
+//! A platform may describe itself with a DeviceTree, with ACPI, or both.
+//! SMBIOS entry points are passed on to the kernel as found.
+
+use super::efi::{self, configuration_table as guids};
+
+const RSDP_SIGNATURE: &[u8] = b"RSD PTR ";
+const RSDP_V1_SIZE: usize = 20;
+const RSDP_V2_MIN_SIZE: usize = 36;
+
+const SMBIOS_ANCHOR: &[u8] = b"_SM_";
+const SMBIOS_MIN_SIZE: usize = 0x1f;
+const SMBIOS3_ANCHOR: &[u8] = b"_SM3_";
+const SMBIOS3_MIN_SIZE: usize = 0x18;
+
+pub struct FirmwareTables {
+    pub device_tree: Option<*const u8>,
+    /// ACPI Root System Description Pointer.
+    pub acpi_rsdp: Option<u64>,
+    /// SMBIOS 2.x (32 bit) entry point.
+    pub smbios: Option<u64>,
+    /// SMBIOS 3.x (64 bit) entry point.
+    pub smbios3: Option<u64>,
+}
+
+impl FirmwareTables {
+    /// Look up all known tables, ignoring any that fail validation.
+    pub fn discover() -> FirmwareTables {
+        let acpi_rsdp = find_valid(&guids::ACPI_20_TABLE_GUID, validate_rsdp)
+            .or_else(|| find_valid(&guids::ACPI_TABLE_GUID, validate_rsdp));
+
+        FirmwareTables {
+            device_tree: efi::configuration_table(&guids::DEVICE_TREE_GUID)
+                .map(|dtb| dtb as *const u8),
+            acpi_rsdp,
+            smbios: find_valid(&guids::SMBIOS_TABLE_GUID, validate_smbios),
+            smbios3: find_valid(&guids::SMBIOS3_TABLE_GUID, validate_smbios3),
+        }
+    }
+}
+
+fn find_valid(guid: &efi::Guid, validate: unsafe fn(*const u8) -> bool) -> Option<u64> {
+    let table = efi::configuration_table(guid)? as *const u8;
+    if table.is_null() {
+        return None;
+    }
+
+    if unsafe { validate(table) } {
+        Some(table as u64)
+    } else {
+        warn!("Ignoring invalid firmware table at {:#018X}", table as u64);
+        None
+    }
+}
+
+/// Bytes must sum up to zero.
+fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+unsafe fn validate_rsdp(table: *const u8) -> bool {
+    let v1 = core::slice::from_raw_parts(table, RSDP_V1_SIZE);
+    if !v1.starts_with(RSDP_SIGNATURE) || !checksum_valid(v1) {
+        return false;
+    }
+
+    let revision = v1[15];
+    if revision < 2 {
+        return true;
+    }
+
+    let length = u32::from_le_bytes([*table.add(20), *table.add(21), *table.add(22), *table.add(23)]);
+    let length = length as usize;
+    length >= RSDP_V2_MIN_SIZE && checksum_valid(core::slice::from_raw_parts(table, length))
+}
+
+unsafe fn validate_smbios(table: *const u8) -> bool {
+    let anchor = core::slice::from_raw_parts(table, SMBIOS_ANCHOR.len());
+    if anchor != SMBIOS_ANCHOR {
+        return false;
+    }
+
+    let length = *table.add(5) as usize;
+    length >= SMBIOS_MIN_SIZE && checksum_valid(core::slice::from_raw_parts(table, length))
+}
+
+unsafe fn validate_smbios3(table: *const u8) -> bool {
+    let anchor = core::slice::from_raw_parts(table, SMBIOS3_ANCHOR.len());
+    if anchor != SMBIOS3_ANCHOR {
+        return false;
+    }
+
+    let length = *table.add(6) as usize;
+    length >= SMBIOS3_MIN_SIZE && checksum_valid(core::slice::from_raw_parts(table, length))
+}
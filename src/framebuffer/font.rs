@@ -0,0 +1,95 @@
+//! PC Screen Font (PSF1 and PSF2) bitmap fonts.
+
+/// Built-in 8x16 font covering printable ASCII.
+pub static DEFAULT_FONT: &[u8] = include_bytes!("maia-8x16.psf");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_HEADER_SIZE: usize = 4;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+
+#[derive(Clone, Copy)]
+pub struct Font<'a> {
+    glyphs: &'a [u8],
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Font<'a> {
+    pub fn from_buffer(buffer: &'a [u8]) -> Option<Font<'a>> {
+        if buffer.len() >= PSF2_HEADER_SIZE && buffer[..4] == PSF2_MAGIC {
+            let field = |index: usize| {
+                let offset = index * 4;
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(&buffer[offset..(offset + 4)]);
+                u32::from_le_bytes(bytes) as usize
+            };
+
+            let header_size = field(2);
+            let glyph_count = field(4);
+            let bytes_per_glyph = field(5);
+            let height = field(6);
+            let width = field(7);
+
+            return Font::new(&buffer[header_size.min(buffer.len())..], glyph_count, bytes_per_glyph, width, height);
+        }
+
+        if buffer.len() >= PSF1_HEADER_SIZE && buffer[..2] == PSF1_MAGIC {
+            let glyph_count = if buffer[2] & PSF1_MODE_512 != 0 { 512 } else { 256 };
+            let height = buffer[3] as usize;
+
+            return Font::new(&buffer[PSF1_HEADER_SIZE..], glyph_count, height, 8, height);
+        }
+
+        None
+    }
+
+    fn new(
+        glyphs: &'a [u8],
+        glyph_count: usize,
+        bytes_per_glyph: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<Font<'a>> {
+        if width == 0 || height == 0 || bytes_per_glyph < ((width + 7) / 8) * height {
+            return None;
+        }
+        if glyphs.len() < glyph_count * bytes_per_glyph {
+            return None;
+        }
+
+        Some(Font { glyphs, glyph_count, bytes_per_glyph, width, height })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Bitmap of a glyph, one row after another, each row padded to whole bytes.
+    ///
+    /// Characters are mapped to glyphs by code point; the Unicode
+    /// translation table of PSF fonts is not used.
+    pub fn glyph(&self, c: char) -> Option<&'a [u8]> {
+        let index = c as usize;
+        if index >= self.glyph_count {
+            return None;
+        }
+
+        let start = index * self.bytes_per_glyph;
+        Some(&self.glyphs[start..(start + self.bytes_per_glyph)])
+    }
+
+    /// Whether the pixel at `(x, y)` of a glyph bitmap is set.
+    pub fn pixel(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        let bytes_per_row = (self.width + 7) / 8;
+        glyph[y * bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
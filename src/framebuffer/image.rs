@@ -0,0 +1,301 @@
+//! Decoding of BMP and QOI images.
+//!
+//! Images are decoded while drawing, so no buffer for the decoded pixels
+//! is needed. Transparent pixels are blended onto black.
+
+use super::Color;
+
+#[derive(Debug)]
+pub enum ImageError {
+    UnknownFormat,
+    Unsupported,
+    InvalidFormat,
+}
+
+impl core::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ImageError::UnknownFormat => write!(f, "Unknown image format"),
+            ImageError::Unsupported => write!(f, "Unsupported image variant"),
+            ImageError::InvalidFormat => write!(f, "Invalid image data"),
+        }
+    }
+}
+
+const BMP_MAGIC: &[u8] = b"BM";
+const BMP_FILE_HEADER_SIZE: usize = 14;
+const BMP_INFO_HEADER_SIZE: usize = 40;
+const BMP_RGB: u32 = 0;
+const BMP_BITFIELDS: u32 = 3;
+
+const QOI_MAGIC: &[u8] = b"qoif";
+const QOI_HEADER_SIZE: usize = 14;
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_OP_RGBA: u8 = 0xff;
+const QOI_MASK: u8 = 0xc0;
+
+pub struct Image<'a> {
+    width: usize,
+    height: usize,
+    format: Format<'a>,
+}
+
+enum Format<'a> {
+    Bmp(Bmp<'a>),
+    Qoi(&'a [u8]),
+}
+
+struct Bmp<'a> {
+    pixels: &'a [u8],
+    palette: &'a [u8],
+    bits_per_pixel: usize,
+    row_size: usize,
+    top_down: bool,
+    /// Red, green, blue and alpha masks of 32 bit pixels.
+    masks: [u32; 4],
+}
+
+impl<'a> Image<'a> {
+    pub fn from_buffer(buffer: &'a [u8]) -> Result<Image<'a>, ImageError> {
+        if buffer.starts_with(BMP_MAGIC) {
+            Image::from_bmp(buffer)
+        } else if buffer.starts_with(QOI_MAGIC) {
+            Image::from_qoi(buffer)
+        } else {
+            Err(ImageError::UnknownFormat)
+        }
+    }
+
+    fn from_bmp(buffer: &'a [u8]) -> Result<Image<'a>, ImageError> {
+        let pixel_offset = read_u32_le(buffer, 10)? as usize;
+        let info_size = read_u32_le(buffer, BMP_FILE_HEADER_SIZE)? as usize;
+        if info_size < BMP_INFO_HEADER_SIZE {
+            return Err(ImageError::Unsupported);
+        }
+
+        let info = BMP_FILE_HEADER_SIZE;
+        let width = read_u32_le(buffer, info + 4)? as i32;
+        let height = read_u32_le(buffer, info + 8)? as i32;
+        let bits_per_pixel = read_u16_le(buffer, info + 14)? as usize;
+        let compression = read_u32_le(buffer, info + 16)?;
+        let colors_used = read_u32_le(buffer, info + 32)? as usize;
+
+        if width <= 0 || height == 0 {
+            return Err(ImageError::InvalidFormat);
+        }
+        let width = width as usize;
+        let (height, top_down) = match height {
+            height if height < 0 => (height.wrapping_neg() as usize, true),
+            height => (height as usize, false),
+        };
+
+        let masks = match (compression, bits_per_pixel) {
+            (BMP_RGB, 1) | (BMP_RGB, 4) | (BMP_RGB, 8) | (BMP_RGB, 24) =>
+                [0; 4],
+            (BMP_RGB, 32) =>
+                [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0],
+            (BMP_BITFIELDS, 32) => {
+                // Masks follow a plain info header, or are part of a larger one
+                let alpha = if info_size > BMP_INFO_HEADER_SIZE {
+                    read_u32_le(buffer, info + 52)?
+                } else {
+                    0
+                };
+                [
+                    read_u32_le(buffer, info + 40)?,
+                    read_u32_le(buffer, info + 44)?,
+                    read_u32_le(buffer, info + 48)?,
+                    alpha,
+                ]
+            },
+            _ => return Err(ImageError::Unsupported),
+        };
+
+        let palette = if bits_per_pixel <= 8 {
+            let count = match colors_used {
+                0 => 1 << bits_per_pixel,
+                count => count.min(1 << bits_per_pixel),
+            };
+            let start = info + info_size;
+            buffer.get(start..(start + count * 4)).ok_or(ImageError::InvalidFormat)?
+        } else {
+            &[]
+        };
+
+        let row_size = (bits_per_pixel * width + 31) / 32 * 4;
+        let pixels = row_size.checked_mul(height)
+            .and_then(|size| buffer.get(pixel_offset..pixel_offset.checked_add(size)?))
+            .ok_or(ImageError::InvalidFormat)?;
+
+        Ok(Image {
+            width,
+            height,
+            format: Format::Bmp(Bmp { pixels, palette, bits_per_pixel, row_size, top_down, masks }),
+        })
+    }
+
+    fn from_qoi(buffer: &'a [u8]) -> Result<Image<'a>, ImageError> {
+        let width = read_u32_be(buffer, 4)? as usize;
+        let height = read_u32_be(buffer, 8)? as usize;
+        if width == 0 || height == 0 {
+            return Err(ImageError::InvalidFormat);
+        }
+
+        Ok(Image { width, height, format: Format::Qoi(&buffer[QOI_HEADER_SIZE..]) })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Decode the image, passing each pixel to `put` in an unspecified order.
+    ///
+    /// Truncated images fail after the available pixels have been passed on.
+    pub fn draw<F>(&self, put: F) -> Result<(), ImageError>
+    where
+        F: FnMut(usize, usize, Color),
+    {
+        match &self.format {
+            Format::Bmp(bmp) => bmp.draw(self.width, self.height, put),
+            Format::Qoi(data) => draw_qoi(data, self.width, self.height, put),
+        }
+    }
+}
+
+impl<'a> Bmp<'a> {
+    fn draw<F>(&self, width: usize, height: usize, mut put: F) -> Result<(), ImageError>
+    where
+        F: FnMut(usize, usize, Color),
+    {
+        for row in 0..height {
+            let y = if self.top_down { row } else { height - 1 - row };
+            let data = &self.pixels[(row * self.row_size)..((row + 1) * self.row_size)];
+
+            for x in 0..width {
+                let color = match self.bits_per_pixel {
+                    24 => Color::rgb(data[x * 3 + 2], data[x * 3 + 1], data[x * 3]),
+                    32 => self.masked_color(read_u32_le(data, x * 4)?),
+                    bits => {
+                        let bit = x * bits;
+                        let index = (data[bit / 8] as usize >> (8 - bits - bit % 8)) & ((1 << bits) - 1);
+                        self.palette_color(index)?
+                    },
+                };
+                put(x, y, color);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn masked_color(&self, pixel: u32) -> Color {
+        let [red, green, blue, alpha] = self.masks;
+        let color = Color::rgb(channel(pixel, red), channel(pixel, green), channel(pixel, blue));
+        if alpha == 0 {
+            color
+        } else {
+            blend(color, channel(pixel, alpha))
+        }
+    }
+
+    fn palette_color(&self, index: usize) -> Result<Color, ImageError> {
+        let entry = self.palette.get((index * 4)..(index * 4 + 3)).ok_or(ImageError::InvalidFormat)?;
+        Ok(Color::rgb(entry[2], entry[1], entry[0]))
+    }
+}
+
+fn draw_qoi<F>(mut data: &[u8], width: usize, height: usize, mut put: F) -> Result<(), ImageError>
+where
+    F: FnMut(usize, usize, Color),
+{
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0u8, 0, 0, 255];
+    let mut run = 0;
+
+    let mut next = || -> Result<u8, ImageError> {
+        let (&byte, rest) = data.split_first().ok_or(ImageError::InvalidFormat)?;
+        data = rest;
+        Ok(byte)
+    };
+
+    for position in 0..(width * height) {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let op = next()?;
+            match op {
+                QOI_OP_RGB => {
+                    pixel[0] = next()?;
+                    pixel[1] = next()?;
+                    pixel[2] = next()?;
+                },
+                QOI_OP_RGBA => {
+                    pixel = [next()?, next()?, next()?, next()?];
+                },
+                _ => match op & QOI_MASK {
+                    QOI_OP_INDEX => pixel = index[op as usize],
+                    QOI_OP_DIFF => {
+                        pixel[0] = pixel[0].wrapping_add((op >> 4) & 0x03).wrapping_sub(2);
+                        pixel[1] = pixel[1].wrapping_add((op >> 2) & 0x03).wrapping_sub(2);
+                        pixel[2] = pixel[2].wrapping_add(op & 0x03).wrapping_sub(2);
+                    },
+                    QOI_OP_LUMA => {
+                        let green = (op & 0x3f).wrapping_sub(32);
+                        let rest = next()?;
+                        pixel[0] = pixel[0].wrapping_add(green.wrapping_sub(8).wrapping_add(rest >> 4));
+                        pixel[1] = pixel[1].wrapping_add(green);
+                        pixel[2] = pixel[2].wrapping_add(green.wrapping_sub(8).wrapping_add(rest & 0x0f));
+                    },
+                    // QOI_OP_RUN, repeating the previous pixel
+                    _ => run = (op & 0x3f) as usize,
+                },
+            }
+
+            let [r, g, b, a] = pixel;
+            let hash = (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64;
+            index[hash] = pixel;
+        }
+
+        put(position % width, position / width, blend(Color::rgb(pixel[0], pixel[1], pixel[2]), pixel[3]));
+    }
+
+    Ok(())
+}
+
+/// Scale a masked channel to 8 bits.
+fn channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = mask >> shift;
+    (((pixel & mask) >> shift) * 255 / max) as u8
+}
+
+/// Blend onto black.
+fn blend(color: Color, alpha: u8) -> Color {
+    let scale = |value: u8| (value as u32 * alpha as u32 / 255) as u8;
+    Color::rgb(scale(color.red), scale(color.green), scale(color.blue))
+}
+
+fn read_u16_le(buffer: &[u8], offset: usize) -> Result<u16, ImageError> {
+    let bytes = buffer.get(offset..(offset + 2)).ok_or(ImageError::InvalidFormat)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32_le(buffer: &[u8], offset: usize) -> Result<u32, ImageError> {
+    let bytes = buffer.get(offset..(offset + 4)).ok_or(ImageError::InvalidFormat)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u32_be(buffer: &[u8], offset: usize) -> Result<u32, ImageError> {
+    let bytes = buffer.get(offset..(offset + 4)).ok_or(ImageError::InvalidFormat)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
@@ -0,0 +1,233 @@
+//! Framebuffer discovery through the UEFI Graphics Output Protocol.
+
+pub mod font;
+pub mod image;
+pub mod splash;
+pub mod text_console;
+
+pub use self::font::Font;
+pub use self::image::Image;
+pub use self::splash::Splash;
+pub use self::text_console::{Color, TextConsole};
+
+use super::{efi, fdt, string};
+use efi::graphics_output::{self, ModeInformation};
+
+/// Linear framebuffer description, as handed over to the kernel.
+///
+/// Pixels are `bits_per_pixel` wide, with the color channels given by
+/// the masks applied to the little-endian pixel value.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Framebuffer {
+    pub address: u64,
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per scan line.
+    pub stride: u32,
+    pub bits_per_pixel: u32,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub reserved_mask: u32,
+}
+
+impl Framebuffer {
+    /// Locate the Graphics Output Protocol and describe its framebuffer,
+    /// switching to the given resolution first if requested. If that fails,
+    /// the current mode is kept.
+    ///
+    /// Returns `None` if the display has no linear framebuffer.
+    pub fn discover(resolution: Option<(u32, u32)>) -> Result<Option<Framebuffer>, efi::Status> {
+        let gop = efi::GraphicsOutput::locate()?;
+
+        if let Some((width, height)) = resolution {
+            if let Err(status) = set_resolution(gop, width, height) {
+                warn!("Unable to set resolution {}x{}: {:#x}", width, height, status);
+            }
+        }
+
+        let mode = gop.mode();
+        Ok(Framebuffer::from_mode(
+            &gop.mode_info(),
+            mode.frame_buffer_base,
+            mode.frame_buffer_size as u64,
+        ))
+    }
+
+    fn from_mode(info: &ModeInformation, address: u64, size: u64) -> Option<Framebuffer> {
+        let (red_mask, green_mask, blue_mask, reserved_mask) = match info.pixel_format {
+            graphics_output::PIXEL_RGB_RESERVED_8BIT =>
+                (0x0000_00ff, 0x0000_ff00, 0x00ff_0000, 0xff00_0000),
+            graphics_output::PIXEL_BGR_RESERVED_8BIT =>
+                (0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000),
+            graphics_output::PIXEL_BIT_MASK => {
+                let masks = info.pixel_information;
+                (masks.red_mask, masks.green_mask, masks.blue_mask, masks.reserved_mask)
+            },
+            _ => return None,
+        };
+
+        let used_bits = red_mask | green_mask | blue_mask | reserved_mask;
+        let bits_per_pixel = ((32 - used_bits.leading_zeros()) + 7) & !7;
+        if bits_per_pixel == 0 {
+            return None;
+        }
+
+        Some(Framebuffer {
+            address,
+            size,
+            width: info.horizontal_resolution,
+            height: info.vertical_resolution,
+            stride: info.pixels_per_scan_line * (bits_per_pixel / 8),
+            bits_per_pixel,
+            red_mask,
+            green_mask,
+            blue_mask,
+            reserved_mask,
+        })
+    }
+
+    /// Encode a color as a pixel value in the framebuffer's format.
+    pub fn pixel(&self, color: Color) -> u32 {
+        fn scale(value: u8, mask: u32) -> u32 {
+            if mask == 0 {
+                return 0;
+            }
+            let shift = mask.trailing_zeros();
+            let max = mask >> shift;
+            ((value as u32 * max + 127) / 255) << shift
+        }
+
+        scale(color.red, self.red_mask)
+            | scale(color.green, self.green_mask)
+            | scale(color.blue, self.blue_mask)
+    }
+
+    /// Unsafe: the framebuffer must be mapped at its physical address.
+    pub unsafe fn write_pixel(&self, x: usize, y: usize, pixel: u32) {
+        if x >= self.width as usize || y >= self.height as usize {
+            return;
+        }
+
+        let bytes_per_pixel = (self.bits_per_pixel / 8) as usize;
+        let offset = y * self.stride as usize + x * bytes_per_pixel;
+        let address = (self.address as usize + offset) as *mut u8;
+
+        match bytes_per_pixel {
+            4 => core::ptr::write_volatile(address as *mut u32, pixel),
+            2 => core::ptr::write_volatile(address as *mut u16, pixel as u16),
+            _ => {
+                for i in 0..bytes_per_pixel {
+                    core::ptr::write_volatile(address.add(i), (pixel >> (i * 8)) as u8);
+                }
+            },
+        }
+    }
+
+    /// Fill a rectangle, clipped to the visible area.
+    ///
+    /// Unsafe: the framebuffer must be mapped at its physical address.
+    pub unsafe fn fill(&self, x: usize, y: usize, width: usize, height: usize, pixel: u32) {
+        let x_end = (x + width).min(self.width as usize);
+        let y_end = (y + height).min(self.height as usize);
+
+        for y in y..y_end {
+            for x in x..x_end {
+                self.write_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    /// Pixel format name as used by the `simple-framebuffer` binding.
+    pub fn simple_framebuffer_format(&self) -> Option<&'static str> {
+        match (self.bits_per_pixel, self.red_mask, self.green_mask, self.blue_mask) {
+            (32, 0x00ff_0000, 0x0000_ff00, 0x0000_00ff) => Some("x8r8g8b8"),
+            (32, 0x0000_00ff, 0x0000_ff00, 0x00ff_0000) => Some("x8b8g8r8"),
+            (16, 0xf800, 0x07e0, 0x001f) => Some("r5g6b5"),
+            _ => None,
+        }
+    }
+
+    /// Describe the framebuffer with a `simple-framebuffer` node in `/chosen`.
+    pub fn add_to_device_tree(
+        &self,
+        device_tree: &mut fdt::DeviceTreeWriter,
+    ) -> Result<(), fdt::FdtError> {
+        let format = match self.simple_framebuffer_format() {
+            Some(format) => format,
+            None => return Ok(()),
+        };
+
+        let chosen = device_tree.find_or_add_node("/chosen")?;
+        let (address_cells, size_cells) = {
+            let node = device_tree.as_device_tree().node_at(chosen)?;
+            (
+                node.property("#address-cells").and_then(|p| p.as_u32()),
+                node.property("#size-cells").and_then(|p| p.as_u32()),
+            )
+        };
+
+        let (address_cells, size_cells) = match (address_cells, size_cells) {
+            (Some(address_cells), Some(size_cells)) => (address_cells, size_cells),
+            _ => {
+                device_tree.set_property_u32(chosen, "#address-cells", 2)?;
+                device_tree.set_property_u32(chosen, "#size-cells", 2)?;
+                device_tree.set_property(chosen, "ranges", &[])?;
+                (2, 2)
+            },
+        };
+
+        let mut name_buffer = [0u8; 32];
+        let name = string::format(&mut name_buffer, format_args!("framebuffer@{:x}", self.address))
+            .ok_or(fdt::FdtError::BufferOverflow)?;
+
+        let node = device_tree.add_subnode(chosen, name)?;
+        device_tree.set_property_str(node, "compatible", "simple-framebuffer")?;
+        device_tree.set_property_cells(
+            node,
+            "reg",
+            &[(self.address, address_cells), (self.size, size_cells)],
+        )?;
+        device_tree.set_property_u32(node, "width", self.width)?;
+        device_tree.set_property_u32(node, "height", self.height)?;
+        device_tree.set_property_u32(node, "stride", self.stride)?;
+        device_tree.set_property_str(node, "format", format)?;
+        device_tree.set_property_str(node, "status", "okay")?;
+
+        Ok(())
+    }
+}
+
+/// Parse a resolution given as `<width>x<height>`.
+pub fn parse_resolution(resolution: &str) -> Option<(u32, u32)> {
+    let separator = resolution.find('x')?;
+    let width = resolution[..separator].parse().ok()?;
+    let height = resolution[(separator + 1)..].parse().ok()?;
+
+    Some((width, height))
+}
+
+fn set_resolution(
+    gop: &mut efi::GraphicsOutput,
+    width: u32,
+    height: u32,
+) -> Result<(), efi::Status> {
+    let current = gop.mode_info();
+    if current.horizontal_resolution == width && current.vertical_resolution == height {
+        return Ok(());
+    }
+
+    for mode in 0..gop.mode().max_mode {
+        let info = gop.query_mode(mode)?;
+        if info.horizontal_resolution == width
+            && info.vertical_resolution == height
+            && info.pixel_format != graphics_output::PIXEL_BLT_ONLY
+        {
+            return gop.set_mode(mode);
+        }
+    }
+
+    Err(efi::status::UNSUPPORTED)
+}
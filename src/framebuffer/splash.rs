@@ -0,0 +1,83 @@
+//! Boot splash screen with a progress bar.
+
+use super::{image::{Image, ImageError}, Color, Framebuffer};
+
+// Embed the splash image given by the SPLASH environment variable, if any
+include!(concat!(env!("OUT_DIR"), "/splash_info.rs"));
+
+/// Splash image embedded at build time.
+pub fn embedded_image() -> Option<&'static [u8]> {
+    SPLASH_IMAGE
+}
+
+const BAR_HEIGHT: usize = 8;
+const BAR_SPACING: usize = 32;
+const BAR_BORDER: Color = Color::rgb(0x80, 0x80, 0x80);
+const BAR_FILL: Color = Color::WHITE;
+
+pub struct Splash {
+    framebuffer: Framebuffer,
+    bar_x: usize,
+    bar_y: usize,
+    bar_width: usize,
+    progress: usize,
+}
+
+impl Splash {
+    /// Clear the screen and draw `image` centered, with an empty
+    /// progress bar below it.
+    ///
+    /// Unsafe: the framebuffer must be mapped at its physical address.
+    pub unsafe fn show(framebuffer: Framebuffer, image: &Image) -> Result<Splash, ImageError> {
+        let screen_width = framebuffer.width as usize;
+        let screen_height = framebuffer.height as usize;
+
+        let bar_width = screen_width / 3;
+        let total_height = image.height() + BAR_SPACING + BAR_HEIGHT;
+        let image_x = screen_width.saturating_sub(image.width()) / 2;
+        let image_y = screen_height.saturating_sub(total_height) / 2;
+
+        framebuffer.fill(0, 0, screen_width, screen_height, framebuffer.pixel(Color::BLACK));
+
+        let splash = Splash {
+            framebuffer,
+            bar_x: (screen_width - bar_width) / 2,
+            bar_y: (image_y + image.height() + BAR_SPACING).min(screen_height.saturating_sub(BAR_HEIGHT)),
+            bar_width,
+            progress: 0,
+        };
+
+        image.draw(|x, y, color| {
+            framebuffer.write_pixel(image_x + x, image_y + y, framebuffer.pixel(color));
+        })?;
+
+        splash.draw_bar();
+        Ok(splash)
+    }
+
+    /// Advance the progress bar to `percent`.
+    pub fn set_progress(&mut self, percent: usize) {
+        self.progress = percent.min(100);
+        unsafe { self.draw_bar(); }
+    }
+
+    /// Clear the screen.
+    pub fn hide(self) {
+        let (width, height) = (self.framebuffer.width as usize, self.framebuffer.height as usize);
+        unsafe { self.framebuffer.fill(0, 0, width, height, self.framebuffer.pixel(Color::BLACK)); }
+    }
+
+    unsafe fn draw_bar(&self) {
+        let framebuffer = &self.framebuffer;
+        let (x, y, width) = (self.bar_x, self.bar_y, self.bar_width);
+
+        let border = framebuffer.pixel(BAR_BORDER);
+        framebuffer.fill(x, y, width, 1, border);
+        framebuffer.fill(x, y + BAR_HEIGHT - 1, width, 1, border);
+        framebuffer.fill(x, y, 1, BAR_HEIGHT, border);
+        framebuffer.fill((x + width).saturating_sub(1), y, 1, BAR_HEIGHT, border);
+
+        let filled = width.saturating_sub(4) * self.progress / 100;
+        framebuffer.fill(x + 2, y + 2, filled, BAR_HEIGHT - 4, framebuffer.pixel(BAR_FILL));
+    }
+}
@@ -0,0 +1,164 @@
+//! Text output rendered directly into the framebuffer.
+//!
+//! Only plain memory accesses are used, so the console keeps working
+//! after boot services have been exited.
+
+use super::{font::Font, Framebuffer};
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0x00, 0x00, 0x00);
+    pub const LIGHT_GRAY: Color = Color::rgb(0xaa, 0xaa, 0xaa);
+    pub const WHITE: Color = Color::rgb(0xff, 0xff, 0xff);
+    pub const RED: Color = Color::rgb(0xff, 0x55, 0x55);
+    pub const YELLOW: Color = Color::rgb(0xff, 0xff, 0x55);
+
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Color {
+        Color { red, green, blue }
+    }
+}
+
+pub struct TextConsole {
+    framebuffer: Framebuffer,
+    font: Font<'static>,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    foreground: u32,
+    background: u32,
+}
+
+impl TextConsole {
+    /// Unsafe: the framebuffer must be mapped at its physical address.
+    pub unsafe fn new(framebuffer: Framebuffer, font: Font<'static>) -> Option<TextConsole> {
+        let columns = framebuffer.width as usize / font.width();
+        let rows = framebuffer.height as usize / font.height();
+        if columns == 0 || rows == 0 {
+            return None;
+        }
+
+        Some(TextConsole {
+            framebuffer,
+            font,
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            foreground: framebuffer.pixel(Color::LIGHT_GRAY),
+            background: framebuffer.pixel(Color::BLACK),
+        })
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn set_colors(&mut self, foreground: Color, background: Color) {
+        self.foreground = self.framebuffer.pixel(foreground);
+        self.background = self.framebuffer.pixel(background);
+    }
+
+    pub fn set_cursor(&mut self, column: usize, row: usize) {
+        self.column = column.min(self.columns - 1);
+        self.row = row.min(self.rows - 1);
+    }
+
+    pub fn clear(&mut self) {
+        let (width, height) = (self.framebuffer.width as usize, self.framebuffer.height as usize);
+        unsafe { self.framebuffer.fill(0, 0, width, height, self.background); }
+        self.column = 0;
+        self.row = 0;
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+    }
+
+    pub fn write_char(&mut self, c: char) {
+        match c {
+            '\r' => self.column = 0,
+            '\n' => self.new_line(),
+            '\t' => {
+                let next_stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < next_stop.min(self.columns) {
+                    self.put(' ');
+                }
+            },
+            '\x08' => self.column = self.column.saturating_sub(1),
+            c => self.put(c),
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        if self.column >= self.columns {
+            self.column = 0;
+            self.new_line();
+        }
+
+        self.draw_glyph(self.column, self.row, c);
+        self.column += 1;
+    }
+
+    fn new_line(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    fn scroll(&mut self) {
+        let line_height = self.font.height();
+        let stride = self.framebuffer.stride as usize;
+        let text_height = self.rows * line_height;
+
+        unsafe {
+            let base = self.framebuffer.address as *mut u8;
+            core::ptr::copy(
+                base.add(line_height * stride),
+                base,
+                (text_height - line_height) * stride,
+            );
+
+            self.framebuffer.fill(
+                0,
+                text_height - line_height,
+                self.framebuffer.width as usize,
+                line_height,
+                self.background,
+            );
+        }
+    }
+
+    fn draw_glyph(&mut self, column: usize, row: usize, c: char) {
+        let glyph = self.font.glyph(c)
+            .or_else(|| self.font.glyph('?'))
+            .unwrap_or(&[]);
+
+        let x0 = column * self.font.width();
+        let y0 = row * self.font.height();
+
+        for y in 0..self.font.height() {
+            for x in 0..self.font.width() {
+                let set = !glyph.is_empty() && self.font.pixel(glyph, x, y);
+                let pixel = if set { self.foreground } else { self.background };
+                unsafe { self.framebuffer.write_pixel(x0 + x, y0 + y, pixel); }
+            }
+        }
+    }
+}
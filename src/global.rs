@@ -0,0 +1,55 @@
+//! Global state.
+//!
+//! Maia runs single threaded on the boot hart with interrupts disabled, so
+//! globals need no locking. They are never handed out by reference though:
+//! plain values are copied in and out, and anything else is only accessed
+//! within a closure, which is refused while the same global is already in
+//! use further up the call stack. No two mutable references to a global can
+//! exist at the same time.
+
+use core::cell::{Cell, UnsafeCell};
+
+pub struct Global<T> {
+    value: UnsafeCell<T>,
+    in_use: Cell<bool>,
+}
+
+// Maia is single threaded, see above
+unsafe impl<T> Sync for Global<T> {}
+
+impl<T> Global<T> {
+    pub const fn new(value: T) -> Global<T> {
+        Global {
+            value: UnsafeCell::new(value),
+            in_use: Cell::new(false),
+        }
+    }
+
+    /// Run `f` with exclusive access to the value. Returns `None` without
+    /// calling `f` if the value is already being accessed, e.g. when a
+    /// console sink ends up writing to the console.
+    pub fn try_with<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if self.in_use.replace(true) {
+            return None;
+        }
+
+        let result = f(unsafe { &mut *self.value.get() });
+        self.in_use.set(false);
+
+        Some(result)
+    }
+}
+
+impl<T: Copy> Global<T> {
+    pub fn get(&self) -> T {
+        // only ever replaced as a whole by `set`, never borrowed
+        unsafe { *self.value.get() }
+    }
+
+    pub fn set(&self, value: T) {
+        self.try_with(|current| *current = value);
+    }
+}
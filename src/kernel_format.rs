@@ -0,0 +1,45 @@
+//! Kernel image formats Maia can boot.
+
+use super::{elf, linux_image::{self, LinuxImage}, pe};
+
+/// Number of leading bytes `KernelFormat::detect` needs to recognize any
+/// format.
+pub const DETECT_SIZE: usize = linux_image::HEADER_SIZE;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum KernelFormat {
+    /// ELF executable, entered with the Maia entry ABI, see `entry`.
+    Elf,
+    /// Linux RISC-V Image, entered with the Linux boot ABI: `a0` holding
+    /// the hart id and `a1` the device tree.
+    LinuxImage,
+    /// PE32+ image, loaded by Maia and entered with the Maia entry ABI, or
+    /// started by the firmware as an EFI stub kernel.
+    Pe,
+}
+
+impl KernelFormat {
+    /// Recognize the format by the magic in the first bytes of the image.
+    ///
+    /// Linux Images built with the EFI stub are PE images, too, and are
+    /// recognized as Linux Images.
+    pub fn detect(data: &[u8]) -> Option<KernelFormat> {
+        if data.starts_with(&elf::MAGIC) {
+            Some(KernelFormat::Elf)
+        } else if LinuxImage::is_linux_image(data) {
+            Some(KernelFormat::LinuxImage)
+        } else if data.starts_with(&pe::MAGIC) {
+            Some(KernelFormat::Pe)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KernelFormat::Elf => "ELF",
+            KernelFormat::LinuxImage => "Linux Image",
+            KernelFormat::Pe => "PE",
+        }
+    }
+}
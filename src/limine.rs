@@ -0,0 +1,1083 @@
+//! Limine boot protocol, for ELF kernels embedding Limine requests.
+//!
+//! Requests are 8 byte aligned structures in the loaded image, identified by
+//! a magic number. Maia answers the requests it knows by pointing their
+//! `response` field to a response structure, and leaves the others alone.
+//! Structure layouts follow `limine.h` for RISC-V.
+//!
+//! The kernel is entered with paging enabled and mapped at its virtual
+//! addresses. All memory in the UEFI memory map and the framebuffer are
+//! mapped at the higher half direct map (HHDM) offset of the paging mode,
+//! which all pointers in the responses use, except those the base revision
+//! makes physical. Responses, page tables and stacks are in loader data
+//! memory, reported as bootloader reclaimable.
+
+use super::{
+    boot::{self, Error},
+    boot_info::{BootInfo, MemoryRange},
+    efi, elf, entry, fdt,
+    firmware_tables::FirmwareTables,
+    framebuffer::Framebuffer,
+    memory_map::{MemoryRegion, RegionKind},
+    paging::{self, PageTable},
+    serial::sbi,
+};
+
+/// First half of the identifier of every request.
+const COMMON_MAGIC: [u64; 2] = [0xc7b1_dd30_df4c_8b88, 0x0a82_e883_a194_f07b];
+
+/// Base revision tag, which kernels place along with their requests.
+const BASE_REVISION_MAGIC: [u64; 2] = [0xf956_2b2d_5c95_a6c8, 0x6a7b_3849_4453_6bdc];
+
+/// Highest base revision Maia implements. From base revision 3 on, the
+/// RSDP address is physical.
+const BASE_REVISION: u64 = 3;
+
+/// Stack size of every hart, unless the kernel requests a larger one.
+pub const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+
+/// Maximum number of harts listed for the kernel.
+const MAX_HARTS: usize = 64;
+
+/// Room for one of each request Maia answers.
+const MAX_REQUESTS: usize = 16;
+
+/// Relocatable kernels linked below this address are moved here.
+const KERNEL_BASE: u64 = 0xffff_ffff_8000_0000;
+
+/// Request fields
+const REQUEST_REVISION: usize = 32;
+const REQUEST_RESPONSE: usize = 40;
+const REQUEST_DATA: usize = 48;
+
+/// Paging modes
+const PAGING_MODE_SV39: u64 = 0;
+const PAGING_MODE_SV48: u64 = 1;
+const PAGING_MODE_SV57: u64 = 2;
+
+/// Memory map entry types
+const MEMMAP_USABLE: u64 = 0;
+const MEMMAP_RESERVED: u64 = 1;
+const MEMMAP_ACPI_RECLAIMABLE: u64 = 2;
+const MEMMAP_ACPI_NVS: u64 = 3;
+const MEMMAP_BOOTLOADER_RECLAIMABLE: u64 = 5;
+const MEMMAP_EXECUTABLE_AND_MODULES: u64 = 6;
+const MEMMAP_FRAMEBUFFER: u64 = 7;
+
+const FRAMEBUFFER_RGB: u8 = 1;
+
+/// Structure sizes
+const MEMMAP_ENTRY_SIZE: usize = 24;
+const FRAMEBUFFER_SIZE: usize = 80;
+const FILE_SIZE: usize = 112;
+const SMP_INFO_SIZE: usize = 40;
+/// What a started hart needs to enter the kernel, see `entry::limine_ap_entry`.
+const PARKING_BLOCK_SIZE: usize = 32;
+
+/// Space for the fixed size responses, on top of the variable parts.
+const RESPONSE_HEADROOM: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Request {
+    BootloaderInfo,
+    StackSize,
+    Hhdm,
+    Framebuffer,
+    PagingMode,
+    Smp,
+    MemoryMap,
+    Modules,
+    Rsdp,
+    KernelAddress,
+    DeviceTree,
+    BspHartId,
+}
+
+/// Second half of the identifiers of the requests Maia answers.
+const REQUESTS: &[([u64; 2], Request)] = &[
+    ([0xf550_38d8_e2a1_202f, 0x2794_26fc_f5f5_9740], Request::BootloaderInfo),
+    ([0x224e_f046_0a8e_8926, 0xe1cb_0fc2_5f46_ea3d], Request::StackSize),
+    ([0x48dc_f1cb_8ad2_b852, 0x6398_4e95_9a98_244b], Request::Hhdm),
+    ([0x9d58_27dc_d881_dd75, 0xa314_8604_f6fa_b11b], Request::Framebuffer),
+    ([0x95c1_a0ed_ab09_44cb, 0xa4e5_cb38_42f7_488a], Request::PagingMode),
+    ([0x95a6_7b81_9a1b_857e, 0xa0b6_1b72_3b6a_73e0], Request::Smp),
+    ([0x67cf_3d9d_378a_806f, 0xe304_acdf_c50c_3c62], Request::MemoryMap),
+    ([0x3e7e_2797_02be_32af, 0xca1c_4f3b_d128_0cee], Request::Modules),
+    ([0xc5e7_7b6b_397e_7b43, 0x2763_7845_accd_cf3c], Request::Rsdp),
+    ([0x71ba_7686_3cc5_5f63, 0xb264_4a48_c516_a487], Request::KernelAddress),
+    ([0xb40d_db48_fb54_bac7, 0x5450_8149_3f81_ffb7], Request::DeviceTree),
+    ([0x1369_359f_0255_25f9, 0x2ff2_a561_7839_1bb6], Request::BspHartId),
+];
+
+impl Request {
+    fn name(self) -> &'static str {
+        match self {
+            Request::BootloaderInfo => "bootloader info",
+            Request::StackSize => "stack size",
+            Request::Hhdm => "HHDM",
+            Request::Framebuffer => "framebuffer",
+            Request::PagingMode => "paging mode",
+            Request::Smp => "SMP",
+            Request::MemoryMap => "memory map",
+            Request::Modules => "modules",
+            Request::Rsdp => "RSDP",
+            Request::KernelAddress => "kernel address",
+            Request::DeviceTree => "device tree",
+            Request::BspHartId => "BSP hart id",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LimineError {
+    /// The kernel is relocatable but does not fit in the higher half.
+    InvalidKernelAddress,
+    /// The minimum paging mode of the kernel is not supported by the hart.
+    UnsupportedPagingMode,
+    PageTable(efi::Status),
+    MemoryAllocationFailed,
+    BufferOverflow,
+    /// A request field lies beyond the end of the kernel image.
+    TruncatedRequest(usize),
+}
+
+impl core::fmt::Display for LimineError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            LimineError::InvalidKernelAddress => write!(f, "kernel does not fit in the higher half"),
+            LimineError::UnsupportedPagingMode => write!(f, "required paging mode not supported"),
+            LimineError::PageTable(status) => write!(f, "unable to set up page tables: {:#x}", status),
+            LimineError::MemoryAllocationFailed => write!(f, "memory allocation failed"),
+            LimineError::BufferOverflow => write!(f, "responses too large"),
+            LimineError::TruncatedRequest(offset) =>
+                write!(f, "request field at image offset {:#x} is out of range", offset),
+        }
+    }
+}
+
+/// Whether `data`, 8 byte aligned in a kernel image, contains a request or
+/// a base revision tag.
+pub fn contains_requests(data: &[u8]) -> bool {
+    (0..(data.len() / 8)).any(|index| {
+        let magic = read_magic(data, index * 8);
+        magic == Some(COMMON_MAGIC) || magic == Some(BASE_REVISION_MAGIC)
+    })
+}
+
+/// Whether `address` is in the higher half, where only kernels entered with
+/// paging enabled can be placed.
+pub fn is_higher_half(address: u64) -> bool {
+    address & (1 << 63) != 0
+}
+
+/// Virtual address relocatable kernels linked at `link_base` run at.
+fn relocated_base(link_base: u64, size: u64) -> Result<u64, LimineError> {
+    let base = if link_base < KERNEL_BASE { KERNEL_BASE } else { link_base };
+    match base.checked_add(size) {
+        Some(_) => Ok(base),
+        None => Err(LimineError::InvalidKernelAddress),
+    }
+}
+
+/// Virtual address a Limine kernel runs at. Static kernels loaded at their
+/// physical load address must be linked at a fixed offset from it.
+pub fn virtual_base(
+    kernel_elf: &elf::ElfFile,
+    layout: &elf::Layout,
+    relocatable: bool,
+) -> Result<u64, Error> {
+    if relocatable {
+        return Ok(relocated_base(layout.base as u64, layout.size() as u64)?);
+    }
+    if layout.address_kind == elf::AddressKind::Virtual {
+        return Ok(layout.base as u64);
+    }
+
+    let virtual_layout = elf::Layout::plan(kernel_elf, elf::AddressKind::Virtual)?;
+    let same_offsets = virtual_layout.page_count == layout.page_count
+        && virtual_layout.segments().iter()
+            .zip(layout.segments())
+            .all(|(virtual_segment, segment)| virtual_segment.image_offset == segment.image_offset);
+    if !same_offsets {
+        error!(target: "elf", "Segments are not linked at a fixed offset from their load addresses");
+        return Err(LimineError::InvalidKernelAddress.into());
+    }
+
+    Ok(virtual_layout.base as u64)
+}
+
+/// Loaded Limine kernel, with the requests found in its image.
+pub struct Kernel {
+    image: &'static mut [u8],
+    virtual_base: u64,
+    entry_point: u64,
+    requests: [(Request, usize); MAX_REQUESTS],
+    request_count: usize,
+    base_revision: Option<usize>,
+}
+
+impl Kernel {
+    /// Find the requests in `image`, which is loaded and relocated to run
+    /// at `virtual_base`, entered at the virtual `entry_point`.
+    pub fn new(image: &'static mut [u8], virtual_base: u64, entry_point: u64) -> Kernel {
+        let mut kernel = Kernel {
+            image,
+            virtual_base,
+            entry_point,
+            requests: [(Request::Hhdm, 0); MAX_REQUESTS],
+            request_count: 0,
+            base_revision: None,
+        };
+
+        let mut offset = 0;
+        while offset + 16 <= kernel.image.len() {
+            let magic = read_magic(kernel.image, offset);
+            if magic == Some(BASE_REVISION_MAGIC) {
+                debug!(
+                    target: "limine",
+                    "Base revision {} tag at offset {:#x}",
+                    read_u64(kernel.image, offset + 16).unwrap_or(0),
+                    offset,
+                );
+                kernel.base_revision = Some(offset);
+            } else if magic == Some(COMMON_MAGIC) {
+                kernel.add_request(offset);
+            }
+
+            offset += 8;
+        }
+
+        kernel
+    }
+
+    /// Size of the stack the kernel requests, if it does.
+    pub fn stack_size(&self) -> Option<usize> {
+        let size = self.read(self.request(Request::StackSize)? + REQUEST_DATA)?;
+        Some((size as usize).max(DEFAULT_STACK_SIZE))
+    }
+
+    pub fn wants_modules(&self) -> bool {
+        self.request(Request::Modules).is_some()
+    }
+
+    fn add_request(&mut self, offset: usize) {
+        let id = read_magic(self.image, offset + 16);
+        let request = match REQUESTS.iter().find(|&&(known, _)| Some(known) == id) {
+            Some(&(_, request)) => request,
+            None => {
+                let [first, second] = id.unwrap_or([0, 0]);
+                debug!(
+                    target: "limine",
+                    "Unsupported request {:#018x} {:#018x} at offset {:#x}",
+                    first,
+                    second,
+                    offset,
+                );
+                return;
+            },
+        };
+
+        if offset + REQUEST_DATA > self.image.len() {
+            return;
+        }
+        if self.request(request).is_some() {
+            warn!(target: "limine", "Ignoring duplicate {} request", request.name());
+            return;
+        }
+
+        // there are fewer kinds of requests Maia answers than room here
+        debug!(target: "limine", "{} request at offset {:#x}", request.name(), offset);
+        self.requests[self.request_count] = (request, offset);
+        self.request_count += 1;
+    }
+
+    fn request(&self, request: Request) -> Option<usize> {
+        self.requests[..self.request_count].iter()
+            .find(|&&(found, _)| found == request)
+            .map(|&(_, offset)| offset)
+    }
+
+    fn read(&self, offset: usize) -> Option<u64> {
+        read_u64(self.image, offset)
+    }
+
+    fn write(&mut self, offset: usize, value: u64) -> Result<(), LimineError> {
+        let field = offset.checked_add(8)
+            .and_then(|end| self.image.get_mut(offset..end))
+            .ok_or(LimineError::TruncatedRequest(offset))?;
+        field.copy_from_slice(&value.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Mark the base revision tag as supported if it is, returning the base
+    /// revision the kernel is booted with. Kernels without the tag get
+    /// base revision 0.
+    fn accept_base_revision(&mut self) -> Result<u64, LimineError> {
+        let offset = match self.base_revision {
+            Some(offset) => offset,
+            None => return Ok(0),
+        };
+
+        let requested = self.read(offset + 16).unwrap_or(0);
+        let revision = requested.min(BASE_REVISION);
+        if requested > BASE_REVISION {
+            warn!(
+                target: "limine",
+                "Base revision {} not supported, booting with base revision {}",
+                requested,
+                revision,
+            );
+        } else {
+            self.write(offset + 16, 0)?;
+        }
+        self.write(offset + 8, revision)?;
+
+        Ok(revision)
+    }
+
+    /// Paging mode the kernel is entered with, the one it prefers if the
+    /// hart supports it, or else the most capable one it accepts.
+    fn select_paging_mode(&self, supported: paging::Mode) -> Result<paging::Mode, LimineError> {
+        let (preferred, max, min) = match self.request(Request::PagingMode) {
+            Some(offset) => {
+                let mode = self.read(offset + REQUEST_DATA).unwrap_or(PAGING_MODE_SV48);
+                if self.read(offset + REQUEST_REVISION).unwrap_or(0) >= 1 {
+                    (
+                        mode,
+                        self.read(offset + REQUEST_DATA + 8).unwrap_or(mode),
+                        self.read(offset + REQUEST_DATA + 16).unwrap_or(PAGING_MODE_SV39),
+                    )
+                } else {
+                    (mode, mode, PAGING_MODE_SV39)
+                }
+            },
+            None => (PAGING_MODE_SV48, PAGING_MODE_SV48, PAGING_MODE_SV39),
+        };
+
+        let mode = preferred.min(max).min(paging_mode_value(supported));
+        if mode < min {
+            return Err(LimineError::UnsupportedPagingMode);
+        }
+
+        Ok(match mode {
+            PAGING_MODE_SV39 => paging::Mode::Sv39,
+            PAGING_MODE_SV48 => paging::Mode::Sv48,
+            _ => paging::Mode::Sv57,
+        })
+    }
+
+    fn physical_base(&self) -> u64 {
+        self.image.as_ptr() as u64
+    }
+}
+
+/// Module passed to the kernel.
+#[derive(Clone, Copy)]
+pub struct Module<'a> {
+    pub path: &'a str,
+    pub cmdline: &'a str,
+    pub data: &'a [u8],
+}
+
+/// What the responses describe, besides the kernel itself.
+pub struct Environment<'a> {
+    /// Current UEFI memory map, all memory in it is direct mapped.
+    pub memory_map: &'a efi::MemoryMap,
+    /// Maximum number of regions in the final physical memory map.
+    pub memory_map_capacity: usize,
+    pub framebuffer: Option<&'a Framebuffer>,
+    pub modules: &'a [Module<'a>],
+    pub acpi_rsdp: Option<u64>,
+    pub device_tree: Option<u64>,
+    pub boot_hart_id: Option<u64>,
+    /// Harts listed in the SMP response, the boot hart included.
+    pub harts: &'a [u64],
+    /// Most capable paging mode the boot hart supports.
+    pub paging_mode: paging::Mode,
+    /// Stack of the boot hart. Other harts get stacks of the same size.
+    pub stack: MemoryRange,
+    pub bootloader_name: &'a str,
+}
+
+/// Kernel entry state, see `entry::enter_limine`.
+pub struct Entry {
+    pub entry_point: u64,
+    pub stack_top: u64,
+    pub satp: u64,
+}
+
+/// Response with an array of pointers to entries, filled in late.
+struct ArrayResponse {
+    response: usize,
+    entries: usize,
+    pointers: usize,
+    capacity: usize,
+}
+
+/// Everything set up for a Limine kernel while boot services are active.
+/// The memory map and the harts started are only added once they are
+/// exited, see `finish`.
+pub struct Handoff {
+    page_table: PageTable,
+    responses: ResponseBuffer,
+    memory_map: Option<ArrayResponse>,
+    smp: Option<(ArrayResponse, u64)>,
+    /// Page aligned physical range of the framebuffer.
+    framebuffer: Option<(u64, u64)>,
+    entry_point: u64,
+    stack_top: u64,
+}
+
+impl Handoff {
+    /// Build the page tables and answer the requests of `kernel`, all but
+    /// the memory map response, which is only final once boot services are
+    /// exited.
+    pub fn prepare(kernel: &mut Kernel, environment: &Environment) -> Result<Handoff, LimineError> {
+        let base_revision = kernel.accept_base_revision()?;
+        let mode = kernel.select_paging_mode(environment.paging_mode)?;
+        let hhdm_offset = hhdm_offset(mode);
+        info!(
+            target: "limine",
+            "Limine base revision {}, {} paging, HHDM at {:#018x}",
+            base_revision,
+            mode.name(),
+            hhdm_offset,
+        );
+
+        let page_table = build_page_table(kernel, environment, mode, hhdm_offset)
+            .map_err(LimineError::PageTable)?;
+        debug!(target: "limine", "{} page table(s)", page_table.table_count());
+
+        let strings: usize = environment.modules.iter()
+            .map(|module| module.path.len() + module.cmdline.len() + 2)
+            .sum();
+        let size = RESPONSE_HEADROOM
+            + environment.bootloader_name.len()
+            + strings
+            + environment.modules.len() * (FILE_SIZE + 8)
+            + (environment.memory_map_capacity + 2) * (MEMMAP_ENTRY_SIZE + 8)
+            + environment.harts.len() * (SMP_INFO_SIZE + 8 + PARKING_BLOCK_SIZE);
+        let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, efi::memory::page_count(size))
+            .map_err(|_| LimineError::MemoryAllocationFailed)?;
+
+        let stack = environment.stack;
+        let mut handoff = Handoff {
+            page_table,
+            responses: ResponseBuffer { buffer, size: 0, hhdm_offset },
+            memory_map: None,
+            smp: None,
+            framebuffer: environment.framebuffer.map(page_range),
+            entry_point: kernel.entry_point,
+            stack_top: stack.address + stack.size + hhdm_offset,
+        };
+
+        for index in 0..kernel.request_count {
+            let (request, offset) = kernel.requests[index];
+            let revision = kernel.read(offset + REQUEST_REVISION).unwrap_or(0);
+            let r = &mut handoff.responses;
+
+            let response = match request {
+                Request::BootloaderInfo => {
+                    let response = r.add(24)?;
+                    let name = r.add_string(environment.bootloader_name)?;
+                    let version = r.add_string(env!("CARGO_PKG_VERSION"))?;
+                    r.write_u64(response + 8, name);
+                    r.write_u64(response + 16, version);
+                    Some(response)
+                },
+                Request::StackSize => Some(r.add(8)?),
+                Request::Hhdm => {
+                    let response = r.add(16)?;
+                    r.write_u64(response + 8, hhdm_offset);
+                    Some(response)
+                },
+                Request::Framebuffer => match environment.framebuffer {
+                    Some(framebuffer) => Some(r.add_framebuffer(framebuffer)?),
+                    None => None,
+                },
+                Request::PagingMode => {
+                    let response = r.add(16)?;
+                    r.write_u64(response + 8, paging_mode_value(mode));
+                    Some(response)
+                },
+                Request::Smp => match environment.boot_hart_id {
+                    Some(boot_hart_id) => {
+                        let smp = handoff.prepare_smp(environment, boot_hart_id)?;
+                        let response = smp.response;
+                        handoff.smp = Some((smp, boot_hart_id));
+                        Some(response)
+                    },
+                    None => {
+                        warn!(target: "limine", "Boot hart unknown, not answering the SMP request");
+                        None
+                    },
+                },
+                Request::MemoryMap => {
+                    let capacity = environment.memory_map_capacity + 2;
+                    let memory_map = ArrayResponse {
+                        response: r.add(24)?,
+                        entries: r.add(capacity * MEMMAP_ENTRY_SIZE)?,
+                        pointers: r.add(capacity * 8)?,
+                        capacity,
+                    };
+                    let response = memory_map.response;
+                    handoff.memory_map = Some(memory_map);
+                    Some(response)
+                },
+                Request::Modules => {
+                    let internal_count = match revision {
+                        0 => 0,
+                        _ => kernel.read(offset + REQUEST_DATA).unwrap_or(0),
+                    };
+                    if internal_count != 0 {
+                        warn!(target: "limine", "Internal modules are not supported");
+                    }
+                    Some(r.add_modules(environment.modules)?)
+                },
+                Request::Rsdp => match environment.acpi_rsdp {
+                    Some(rsdp) => {
+                        let response = r.add(16)?;
+                        let address = if base_revision >= 3 { rsdp } else { rsdp + hhdm_offset };
+                        r.write_u64(response + 8, address);
+                        Some(response)
+                    },
+                    None => None,
+                },
+                Request::KernelAddress => {
+                    let response = r.add(24)?;
+                    r.write_u64(response + 8, kernel.physical_base());
+                    r.write_u64(response + 16, kernel.virtual_base);
+                    Some(response)
+                },
+                Request::DeviceTree => match environment.device_tree {
+                    Some(dtb) => {
+                        let response = r.add(16)?;
+                        r.write_u64(response + 8, dtb + hhdm_offset);
+                        Some(response)
+                    },
+                    None => None,
+                },
+                Request::BspHartId => match environment.boot_hart_id {
+                    Some(boot_hart_id) => {
+                        let response = r.add(16)?;
+                        r.write_u64(response + 8, boot_hart_id);
+                        Some(response)
+                    },
+                    None => None,
+                },
+            };
+
+            // all responses are revision 0, which the buffer is zeroed for
+            match response {
+                Some(response) => {
+                    kernel.write(offset + REQUEST_RESPONSE, handoff.responses.address(response))?;
+                    debug!(target: "limine", "Answered {} request", request.name());
+                },
+                None => debug!(target: "limine", "Not answering {} request", request.name()),
+            }
+        }
+
+        Ok(handoff)
+    }
+
+    /// Describe every hart in the SMP response, and allocate stacks for
+    /// the ones other than the boot hart, which are started by `finish`.
+    fn prepare_smp(
+        &mut self,
+        environment: &Environment,
+        boot_hart_id: u64,
+    ) -> Result<ArrayResponse, LimineError> {
+        let harts = environment.harts;
+        let stack_size = environment.stack.size as usize;
+
+        let stacks = match harts.len() {
+            0 | 1 => None,
+            count => Some(efi::allocate_pages(
+                efi::memory::LOADER_DATA,
+                efi::memory::page_count(stack_size) * (count - 1),
+            ).map_err(|_| LimineError::MemoryAllocationFailed)?),
+        };
+        let stack_size = efi::memory::page_count(stack_size) * efi::memory::PAGE_SIZE;
+
+        let r = &mut self.responses;
+        let smp = ArrayResponse {
+            response: r.add(40)?,
+            entries: r.add(harts.len() * SMP_INFO_SIZE)?,
+            pointers: r.add(harts.len() * 8)?,
+            capacity: harts.len(),
+        };
+        let parking = r.add(harts.len() * PARKING_BLOCK_SIZE)?;
+
+        r.write_u64(smp.response + 16, boot_hart_id);
+        r.write_u64(smp.response + 32, r.address(smp.pointers));
+
+        let stacks_address = stacks.map_or(0, |stacks| stacks.as_ptr() as u64);
+        let mut stack_count = 0;
+        for (index, &hart_id) in harts.iter().enumerate() {
+            let info = smp.entries + index * SMP_INFO_SIZE;
+            r.write_u64(info, index as u64);
+            r.write_u64(info + 8, hart_id);
+
+            if hart_id == boot_hart_id {
+                continue;
+            }
+
+            stack_count += 1;
+            let stack_top = stacks_address + (stack_count * stack_size) as u64;
+            let block = parking + index * PARKING_BLOCK_SIZE;
+            r.write_u64(block, self.page_table.satp());
+            r.write_u64(block + 8, r.hhdm_offset);
+            r.write_u64(block + 16, r.physical(info));
+            r.write_u64(block + 24, stack_top + r.hhdm_offset);
+        }
+
+        Ok(smp)
+    }
+
+    /// Add the final memory map, start the other harts and return the
+    /// kernel entry state. Does not allocate, as boot services are exited.
+    pub fn finish(mut self, regions: &[MemoryRegion]) -> Entry {
+        if let Some(memory_map) = self.memory_map.take() {
+            self.finish_memory_map(&memory_map, regions);
+        }
+
+        if let Some((smp, boot_hart_id)) = self.smp.take() {
+            self.start_harts(&smp, boot_hart_id);
+        }
+
+        Entry {
+            entry_point: self.entry_point,
+            stack_top: self.stack_top,
+            satp: self.page_table.satp(),
+        }
+    }
+
+    /// Translate the physical memory map, with the framebuffer added.
+    fn finish_memory_map(&mut self, memory_map: &ArrayResponse, regions: &[MemoryRegion]) {
+        let (framebuffer_start, framebuffer_end) = self.framebuffer.unwrap_or((0, 0));
+        let mut framebuffer_added = self.framebuffer.is_none();
+        let mut count = 0;
+
+        for region in regions {
+            let start = region.base;
+            let end = region.base + region.length;
+            let memory_type = memory_type(region.kind);
+
+            // regions are sorted, the framebuffer goes before the first one
+            // past it, with any overlapping part left out
+            let parts = [
+                (start, end.min(framebuffer_start)),
+                (start.max(framebuffer_end), end),
+            ];
+            for &(part_start, part_end) in parts.iter() {
+                if part_start >= part_end {
+                    continue;
+                }
+
+                if !framebuffer_added && part_start >= framebuffer_start {
+                    self.push_memory_map_entry(
+                        memory_map,
+                        &mut count,
+                        framebuffer_start,
+                        framebuffer_end,
+                        MEMMAP_FRAMEBUFFER,
+                    );
+                    framebuffer_added = true;
+                }
+                self.push_memory_map_entry(memory_map, &mut count, part_start, part_end, memory_type);
+            }
+        }
+
+        if !framebuffer_added {
+            self.push_memory_map_entry(
+                memory_map,
+                &mut count,
+                framebuffer_start,
+                framebuffer_end,
+                MEMMAP_FRAMEBUFFER,
+            );
+        }
+
+        let r = &mut self.responses;
+        r.write_u64(memory_map.response + 8, count as u64);
+        r.write_u64(memory_map.response + 16, r.address(memory_map.pointers));
+    }
+
+    fn push_memory_map_entry(
+        &mut self,
+        memory_map: &ArrayResponse,
+        count: &mut usize,
+        start: u64,
+        end: u64,
+        memory_type: u64,
+    ) {
+        if *count == memory_map.capacity {
+            return;
+        }
+
+        let r = &mut self.responses;
+        let entry = memory_map.entries + *count * MEMMAP_ENTRY_SIZE;
+        r.write_u64(entry, start);
+        r.write_u64(entry + 8, end - start);
+        r.write_u64(entry + 16, memory_type);
+        r.write_u64(memory_map.pointers + *count * 8, r.address(entry));
+        *count += 1;
+    }
+
+    /// Start every hart but the boot hart, parked until the kernel writes
+    /// their `goto_address`, and list those that started.
+    fn start_harts(&mut self, smp: &ArrayResponse, boot_hart_id: u64) {
+        let parking = smp.pointers + smp.capacity * 8;
+        let r = &mut self.responses;
+
+        let mut count = 0;
+        for index in 0..smp.capacity {
+            let info = smp.entries + index * SMP_INFO_SIZE;
+            let hart_id = r.read_u64(info + 8);
+
+            if hart_id != boot_hart_id {
+                let block = r.physical(parking + index * PARKING_BLOCK_SIZE);
+                if let Err(error) = sbi::hart_start(hart_id, entry::limine_ap_entry(), block) {
+                    warn!(target: "limine", "Unable to start hart {}: SBI error {}", hart_id, error);
+                    continue;
+                }
+            }
+
+            r.write_u64(smp.pointers + count * 8, r.address(info));
+            count += 1;
+        }
+
+        r.write_u64(smp.response + 24, count as u64);
+        debug!(target: "limine", "{} hart(s) available to the kernel", count);
+    }
+}
+
+/// Build the page tables of a Limine kernel and answer its requests, all
+/// but the memory map, which is added once boot services are exited.
+pub fn prepare(
+    kernel: &mut Kernel,
+    tables: &FirmwareTables,
+    boot_info: &BootInfo,
+    dtb: *const u8,
+    modules: &[Option<boot::Module>],
+    framebuffer: Option<&Framebuffer>,
+    memory_map_capacity: usize,
+) -> Result<Handoff, Error> {
+    let boot_hart_id = match boot_info.boot_hart_id {
+        u64::MAX => None,
+        hart_id => Some(hart_id),
+    };
+    let mut harts = [0u64; MAX_HARTS];
+    let (hart_count, paging_mode) = list_harts(tables.device_tree, boot_hart_id, &mut harts);
+
+    let mut limine_modules = [Module { path: "", cmdline: "", data: &[] }; boot::MAX_MODULES];
+    let mut module_count = 0;
+    for module in modules.iter().flatten() {
+        limine_modules[module_count] = Module {
+            path: module.path,
+            cmdline: module.string,
+            data: module.data,
+        };
+        module_count += 1;
+    }
+
+    // the direct map covers the memory known now, which is where anything
+    // allocated later comes from
+    let efi_memory_map = efi::MemoryMap::allocate()
+        .map_err(|status| {
+            error!("Unable to get memory map: {:#x}", status);
+            Error::MemoryMapUnavailable
+        })?;
+
+    let environment = Environment {
+        memory_map: &efi_memory_map,
+        memory_map_capacity,
+        framebuffer,
+        modules: &limine_modules[..module_count],
+        acpi_rsdp: tables.acpi_rsdp,
+        device_tree: match dtb.is_null() {
+            true => None,
+            false => Some(dtb as u64),
+        },
+        boot_hart_id,
+        harts: &harts[..hart_count],
+        paging_mode,
+        stack: boot_info.kernel_stack,
+        bootloader_name: boot::BOOT_LOADER_NAME,
+    };
+
+    let handoff = Handoff::prepare(kernel, &environment);
+    efi_memory_map.free();
+
+    Ok(handoff?)
+}
+
+/// Fill `harts` with the ids of the harts enabled in `/cpus` of the device
+/// tree, returning their number along with the most capable paging mode of
+/// the boot hart, from its `mmu-type`. Without a device tree, only the boot
+/// hart is known and Sv39 is assumed.
+fn list_harts(
+    device_tree: Option<*const u8>,
+    boot_hart_id: Option<u64>,
+    harts: &mut [u64],
+) -> (usize, paging::Mode) {
+    let mut count = 0;
+    let mut paging_mode = paging::Mode::Sv39;
+
+    let device_tree = device_tree
+        .and_then(|dtb| unsafe { fdt::DeviceTree::from_address(dtb) }.ok());
+    let cpus = device_tree.as_ref().and_then(|device_tree| device_tree.find_node("/cpus"));
+    for cpu in cpus.iter().flat_map(|cpus| cpus.children()) {
+        let device_type = cpu.property("device_type").and_then(|property| property.as_str());
+        if device_type != Some("cpu") || !cpu.is_enabled() {
+            continue;
+        }
+
+        let hart_id = match cpu.reg().and_then(|mut reg| reg.next()) {
+            Some((hart_id, _)) => hart_id,
+            None => continue,
+        };
+
+        if Some(hart_id) == boot_hart_id {
+            let mmu_type = cpu.property("mmu-type").and_then(|property| property.as_str());
+            if let Some(mode) = mmu_type.and_then(paging::Mode::from_mmu_type) {
+                paging_mode = mode;
+            }
+        }
+
+        if count == harts.len() {
+            warn!("Too many harts, ignoring hart {}", hart_id);
+            continue;
+        }
+        harts[count] = hart_id;
+        count += 1;
+    }
+
+    // the boot hart is always listed, even if the device tree disagrees
+    if let Some(boot_hart_id) = boot_hart_id {
+        if !harts[..count].contains(&boot_hart_id) {
+            count = count.min(harts.len() - 1);
+            harts[count] = boot_hart_id;
+            count += 1;
+        }
+    }
+
+    (count, paging_mode)
+}
+
+/// Map the kernel at its virtual addresses, the memory in the UEFI memory
+/// map and the framebuffer at the HHDM offset, and the trampoline that
+/// switches to the tables at its physical address.
+fn build_page_table(
+    kernel: &Kernel,
+    environment: &Environment,
+    mode: paging::Mode,
+    hhdm_offset: u64,
+) -> Result<PageTable, efi::Status> {
+    use paging::{EXECUTE, READ, WRITE};
+
+    let mut page_table = PageTable::new(mode)?;
+
+    // physical addresses must fit in the half of the address space below
+    // the HHDM offset
+    let limit = 1u64 << (mode.address_bits() - 1);
+    let mut cursor = 0;
+    while let Some((start, end)) = next_memory_run(environment.memory_map, cursor) {
+        cursor = end;
+        if start >= limit {
+            warn!(target: "limine", "Memory from {:#018x} on is beyond the HHDM", start);
+            break;
+        }
+
+        let end = end.min(limit);
+        page_table.map(start + hhdm_offset, start, end - start, READ | WRITE)?;
+    }
+
+    if let Some((start, end)) = environment.framebuffer.map(page_range) {
+        page_table.map(start + hhdm_offset, start, end - start, READ | WRITE)?;
+    }
+
+    page_table.map(
+        kernel.virtual_base,
+        kernel.physical_base(),
+        kernel.image.len() as u64,
+        READ | WRITE | EXECUTE,
+    )?;
+
+    let (start, end) = entry::limine_trampoline();
+    let start = start & !(paging::PAGE_SIZE - 1);
+    let end = (end + paging::PAGE_SIZE - 1) & !(paging::PAGE_SIZE - 1);
+    page_table.map(start, start, end - start, READ | EXECUTE)?;
+
+    Ok(page_table)
+}
+
+/// Next range of contiguous memory in the UEFI memory map at or after
+/// `cursor`. The descriptors are not necessarily sorted.
+fn next_memory_run(memory_map: &efi::MemoryMap, cursor: u64) -> Option<(u64, u64)> {
+    let ranges = || memory_map.iter().map(|descriptor| {
+        let end = descriptor.physical_start
+            + descriptor.number_of_pages * efi::memory::PAGE_SIZE as u64;
+        (descriptor.physical_start, end)
+    });
+
+    let start = ranges()
+        .filter(|&(_, end)| end > cursor)
+        .map(|(start, _)| start.max(cursor))
+        .min()?;
+
+    let mut end = start;
+    while let Some(next) = ranges()
+        .filter(|&(range_start, range_end)| range_start <= end && range_end > end)
+        .map(|(_, range_end)| range_end)
+        .max()
+    {
+        end = next;
+    }
+
+    Some((start, end))
+}
+
+/// Responses, allocated up front so the memory map can be added after
+/// exiting boot services.
+struct ResponseBuffer {
+    buffer: &'static mut [u8],
+    size: usize,
+    hhdm_offset: u64,
+}
+
+impl ResponseBuffer {
+    /// Reserve `size` zeroed bytes, 8 byte aligned, returning their offset.
+    fn add(&mut self, size: usize) -> Result<usize, LimineError> {
+        let offset = self.size;
+        let end = (offset + size + 7) & !7;
+        if end > self.buffer.len() {
+            return Err(LimineError::BufferOverflow);
+        }
+
+        self.size = end;
+        Ok(offset)
+    }
+
+    /// Add a NUL terminated copy of `string`, returning its address.
+    fn add_string(&mut self, string: &str) -> Result<u64, LimineError> {
+        let offset = self.add(string.len() + 1)?;
+        self.buffer[offset..(offset + string.len())].copy_from_slice(string.as_bytes());
+
+        Ok(self.address(offset))
+    }
+
+    fn add_framebuffer(&mut self, framebuffer: &Framebuffer) -> Result<usize, LimineError> {
+        // size and position of each color field
+        let field = |mask: u32| [mask.count_ones() as u8, mask.trailing_zeros() as u8 & 31];
+
+        let response = self.add(24)?;
+        let entry = self.add(FRAMEBUFFER_SIZE)?;
+        let pointers = self.add(8)?;
+
+        self.write_u64(entry, framebuffer.address + self.hhdm_offset);
+        self.write_u64(entry + 8, framebuffer.width as u64);
+        self.write_u64(entry + 16, framebuffer.height as u64);
+        self.write_u64(entry + 24, framebuffer.stride as u64);
+        let bytes = &mut self.buffer[(entry + 32)..(entry + 41)];
+        bytes[0..2].copy_from_slice(&(framebuffer.bits_per_pixel as u16).to_le_bytes());
+        // Limine only defines the RGB memory model. The label also holds
+        // for BGR and bitmask layouts, as the masks below are taken from the
+        // actual pixel format rather than assumed from it.
+        bytes[2] = FRAMEBUFFER_RGB;
+        bytes[3..5].copy_from_slice(&field(framebuffer.red_mask));
+        bytes[5..7].copy_from_slice(&field(framebuffer.green_mask));
+        bytes[7..9].copy_from_slice(&field(framebuffer.blue_mask));
+
+        self.write_u64(pointers, self.address(entry));
+        self.write_u64(response + 8, 1);
+        self.write_u64(response + 16, self.address(pointers));
+
+        Ok(response)
+    }
+
+    fn add_modules(&mut self, modules: &[Module]) -> Result<usize, LimineError> {
+        let response = self.add(24)?;
+        let pointers = self.add(modules.len() * 8)?;
+
+        for (index, module) in modules.iter().enumerate() {
+            let file = self.add(FILE_SIZE)?;
+            let path = self.add_string(module.path)?;
+            let cmdline = self.add_string(module.cmdline)?;
+
+            self.write_u64(file + 8, module.data.as_ptr() as u64 + self.hhdm_offset);
+            self.write_u64(file + 16, module.data.len() as u64);
+            self.write_u64(file + 24, path);
+            self.write_u64(file + 32, cmdline);
+            self.write_u64(pointers + index * 8, self.address(file));
+        }
+
+        self.write_u64(response + 8, modules.len() as u64);
+        self.write_u64(response + 16, self.address(pointers));
+
+        Ok(response)
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        read_u64(self.buffer, offset).unwrap_or(0)
+    }
+
+    fn write_u64(&mut self, offset: usize, value: u64) {
+        self.buffer[offset..(offset + 8)].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn physical(&self, offset: usize) -> u64 {
+        self.buffer.as_ptr() as u64 + offset as u64
+    }
+
+    /// Address of the data at `offset` as the kernel sees it.
+    fn address(&self, offset: usize) -> u64 {
+        self.physical(offset) + self.hhdm_offset
+    }
+}
+
+/// Start of the higher half, where physical memory is direct mapped.
+fn hhdm_offset(mode: paging::Mode) -> u64 {
+    !((1u64 << (mode.address_bits() - 1)) - 1)
+}
+
+fn paging_mode_value(mode: paging::Mode) -> u64 {
+    match mode {
+        paging::Mode::Sv39 => PAGING_MODE_SV39,
+        paging::Mode::Sv48 => PAGING_MODE_SV48,
+        paging::Mode::Sv57 => PAGING_MODE_SV57,
+    }
+}
+
+/// Memory map type of a region. Everything Maia allocated, including
+/// firmware boot services memory, is bootloader reclaimable.
+fn memory_type(kind: RegionKind) -> u64 {
+    match kind {
+        RegionKind::Usable => MEMMAP_USABLE,
+        RegionKind::Reclaimable | RegionKind::BootInfo | RegionKind::KernelStack =>
+            MEMMAP_BOOTLOADER_RECLAIMABLE,
+        RegionKind::AcpiReclaim => MEMMAP_ACPI_RECLAIMABLE,
+        RegionKind::AcpiNvs => MEMMAP_ACPI_NVS,
+        RegionKind::Kernel | RegionKind::Initrd => MEMMAP_EXECUTABLE_AND_MODULES,
+        RegionKind::Mmio | RegionKind::Reserved | RegionKind::FirmwareRuntime => MEMMAP_RESERVED,
+    }
+}
+
+/// Page aligned physical range of the framebuffer.
+fn page_range(framebuffer: &Framebuffer) -> (u64, u64) {
+    let page_size = paging::PAGE_SIZE;
+    let start = framebuffer.address & !(page_size - 1);
+    let end = (framebuffer.address + framebuffer.size + page_size - 1) & !(page_size - 1);
+    (start, end)
+}
+
+fn read_magic(data: &[u8], offset: usize) -> Option<[u64; 2]> {
+    Some([read_u64(data, offset)?, read_u64(data, offset + 8)?])
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(data.get(offset..(offset + 8))?);
+    Some(u64::from_le_bytes(bytes))
+}
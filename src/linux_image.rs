@@ -0,0 +1,124 @@
+//! RISC-V Linux `Image` header, as described in the kernel's
+//! `Documentation/riscv/boot-image-header.rst`.
+//!
+//! An Image is a flat binary entered at its first byte, with the header
+//! embedded in the first 64 bytes. `load` places it in memory, ready to be
+//! entered with the Linux boot ABI.
+
+use super::{
+    boot::{self, Error, LoadedKernel},
+    efi,
+    kernel_format::KernelFormat,
+};
+
+pub const HEADER_SIZE: usize = 64;
+
+/// Images are placed `text_offset` bytes past an address aligned to this.
+pub const ALIGNMENT: usize = 2 * 1024 * 1024;
+
+const MAGIC: &[u8] = b"RSC\x05";
+const MAGIC_OFFSET: usize = 0x38;
+
+/// Set for big endian kernels.
+const FLAG_BIG_ENDIAN: u64 = 1 << 0;
+
+#[derive(Debug)]
+pub enum LinuxImageError {
+    InvalidFormat,
+    BigEndian,
+}
+
+impl core::fmt::Display for LinuxImageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            LinuxImageError::InvalidFormat => write!(f, "invalid Image header"),
+            LinuxImageError::BigEndian => write!(f, "big endian kernel"),
+        }
+    }
+}
+
+pub struct LinuxImage {
+    text_offset: u64,
+    image_size: u64,
+    version: u32,
+}
+
+impl LinuxImage {
+    /// Check for the header magic at the start of `data`.
+    pub fn is_linux_image(data: &[u8]) -> bool {
+        data.get(MAGIC_OFFSET..(MAGIC_OFFSET + MAGIC.len())) == Some(MAGIC)
+    }
+
+    /// Parse the header at the start of `data`.
+    pub fn from_header(data: &[u8]) -> Result<LinuxImage, LinuxImageError> {
+        if data.len() < HEADER_SIZE || !LinuxImage::is_linux_image(data) {
+            return Err(LinuxImageError::InvalidFormat);
+        }
+
+        let flags = read_u64_le(data, 24);
+        if flags & FLAG_BIG_ENDIAN != 0 {
+            return Err(LinuxImageError::BigEndian);
+        }
+
+        Ok(LinuxImage {
+            text_offset: read_u64_le(data, 8),
+            image_size: read_u64_le(data, 16),
+            version: u32::from_le_bytes([data[32], data[33], data[34], data[35]]),
+        })
+    }
+
+    /// Offset of the image from a 2 MiB aligned address.
+    pub fn text_offset(&self) -> usize {
+        self.text_offset as usize
+    }
+
+    /// Memory needed by the kernel, including space past the end of the
+    /// file. Zero in images too old to record it.
+    pub fn image_size(&self) -> usize {
+        self.image_size as usize
+    }
+
+    /// Major and minor version of the header.
+    pub fn version(&self) -> (u16, u16) {
+        ((self.version >> 16) as u16, self.version as u16)
+    }
+}
+
+/// Load a Linux Image of `file_size` bytes, provided by
+/// `read(file_offset, destination)`, at a 2 MiB aligned address plus its
+/// text offset.
+pub fn load<F>(image: &LinuxImage, file_size: usize, mut read: F) -> Result<LoadedKernel, Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    let (major, minor) = image.version();
+    debug!("Linux Image header version {}.{}", major, minor);
+    debug!("Text offset: {:#x}, image size: {:#x}", image.text_offset(), image.image_size());
+
+    // the image size covers the kernel's memory beyond the file, like bss
+    let size = image.image_size().max(file_size);
+    let kernel_buffer = efi::allocate_pages_aligned(
+        efi::memory::LOADER_CODE,
+        efi::memory::page_count(size),
+        ALIGNMENT,
+        image.text_offset() % ALIGNMENT,
+    ).map_err(|_| Error::MemoryAllocationFailed)?;
+
+    if let Err(error) = read(0, &mut kernel_buffer[..file_size]) {
+        efi::free_pages(kernel_buffer);
+        return Err(error);
+    }
+
+    boot::synchronize_instruction_cache();
+
+    let entry_point = kernel_buffer.as_ptr() as *const core::ffi::c_void;
+    debug!("Kernel entry point in memory: {:#018X}", entry_point as usize);
+
+    Ok(LoadedKernel::new(KernelFormat::LinuxImage, entry_point, kernel_buffer))
+}
+
+fn read_u64_le(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..(offset + 8)]);
+    u64::from_le_bytes(bytes)
+}
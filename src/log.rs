@@ -0,0 +1,187 @@
+//! Leveled logging to the boot console.
+//!
+//! Messages carry a target naming the subsystem they originate from
+//! (e.g. `elf`, `mmap`, `reloc`). The maximum level is chosen at runtime
+//! through the `log` option, either globally or per target:
+//!
+//! ```text
+//! log=debug
+//! log=warn,elf=trace,mmap=debug
+//! ```
+
+use super::{console, global::Global};
+
+pub const DEFAULT_TARGET: &str = "maia";
+
+const MAX_TARGET_FILTERS: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn from_str(name: &str) -> Option<Option<Level>> {
+        match name {
+            "off" => Some(None),
+            "error" => Some(Some(Level::Error)),
+            "warn" => Some(Some(Level::Warn)),
+            "info" => Some(Some(Level::Info)),
+            "debug" => Some(Some(Level::Debug)),
+            "trace" => Some(Some(Level::Trace)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Filter {
+    default: Option<Level>,
+    targets: [(&'static str, Option<Level>); MAX_TARGET_FILTERS],
+    target_count: usize,
+}
+
+const DEFAULT_FILTER: Filter = Filter {
+    default: Some(Level::Info),
+    targets: [("", None); MAX_TARGET_FILTERS],
+    target_count: 0,
+};
+
+static FILTER: Global<Filter> = Global::new(DEFAULT_FILTER);
+
+/// Apply a filter specification such as `info,elf=trace`, replacing any
+/// previously applied one. An empty specification restores the defaults.
+///
+/// Returns `false` if any part of the specification was not understood;
+/// all valid parts are applied regardless.
+pub fn configure(spec: &'static str) -> bool {
+    let mut filter = DEFAULT_FILTER;
+    let mut valid = true;
+
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        match directive.find('=') {
+            None => match Level::from_str(directive) {
+                Some(level) => filter.default = level,
+                None => valid = false,
+            },
+            Some(separator) => {
+                let target = &directive[..separator];
+                let level = Level::from_str(&directive[(separator + 1)..]);
+
+                match level {
+                    Some(level) if filter.target_count < MAX_TARGET_FILTERS => {
+                        filter.targets[filter.target_count] = (target, level);
+                        filter.target_count += 1;
+                    },
+                    _ => valid = false,
+                }
+            },
+        }
+    }
+
+    FILTER.set(filter);
+    valid
+}
+
+pub fn enabled(level: Level, target: &str) -> bool {
+    let filter = FILTER.get();
+
+    let max_level = filter.targets[..filter.target_count].iter()
+        .rev()
+        .find(|&&(filter_target, _)| filter_target == target)
+        .map(|&(_, level)| level)
+        .unwrap_or(filter.default);
+
+    match max_level {
+        Some(max_level) => level <= max_level,
+        None => false,
+    }
+}
+
+pub fn log(level: Level, target: &str, args: core::fmt::Arguments) {
+    if !enabled(level, target) {
+        return;
+    }
+
+    let style = match level {
+        Level::Error => console::Style::Error,
+        Level::Warn => console::Style::Warning,
+        _ => console::Style::Normal,
+    };
+
+    console::set_style(style);
+    if level != Level::Info {
+        console::write_fmt(format_args!("[{}] {}: ", level.name(), target));
+    }
+    console::write_fmt(args);
+    console::set_style(console::Style::Normal);
+    console::write_str("\r\n");
+}
+
+macro_rules! log {
+    (target: $target:expr, $level:expr, $($arg:tt)+) => {
+        $crate::log::log($level, $target, format_args!($($arg)+))
+    };
+    ($level:expr, $($arg:tt)+) => {
+        log!(target: $crate::log::DEFAULT_TARGET, $level, $($arg)+)
+    };
+}
+
+macro_rules! error {
+    (target: $target:expr, $($arg:tt)+) => {
+        log!(target: $target, $crate::log::Level::Error, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        log!($crate::log::Level::Error, $($arg)+)
+    };
+}
+
+macro_rules! warn {
+    (target: $target:expr, $($arg:tt)+) => {
+        log!(target: $target, $crate::log::Level::Warn, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        log!($crate::log::Level::Warn, $($arg)+)
+    };
+}
+
+macro_rules! info {
+    (target: $target:expr, $($arg:tt)+) => {
+        log!(target: $target, $crate::log::Level::Info, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        log!($crate::log::Level::Info, $($arg)+)
+    };
+}
+
+macro_rules! debug {
+    (target: $target:expr, $($arg:tt)+) => {
+        log!(target: $target, $crate::log::Level::Debug, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        log!($crate::log::Level::Debug, $($arg)+)
+    };
+}
+
+macro_rules! trace {
+    (target: $target:expr, $($arg:tt)+) => {
+        log!(target: $target, $crate::log::Level::Trace, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        log!($crate::log::Level::Trace, $($arg)+)
+    };
+}
@@ -0,0 +1,91 @@
+//! Boot log ring buffer, handed over to the kernel.
+//!
+//! The buffer is a single `EfiLoaderData` allocation starting with a
+//! `Header`, followed by `capacity` bytes of log data.
+
+use super::efi;
+
+pub const MAGIC: u64 = u64::from_le_bytes(*b"MAIALOG\0");
+
+pub const DEFAULT_SIZE: usize = 64 * 1024;
+
+#[repr(C)]
+pub struct Header {
+    pub magic: u64,
+    /// Size of the data area following the header, in bytes.
+    pub capacity: u64,
+    /// Total number of bytes ever written. Once this exceeds `capacity`,
+    /// the oldest data starts at `written % capacity`.
+    pub written: u64,
+}
+
+pub struct LogBuffer {
+    header: &'static mut Header,
+    data: &'static mut [u8],
+}
+
+impl LogBuffer {
+    /// Allocate a buffer occupying `size` bytes (rounded up to whole pages).
+    pub fn allocate(size: usize) -> Result<LogBuffer, efi::Status> {
+        let header_size = core::mem::size_of::<Header>();
+        let page_count = efi::memory::page_count(size.max(header_size + 1));
+
+        let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, page_count)?;
+        let (header, data) = buffer.split_at_mut(header_size);
+
+        let header = unsafe { &mut *(header.as_mut_ptr() as *mut Header) };
+        header.magic = MAGIC;
+        header.capacity = data.len() as u64;
+        header.written = 0;
+
+        Ok(LogBuffer { header, data })
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let offset = (self.header.written % self.header.capacity) as usize;
+            self.data[offset] = byte;
+            self.header.written += 1;
+        }
+    }
+
+    /// Logged data in chronological order, split in two at the wrap around point.
+    pub fn contents(&self) -> (&[u8], &[u8]) {
+        let capacity = self.header.capacity;
+        if self.header.written <= capacity {
+            return (&self.data[..(self.header.written as usize)], &[]);
+        }
+
+        let (newer, older) = self.data.split_at((self.header.written % capacity) as usize);
+        (older, newer)
+    }
+
+    /// Data logged after the first `position` bytes ever written, split
+    /// like `contents`. Whatever of it was overwritten since is left out.
+    pub fn contents_since(&self, position: u64) -> (&[u8], &[u8]) {
+        let (older, newer) = self.contents();
+
+        let available = (older.len() + newer.len()) as u64;
+        let skip = available.saturating_sub(self.header.written.saturating_sub(position)) as usize;
+        if skip <= older.len() {
+            (&older[skip..], newer)
+        } else {
+            (&newer[(skip - older.len())..], &[])
+        }
+    }
+
+    /// Total number of bytes ever written.
+    pub fn written(&self) -> u64 {
+        self.header.written
+    }
+
+    /// Physical address of the buffer, including the header.
+    pub fn address(&self) -> u64 {
+        self.header as *const Header as u64
+    }
+
+    /// Size of the buffer in bytes, including the header.
+    pub fn size(&self) -> u64 {
+        (core::mem::size_of::<Header>() + self.data.len()) as u64
+    }
+}
@@ -0,0 +1,124 @@
+//! Persist the boot log to a file on the boot volume.
+//!
+//! The log of each boot is appended to the file. Saving again during the
+//! same boot only appends what was logged since. Once the file would grow
+//! beyond its size limit, it is renamed to `<name>.old` (replacing any
+//! previous backup) and a new file is started.
+
+use super::{console, efi, global::Global, log_buffer::LogBuffer};
+
+pub const DEFAULT_PATH: &str = "\\EFI\\MercurOS\\maia.log";
+pub const DEFAULT_MAX_SIZE: usize = 256 * 1024;
+
+const BACKUP_SUFFIX: &str = ".old";
+const MAX_PATH_LENGTH: usize = 256;
+
+/// Size of the pieces the boot log is copied out in.
+const CHUNK_SIZE: usize = 1024;
+
+/// Position in the boot log up to which it was saved.
+static SAVED: Global<u64> = Global::new(0);
+
+/// Append the contents of the boot log buffer not saved yet to the file
+/// at `path`.
+///
+/// The log is copied out piece by piece and written outside of
+/// `console::with_log_buffer`, so that anything logged meanwhile, e.g.
+/// errors of the file protocol, still reaches the log. It is saved the
+/// next time.
+pub fn save(path: &str, max_size: usize) -> Result<(), efi::Status> {
+    let saved = SAVED.get();
+    let end = match console::with_log_buffer(LogBuffer::written) {
+        Some(end) if end > saved => end,
+        _ => return Ok(()),
+    };
+
+    let volume = efi::File::open_boot_volume()?;
+    let mut file = open(&volume, path, max_size, (end - saved) as usize)?;
+
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut position = saved;
+    while position < end {
+        let copied = console::with_log_buffer(|log_buffer| {
+            copy_chunk(log_buffer, position, end, &mut chunk)
+        });
+        let (start, length) = match copied {
+            Some((start, length)) if length > 0 => (start, length),
+            _ => break,
+        };
+
+        file.write_all(&chunk[..length])?;
+        position = start + length as u64;
+    }
+    file.flush()?;
+
+    SAVED.set(end);
+    Ok(())
+}
+
+/// Copy what was logged from `position` up to `end` into `chunk`, as much as
+/// fits. Returns where the copy starts, past `position` if some of it was
+/// overwritten since, and its length.
+fn copy_chunk(log_buffer: &LogBuffer, position: u64, end: u64, chunk: &mut [u8]) -> (u64, usize) {
+    let (older, newer) = log_buffer.contents_since(position);
+    let start = log_buffer.written() - (older.len() + newer.len()) as u64;
+    let length = chunk.len().min(end.saturating_sub(start) as usize);
+
+    let from_older = length.min(older.len());
+    chunk[..from_older].copy_from_slice(&older[..from_older]);
+    chunk[from_older..length].copy_from_slice(&newer[..(length - from_older)]);
+
+    (start, length)
+}
+
+/// Open the file at `path` to append `length` bytes, starting a new one if
+/// it would grow beyond `max_size`.
+fn open(
+    volume: &efi::File,
+    path: &str,
+    max_size: usize,
+    length: usize,
+) -> Result<efi::File, efi::Status> {
+    let mode = efi::file::FILE_MODE_READ | efi::file::FILE_MODE_WRITE | efi::file::FILE_MODE_CREATE;
+
+    let mut file = volume.open(path, mode)?;
+    let size = file.size()? as usize;
+    if size > 0 && size + length > max_size {
+        rotate(volume, file, path)?;
+        file = volume.open(path, mode)?;
+    }
+
+    file.set_position(u64::MAX)?;
+    Ok(file)
+}
+
+fn rotate(volume: &efi::File, mut file: efi::File, path: &str) -> Result<(), efi::Status> {
+    let mut path_buffer = [0u8; MAX_PATH_LENGTH];
+    let backup_path = concat(&mut path_buffer, path, BACKUP_SUFFIX)
+        .ok_or(efi::status::INVALID_PARAMETER)?;
+
+    match volume.open(backup_path, efi::file::FILE_MODE_READ | efi::file::FILE_MODE_WRITE) {
+        Ok(backup) => backup.delete()?,
+        Err(efi::status::NOT_FOUND) => (),
+        Err(status) => return Err(status),
+    }
+
+    // SetInfo expects the new name relative to the containing directory
+    let backup_name = backup_path.rsplit(|c| c == '\\' || c == '/')
+        .next()
+        .unwrap_or(backup_path);
+
+    file.rename(backup_name)
+}
+
+fn concat<'a>(buffer: &'a mut [u8], a: &str, b: &str) -> Option<&'a str> {
+    let length = a.len() + b.len();
+    if length > buffer.len() {
+        return None;
+    }
+
+    buffer[..a.len()].copy_from_slice(a.as_bytes());
+    buffer[a.len()..length].copy_from_slice(b.as_bytes());
+
+    core::str::from_utf8(&buffer[..length]).ok()
+}
@@ -10,12 +10,39 @@ use core::ffi::c_void;
 
 use mercuros_uefi::{EfiHandle, EfiStatus, EfiSystemTable};
 
+#[macro_use]
+mod console;
+#[macro_use]
+mod log;
+
 pub mod assembly;
 pub mod kernel;
 
 mod boot;
+mod boot_info;
+mod chainload;
+mod compression;
+mod config;
+mod efi;
 mod elf;
+mod entry;
+mod fdt;
+mod firmware_tables;
+mod framebuffer;
+mod global;
+mod kernel_format;
+mod limine;
+mod linux_image;
+mod log_buffer;
+mod log_file;
+mod memory_map;
+mod menu;
+mod multiboot2;
+mod paging;
+mod pe;
 mod relocate;
+mod serial;
+mod string;
 
 #[no_mangle]
 pub extern "C" fn relocate(
@@ -31,6 +58,7 @@ pub extern "efiapi" fn efi_main(
     system_table: *mut EfiSystemTable,
 ) -> EfiStatus {
     let uefi = unsafe {
+        efi::init(image_handle as efi::Handle, system_table as *mut efi::SystemTable);
         mercuros_uefi::Application::from(image_handle, system_table)
     };
 
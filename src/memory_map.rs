@@ -0,0 +1,204 @@
+//! Physical memory map handed to the kernel.
+//!
+//! The UEFI memory map is translated into a compact array of regions,
+//! sorted by base address, with adjacent regions of the same kind merged.
+//! Memory allocated by Maia for the kernel is reported with a dedicated
+//! kind instead of the generic loader data type.
+
+use super::efi;
+
+/// Upper limit of ranges claimed by Maia.
+const MAX_CLAIMS: usize = 32;
+
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum RegionKind {
+    /// Free memory.
+    Usable = 1,
+    /// Memory used by firmware or Maia during boot, free once the kernel
+    /// no longer needs anything Maia handed over.
+    Reclaimable = 2,
+    AcpiReclaim = 3,
+    AcpiNvs = 4,
+    Mmio = 5,
+    Reserved = 6,
+    /// UEFI runtime services code and data.
+    FirmwareRuntime = 7,
+    /// The loaded kernel image.
+    Kernel = 8,
+    /// Initial ramdisk, or modules of Multiboot2 kernels.
+    Initrd = 9,
+    /// Boot information, memory maps, device tree and boot log.
+    BootInfo = 10,
+    /// The stack the kernel is entered with.
+    KernelStack = 11,
+}
+
+impl RegionKind {
+    fn from_efi(memory_type: efi::MemoryType) -> RegionKind {
+        use efi::memory::*;
+
+        match memory_type {
+            CONVENTIONAL_MEMORY => RegionKind::Usable,
+            LOADER_CODE | LOADER_DATA | BOOT_SERVICES_CODE | BOOT_SERVICES_DATA =>
+                RegionKind::Reclaimable,
+            RUNTIME_SERVICES_CODE | RUNTIME_SERVICES_DATA => RegionKind::FirmwareRuntime,
+            ACPI_RECLAIM_MEMORY => RegionKind::AcpiReclaim,
+            ACPI_MEMORY_NVS => RegionKind::AcpiNvs,
+            MEMORY_MAPPED_IO | MEMORY_MAPPED_IO_PORT_SPACE => RegionKind::Mmio,
+            _ => RegionKind::Reserved,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: RegionKind,
+    _reserved: u32,
+}
+
+impl MemoryRegion {
+    fn end(&self) -> u64 {
+        self.base + self.length
+    }
+}
+
+pub struct PhysicalMemoryMap {
+    regions: &'static mut [MemoryRegion],
+    count: usize,
+    claims: [(u64, u64, RegionKind); MAX_CLAIMS],
+    claim_count: usize,
+}
+
+impl PhysicalMemoryMap {
+    /// Number of regions derived from a UEFI memory map of `efi_capacity`
+    /// descriptors, plus those split off by claims, including the buffer
+    /// of the map itself.
+    pub fn capacity_for(efi_capacity: usize) -> usize {
+        efi_capacity + 2 * (MAX_CLAIMS + 1)
+    }
+
+    /// Allocate room for `capacity` regions.
+    pub fn allocate(capacity: usize) -> Result<PhysicalMemoryMap, efi::Status> {
+        let size = capacity * core::mem::size_of::<MemoryRegion>();
+        let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, efi::memory::page_count(size))?;
+
+        let regions = unsafe {
+            core::slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut MemoryRegion, capacity)
+        };
+
+        Ok(PhysicalMemoryMap {
+            regions,
+            count: 0,
+            claims: [(0, 0, RegionKind::Reserved); MAX_CLAIMS],
+            claim_count: 0,
+        })
+    }
+
+    /// Report a range allocated by Maia as `kind`, rounded to whole pages.
+    pub fn claim(&mut self, address: u64, size: u64, kind: RegionKind) {
+        if size == 0 || self.claim_count == MAX_CLAIMS {
+            return;
+        }
+
+        self.claims[self.claim_count] = page_range(address, size, kind);
+        self.claim_count += 1;
+    }
+
+    /// Location of the region array itself.
+    pub fn allocation(&self) -> (u64, u64) {
+        (
+            self.regions.as_ptr() as u64,
+            (self.regions.len() * core::mem::size_of::<MemoryRegion>()) as u64,
+        )
+    }
+
+    /// Translate the final UEFI memory map. Does not allocate, so it can
+    /// be used between fetching the memory map and exiting boot services.
+    ///
+    /// The buffer of `efi_map` is claimed here rather than up front, as it
+    /// is replaced if the map outgrows it.
+    pub fn build(&mut self, efi_map: &efi::MemoryMap) -> Result<(), efi::Status> {
+        let (efi_map_address, efi_map_size) = efi_map.allocation();
+        let mut claims = [(0, 0, RegionKind::Reserved); MAX_CLAIMS + 1];
+        claims[..self.claim_count].copy_from_slice(&self.claims[..self.claim_count]);
+        claims[self.claim_count] = page_range(efi_map_address, efi_map_size, RegionKind::BootInfo);
+        let claims = &mut claims[..(self.claim_count + 1)];
+        claims.sort_unstable_by_key(|&(start, _, _)| start);
+
+        self.count = 0;
+        for descriptor in efi_map.iter() {
+            let kind = RegionKind::from_efi(descriptor.r#type);
+            let start = descriptor.physical_start;
+            let end = start + descriptor.number_of_pages * efi::memory::PAGE_SIZE as u64;
+
+            // split the descriptor around claimed ranges
+            let mut cursor = start;
+            for &(claim_start, claim_end, claim_kind) in claims.iter() {
+                if claim_end <= cursor || claim_start >= end {
+                    continue;
+                }
+
+                if claim_start > cursor {
+                    self.push(cursor, claim_start, kind)?;
+                }
+                let claimed_end = claim_end.min(end);
+                self.push(claim_start.max(cursor), claimed_end, claim_kind)?;
+                cursor = claimed_end;
+            }
+
+            if cursor < end {
+                self.push(cursor, end, kind)?;
+            }
+        }
+
+        let regions = &mut self.regions[..self.count];
+        regions.sort_unstable_by_key(|region| region.base);
+
+        // merge adjacent regions of the same kind
+        let mut merged = 0;
+        for index in 0..self.count {
+            let region = self.regions[index];
+            if merged > 0 {
+                let last = &mut self.regions[merged - 1];
+                if last.kind == region.kind && last.end() == region.base {
+                    last.length += region.length;
+                    continue;
+                }
+            }
+
+            self.regions[merged] = region;
+            merged += 1;
+        }
+        self.count = merged;
+
+        Ok(())
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        &self.regions[..self.count]
+    }
+
+    fn push(&mut self, start: u64, end: u64, kind: RegionKind) -> Result<(), efi::Status> {
+        if self.count == self.regions.len() {
+            return Err(efi::status::BUFFER_TOO_SMALL);
+        }
+
+        self.regions[self.count] = MemoryRegion { base: start, length: end - start, kind, _reserved: 0 };
+        self.count += 1;
+
+        Ok(())
+    }
+}
+
+/// Page aligned range covering `size` bytes at `address`.
+fn page_range(address: u64, size: u64, kind: RegionKind) -> (u64, u64, RegionKind) {
+    let page_size = efi::memory::PAGE_SIZE as u64;
+    let start = address & !(page_size - 1);
+    let end = (address + size + page_size - 1) & !(page_size - 1);
+
+    (start, end, kind)
+}
@@ -0,0 +1,108 @@
+//! Boot menu on the firmware console.
+//!
+//! Lists the boot entries of the configuration file, to be chosen with the
+//! arrow keys and Enter or by their number. With a timeout, the highlighted
+//! entry is chosen once it expires unless a key is pressed first.
+
+use super::{config, efi, string};
+
+const MAX_ENTRIES: usize = 16;
+
+/// Interval at which the console is polled for key presses.
+const POLL_INTERVAL_US: usize = 10_000;
+
+/// Show the menu and wait for an entry to be chosen, starting out with the
+/// selected entry highlighted. `None` if there are no entries to choose
+/// from or no console to choose on.
+pub fn choose_entry(timeout_seconds: Option<usize>) -> Option<&'static str> {
+    let mut entries = [""; MAX_ENTRIES];
+    let mut count = 0;
+    for name in config::entries().take(MAX_ENTRIES) {
+        entries[count] = name;
+        count += 1;
+    }
+    if count == 0 {
+        return None;
+    }
+    let entries = &entries[..count];
+
+    let con_in = efi::con_in()?;
+    con_in.reset();
+
+    // waiting for input must not trip the boot manager's watchdog
+    efi::disable_watchdog();
+
+    let mut highlighted = config::entry()
+        .and_then(|entry| entries.iter().position(|&name| name == entry))
+        .unwrap_or(0);
+    let mut remaining_polls = timeout_seconds
+        .map(|seconds| seconds * (1_000_000 / POLL_INTERVAL_US));
+
+    draw(entries, highlighted, timeout_seconds);
+    loop {
+        let key = match con_in.read_key() {
+            Ok(key) => key,
+            Err(_) => {
+                match remaining_polls {
+                    Some(0) => return Some(entries[highlighted]),
+                    Some(polls) => remaining_polls = Some(polls - 1),
+                    None => (),
+                }
+                efi::stall(POLL_INTERVAL_US);
+                continue;
+            },
+        };
+
+        // any key stops the countdown
+        remaining_polls = None;
+
+        let digit = (key.unicode_char as u8 as char).to_digit(10);
+        match (key.scan_code, key.unicode_char, digit) {
+            (efi::text_input::SCAN_UP, _, _) => {
+                highlighted = highlighted.checked_sub(1).unwrap_or(count - 1);
+            },
+            (efi::text_input::SCAN_DOWN, _, _) => {
+                highlighted = (highlighted + 1) % count;
+            },
+            (_, efi::text_input::CHAR_CARRIAGE_RETURN, _) => {
+                return Some(entries[highlighted]);
+            },
+            (_, c, Some(digit)) if c < 0x80 && digit >= 1 && (digit as usize) <= count => {
+                return Some(entries[digit as usize - 1]);
+            },
+            _ => (),
+        }
+
+        draw(entries, highlighted, None);
+    }
+}
+
+fn draw(entries: &[&str], highlighted: usize, timeout_seconds: Option<usize>) {
+    let con_out = match efi::con_out() {
+        Some(con_out) => con_out,
+        None => return,
+    };
+
+    con_out.clear_screen();
+    con_out.write_str("MercurOS Maia Bootloader\r\n\r\n");
+
+    let mut buffer = [0u8; 128];
+    for (index, name) in entries.iter().enumerate() {
+        let marker = if index == highlighted { '>' } else { ' ' };
+        let line = if index < 9 {
+            string::format(&mut buffer, format_args!(" {} {}. {}\r\n", marker, index + 1, name))
+        } else {
+            string::format(&mut buffer, format_args!(" {}    {}\r\n", marker, name))
+        };
+        con_out.write_str(line.unwrap_or("\r\n"));
+    }
+
+    con_out.write_str("\r\nUp/Down and Enter or a number selects an entry.\r\n");
+    if let Some(seconds) = timeout_seconds {
+        let line = string::format(
+            &mut buffer,
+            format_args!("Booting {} in {} seconds.\r\n", entries[highlighted], seconds),
+        );
+        con_out.write_str(line.unwrap_or(""));
+    }
+}
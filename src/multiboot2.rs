@@ -0,0 +1,446 @@
+//! Multiboot2 boot information, for kernels embedding a Multiboot2 header.
+//!
+//! The specification only defines the machine state for x86 and MIPS. On
+//! RISC-V, the kernel is entered with the Maia entry ABI as usual, with the
+//! boot loader magic in `a3` and the address of the information structure
+//! in `a4`, see `entry`.
+//!
+//! Tag layouts follow GRUB's `multiboot2.h`, which kernels are built
+//! against, where it differs from the text of the specification.
+
+use super::{
+    boot::{self, Error, Module},
+    config, efi,
+    framebuffer::Framebuffer,
+    memory_map::{MemoryRegion, PhysicalMemoryMap, RegionKind},
+};
+
+/// Magic of the header in the kernel image.
+const HEADER_MAGIC: u32 = 0xe852_50d6;
+/// Magic passed to the kernel along with the information structure.
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+/// The header must be contained in this many bytes at the start of the
+/// image, 8 byte aligned.
+pub const SEARCH_SIZE: usize = 32 * 1024;
+const ALIGNMENT: usize = 8;
+
+const HEADER_SIZE: usize = 16;
+const TAG_HEADER_SIZE: usize = 8;
+
+/// Header tags
+const HEADER_TAG_END: u16 = 0;
+const HEADER_TAG_INFORMATION_REQUEST: u16 = 1;
+const HEADER_TAG_CONSOLE_FLAGS: u16 = 4;
+const HEADER_TAG_FRAMEBUFFER: u16 = 5;
+const HEADER_TAG_MODULE_ALIGN: u16 = 6;
+const HEADER_TAG_RELOCATABLE: u16 = 10;
+/// Header tags may be ignored by boot loaders not supporting them.
+const HEADER_TAG_OPTIONAL: u16 = 1 << 0;
+
+/// Information tags
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOT_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ELF_SECTIONS: u32 = 9;
+const TAG_EFI64_SYSTEM_TABLE: u32 = 12;
+
+/// Information tags Maia provides, and thus kernels may request.
+const SUPPORTED_TAGS: &[u32] = &[
+    TAG_CMDLINE,
+    TAG_BOOT_LOADER_NAME,
+    TAG_MODULE,
+    TAG_MEMORY_MAP,
+    TAG_FRAMEBUFFER,
+    TAG_ELF_SECTIONS,
+    TAG_EFI64_SYSTEM_TABLE,
+];
+
+/// Memory map entry types
+const MEMORY_AVAILABLE: u32 = 1;
+const MEMORY_RESERVED: u32 = 2;
+const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+const MEMORY_NVS: u32 = 4;
+const MEMORY_MAP_ENTRY_SIZE: usize = 24;
+
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// Space in the Multiboot2 information for the fixed size tags, on top of
+/// the variable parts.
+const INFO_HEADROOM: usize = 4096;
+
+#[derive(Debug)]
+pub enum Multiboot2Error {
+    InvalidHeader,
+    /// A header tag the kernel requires is not supported.
+    UnsupportedTag(u16),
+    /// An information tag the kernel requires is not provided.
+    UnsupportedRequest(u32),
+    /// A module lies above 4 GiB, beyond the 32 bit module addresses.
+    AddressOutOfRange,
+    BufferOverflow,
+}
+
+impl core::fmt::Display for Multiboot2Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Multiboot2Error::InvalidHeader => write!(f, "invalid Multiboot2 header"),
+            Multiboot2Error::UnsupportedTag(tag) => write!(f, "unsupported header tag {}", tag),
+            Multiboot2Error::UnsupportedRequest(tag) => write!(f, "unsupported information tag {}", tag),
+            Multiboot2Error::AddressOutOfRange => write!(f, "module above 4 GiB"),
+            Multiboot2Error::BufferOverflow => write!(f, "information structure too large"),
+        }
+    }
+}
+
+/// What Multiboot2 kernels get beyond the loaded image.
+pub struct Kernel {
+    pub elf_sections: Option<ElfSections>,
+}
+
+/// ELF section header table of a Multiboot2 kernel, with the addresses of
+/// the sections in memory, followed by the data of the sections that are
+/// not part of any segment, like symbol tables.
+pub struct ElfSections {
+    pub buffer: &'static mut [u8],
+    pub table_size: usize,
+    pub entry_size: usize,
+    pub names_index: usize,
+}
+
+/// Multiboot2 header found in a kernel image.
+pub struct Header {
+    /// Offset of the header in the image.
+    pub offset: usize,
+}
+
+impl Header {
+    /// Search the start of a kernel image for a header with a valid
+    /// checksum, and check that Maia can satisfy it.
+    pub fn find(image: &[u8]) -> Result<Option<Header>, Multiboot2Error> {
+        let end = image.len().min(SEARCH_SIZE);
+
+        let mut offset = 0;
+        while offset + HEADER_SIZE <= end {
+            if read_u32(image, offset) == Some(HEADER_MAGIC) {
+                let architecture = read_u32(image, offset + 4).unwrap_or(0);
+                let length = read_u32(image, offset + 8).unwrap_or(0);
+                let checksum = read_u32(image, offset + 12).unwrap_or(0);
+
+                let sum = HEADER_MAGIC
+                    .wrapping_add(architecture)
+                    .wrapping_add(length)
+                    .wrapping_add(checksum);
+                if sum == 0 {
+                    let header = image.get(offset..(offset + length as usize))
+                        .filter(|header| header.len() >= HEADER_SIZE)
+                        .ok_or(Multiboot2Error::InvalidHeader)?;
+                    check_header_tags(&header[HEADER_SIZE..])?;
+
+                    return Ok(Some(Header { offset }));
+                }
+            }
+
+            offset += ALIGNMENT;
+        }
+
+        Ok(None)
+    }
+}
+
+/// Check that every header tag the kernel does not mark optional is
+/// supported, including the information it requests.
+fn check_header_tags(mut tags: &[u8]) -> Result<(), Multiboot2Error> {
+    loop {
+        let tag_type = read_u16(tags, 0).ok_or(Multiboot2Error::InvalidHeader)?;
+        let flags = read_u16(tags, 2).ok_or(Multiboot2Error::InvalidHeader)?;
+        let size = read_u32(tags, 4).ok_or(Multiboot2Error::InvalidHeader)? as usize;
+        let tag = tags.get(..size)
+            .filter(|tag| tag.len() >= TAG_HEADER_SIZE)
+            .ok_or(Multiboot2Error::InvalidHeader)?;
+        let optional = flags & HEADER_TAG_OPTIONAL != 0;
+
+        match tag_type {
+            HEADER_TAG_END => return Ok(()),
+            HEADER_TAG_INFORMATION_REQUEST => {
+                for request in tag[TAG_HEADER_SIZE..].chunks_exact(4) {
+                    let request = u32::from_le_bytes([request[0], request[1], request[2], request[3]]);
+                    if !optional && !SUPPORTED_TAGS.contains(&request) {
+                        return Err(Multiboot2Error::UnsupportedRequest(request));
+                    }
+                }
+            },
+            // the framebuffer is passed as set up, modules are page aligned
+            // and relocatable kernels are placed by the ELF loader anyway
+            HEADER_TAG_CONSOLE_FLAGS
+            | HEADER_TAG_FRAMEBUFFER
+            | HEADER_TAG_MODULE_ALIGN
+            | HEADER_TAG_RELOCATABLE => {},
+            _ if optional => {},
+            _ => return Err(Multiboot2Error::UnsupportedTag(tag_type)),
+        }
+
+        let next = align(size);
+        if next >= tags.len() {
+            return Err(Multiboot2Error::InvalidHeader);
+        }
+        tags = &tags[next..];
+    }
+}
+
+/// Builds the information structure in a buffer allocated up front, so the
+/// memory map can be added after exiting boot services.
+pub struct InfoWriter {
+    buffer: &'static mut [u8],
+    size: usize,
+}
+
+impl InfoWriter {
+    /// `buffer` must be 8 byte aligned.
+    pub fn new(buffer: &'static mut [u8]) -> Result<InfoWriter, Multiboot2Error> {
+        if buffer.len() < TAG_HEADER_SIZE {
+            return Err(Multiboot2Error::BufferOverflow);
+        }
+
+        // total size and reserved field
+        Ok(InfoWriter { buffer, size: 8 })
+    }
+
+    /// Location of the buffer, as `(address, size)`.
+    pub fn allocation(&self) -> (u64, u64) {
+        (self.buffer.as_ptr() as u64, self.buffer.len() as u64)
+    }
+
+    /// Size of the memory map tag for up to `count` regions.
+    pub fn memory_map_size(count: usize) -> usize {
+        TAG_HEADER_SIZE + 8 + count * MEMORY_MAP_ENTRY_SIZE
+    }
+
+    pub fn add_cmdline(&mut self, cmdline: &str) -> Result<(), Multiboot2Error> {
+        self.add_string(TAG_CMDLINE, cmdline)
+    }
+
+    pub fn add_boot_loader_name(&mut self, name: &str) -> Result<(), Multiboot2Error> {
+        self.add_string(TAG_BOOT_LOADER_NAME, name)
+    }
+
+    /// Add a module loaded at `start`, ending before `end`.
+    pub fn add_module(&mut self, start: u64, end: u64, string: &str) -> Result<(), Multiboot2Error> {
+        if end > u32::MAX as u64 {
+            return Err(Multiboot2Error::AddressOutOfRange);
+        }
+
+        let tag = self.add_tag(TAG_MODULE, 8 + string.len() + 1)?;
+        tag[0..4].copy_from_slice(&(start as u32).to_le_bytes());
+        tag[4..8].copy_from_slice(&(end as u32).to_le_bytes());
+        tag[8..(8 + string.len())].copy_from_slice(string.as_bytes());
+
+        Ok(())
+    }
+
+    /// Add the physical memory map. Does not allocate, so it can be used
+    /// after exiting boot services.
+    pub fn add_memory_map(&mut self, regions: &[MemoryRegion]) -> Result<(), Multiboot2Error> {
+        let tag = self.add_tag(TAG_MEMORY_MAP, 8 + regions.len() * MEMORY_MAP_ENTRY_SIZE)?;
+        tag[0..4].copy_from_slice(&(MEMORY_MAP_ENTRY_SIZE as u32).to_le_bytes());
+
+        for (region, entry) in regions.iter().zip(tag[8..].chunks_exact_mut(MEMORY_MAP_ENTRY_SIZE)) {
+            entry[0..8].copy_from_slice(&region.base.to_le_bytes());
+            entry[8..16].copy_from_slice(&region.length.to_le_bytes());
+            entry[16..20].copy_from_slice(&memory_type(region.kind).to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    pub fn add_framebuffer(&mut self, framebuffer: &Framebuffer) -> Result<(), Multiboot2Error> {
+        // position and size of each color field
+        let field = |mask: u32| [mask.trailing_zeros() as u8 & 31, mask.count_ones() as u8];
+
+        let tag = self.add_tag(TAG_FRAMEBUFFER, 30)?;
+        tag[0..8].copy_from_slice(&framebuffer.address.to_le_bytes());
+        tag[8..12].copy_from_slice(&framebuffer.stride.to_le_bytes());
+        tag[12..16].copy_from_slice(&framebuffer.width.to_le_bytes());
+        tag[16..20].copy_from_slice(&framebuffer.height.to_le_bytes());
+        tag[20] = framebuffer.bits_per_pixel as u8;
+        tag[21] = FRAMEBUFFER_TYPE_RGB;
+        tag[24..26].copy_from_slice(&field(framebuffer.red_mask));
+        tag[26..28].copy_from_slice(&field(framebuffer.green_mask));
+        tag[28..30].copy_from_slice(&field(framebuffer.blue_mask));
+
+        Ok(())
+    }
+
+    /// Add the ELF section header table of the kernel, with the addresses
+    /// of the sections in memory.
+    pub fn add_elf_sections(
+        &mut self,
+        count: usize,
+        entry_size: usize,
+        names_index: usize,
+        headers: &[u8],
+    ) -> Result<(), Multiboot2Error> {
+        let tag = self.add_tag(TAG_ELF_SECTIONS, 12 + headers.len())?;
+        tag[0..4].copy_from_slice(&(count as u32).to_le_bytes());
+        tag[4..8].copy_from_slice(&(entry_size as u32).to_le_bytes());
+        tag[8..12].copy_from_slice(&(names_index as u32).to_le_bytes());
+        tag[12..].copy_from_slice(headers);
+
+        Ok(())
+    }
+
+    pub fn add_efi_system_table(&mut self, address: u64) -> Result<(), Multiboot2Error> {
+        let tag = self.add_tag(TAG_EFI64_SYSTEM_TABLE, 8)?;
+        tag.copy_from_slice(&address.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Terminate the structure, returning its address.
+    pub fn finish(mut self) -> Result<u64, Multiboot2Error> {
+        self.add_tag(TAG_END, 0)?;
+
+        let size = self.size as u32;
+        self.buffer[0..4].copy_from_slice(&size.to_le_bytes());
+        self.buffer[4..8].fill(0);
+
+        Ok(self.buffer.as_ptr() as u64)
+    }
+
+    fn add_string(&mut self, tag_type: u32, string: &str) -> Result<(), Multiboot2Error> {
+        let tag = self.add_tag(tag_type, string.len() + 1)?;
+        tag[..string.len()].copy_from_slice(string.as_bytes());
+
+        Ok(())
+    }
+
+    /// Append a tag with `size` bytes of zeroed contents, returned for the
+    /// caller to fill in.
+    fn add_tag(&mut self, tag_type: u32, size: usize) -> Result<&mut [u8], Multiboot2Error> {
+        let start = self.size;
+        let end = start + TAG_HEADER_SIZE + size;
+        if align(end) > self.buffer.len() {
+            return Err(Multiboot2Error::BufferOverflow);
+        }
+
+        self.buffer[start..align(end)].fill(0);
+        self.buffer[start..(start + 4)].copy_from_slice(&tag_type.to_le_bytes());
+        self.buffer[(start + 4)..(start + 8)].copy_from_slice(&((end - start) as u32).to_le_bytes());
+        self.size = align(end);
+
+        Ok(&mut self.buffer[(start + TAG_HEADER_SIZE)..end])
+    }
+}
+
+/// Allocate the Multiboot2 information structure and add everything but
+/// the memory map, which is only final once boot services are exited.
+pub fn build_info(
+    multiboot: &Kernel,
+    modules: &[Option<Module>],
+    framebuffer: Option<&Framebuffer>,
+    memory_map_capacity: usize,
+) -> Result<InfoWriter, Error> {
+    let cmdline = config::get("cmdline").unwrap_or("");
+    let modules = modules.iter().flatten();
+    let elf_sections = multiboot.elf_sections.as_ref();
+
+    let size = INFO_HEADROOM
+        + cmdline.len()
+        + modules.clone().map(|module| module.string.len()).sum::<usize>()
+        + elf_sections.map_or(0, |sections| sections.table_size)
+        + InfoWriter::memory_map_size(memory_map_capacity);
+    let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, efi::memory::page_count(size))
+        .map_err(|_| Error::MemoryAllocationFailed)?;
+
+    let mut writer = InfoWriter::new(buffer)?;
+    writer.add_boot_loader_name(boot::BOOT_LOADER_NAME)?;
+    writer.add_cmdline(cmdline)?;
+
+    for module in modules {
+        let start = module.data.as_ptr() as u64;
+        writer.add_module(start, start + module.data.len() as u64, module.string)?;
+    }
+
+    if let Some(framebuffer) = framebuffer {
+        writer.add_framebuffer(framebuffer)?;
+    }
+
+    if let Some(system_table) = efi::system_table() {
+        writer.add_efi_system_table(system_table as *const efi::SystemTable as u64)?;
+    }
+
+    if let Some(sections) = elf_sections {
+        writer.add_elf_sections(
+            sections.table_size / sections.entry_size,
+            sections.entry_size,
+            sections.names_index,
+            &sections.buffer[..sections.table_size],
+        )?;
+    }
+
+    Ok(writer)
+}
+
+/// Mark the memory holding the Multiboot2 information in the physical
+/// memory map.
+pub fn claim_memory(
+    memory_map: &mut PhysicalMemoryMap,
+    multiboot: &Kernel,
+    writer: &InfoWriter,
+) {
+    let (info_address, info_size) = writer.allocation();
+    memory_map.claim(info_address, info_size, RegionKind::BootInfo);
+
+    if let Some(sections) = multiboot.elf_sections.as_ref() {
+        memory_map.claim(sections.buffer.as_ptr() as u64, sections.buffer.len() as u64, RegionKind::BootInfo);
+    }
+}
+
+/// Add the final memory map to the Multiboot2 information and terminate
+/// it, returning its address. There is no failing the boot once boot
+/// services are exited, so the kernel is entered without it if it does not
+/// fit.
+pub fn finish_info(
+    mut writer: InfoWriter,
+    memory_map: &PhysicalMemoryMap,
+) -> Option<u64> {
+    let result = writer.add_memory_map(memory_map.regions())
+        .and_then(|_| writer.finish());
+
+    match result {
+        Ok(address) => Some(address),
+        Err(error) => {
+            error!("{}", Error::InvalidMultiboot(error));
+            None
+        },
+    }
+}
+
+/// Memory map type of a region. Everything Maia placed in memory for the
+/// kernel is reported as reserved, as are all kinds of I/O and firmware
+/// memory.
+fn memory_type(kind: RegionKind) -> u32 {
+    match kind {
+        RegionKind::Usable | RegionKind::Reclaimable => MEMORY_AVAILABLE,
+        RegionKind::AcpiReclaim => MEMORY_ACPI_RECLAIMABLE,
+        RegionKind::AcpiNvs => MEMORY_NVS,
+        _ => MEMORY_RESERVED,
+    }
+}
+
+fn align(size: usize) -> usize {
+    (size + ALIGNMENT - 1) & !(ALIGNMENT - 1)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..(offset + 2))
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..(offset + 4))
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
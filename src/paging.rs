@@ -0,0 +1,219 @@
+//! RISC-V page tables, for kernels entered with paging enabled.
+//!
+//! Tables are built in loader data pages while boot services are active.
+//! Mappings use the largest pages that fit, up to 1 GiB, the largest page
+//! size all modes share. All leaf entries have the accessed and dirty bits
+//! set, as harts without hardware updating of those bits fault otherwise.
+
+use super::efi;
+
+pub const READ: u64 = 1 << 1;
+pub const WRITE: u64 = 1 << 2;
+pub const EXECUTE: u64 = 1 << 3;
+
+const VALID: u64 = 1 << 0;
+const ACCESSED: u64 = 1 << 6;
+const DIRTY: u64 = 1 << 7;
+
+pub const PAGE_SIZE: u64 = 4096;
+const PAGE_SHIFT: u32 = 12;
+const INDEX_BITS: u32 = 9;
+const INDEX_MASK: u64 = (1 << INDEX_BITS) - 1;
+/// Physical page number field of an entry.
+const PPN_MASK: u64 = ((1 << 44) - 1) << 10;
+
+/// Level of the largest leaf entries used, 1 GiB pages.
+const MAX_LEAF_LEVEL: u32 = 2;
+
+/// Table pages allocated from firmware at a time.
+const POOL_PAGES: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Mode {
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl Mode {
+    /// Mode named by the `mmu-type` property of a hart's device tree node.
+    pub fn from_mmu_type(mmu_type: &str) -> Option<Mode> {
+        match mmu_type {
+            "riscv,sv39" => Some(Mode::Sv39),
+            "riscv,sv48" => Some(Mode::Sv48),
+            "riscv,sv57" => Some(Mode::Sv57),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Sv39 => "Sv39",
+            Mode::Sv48 => "Sv48",
+            Mode::Sv57 => "Sv57",
+        }
+    }
+
+    /// Width of virtual addresses.
+    pub fn address_bits(self) -> u32 {
+        PAGE_SHIFT + self.levels() * INDEX_BITS
+    }
+
+    fn levels(self) -> u32 {
+        match self {
+            Mode::Sv39 => 3,
+            Mode::Sv48 => 4,
+            Mode::Sv57 => 5,
+        }
+    }
+
+    fn satp_mode(self) -> u64 {
+        match self {
+            Mode::Sv39 => 8,
+            Mode::Sv48 => 9,
+            Mode::Sv57 => 10,
+        }
+    }
+
+    /// Whether the bits above the virtual address width are copies of the
+    /// highest bit, as the hardware requires.
+    fn is_canonical(self, address: u64) -> bool {
+        let shift = 64 - self.address_bits();
+        (((address << shift) as i64) >> shift) as u64 == address
+    }
+}
+
+pub struct PageTable {
+    mode: Mode,
+    root: u64,
+    /// Zeroed pages not used for tables yet.
+    pool: &'static mut [u8],
+    table_count: usize,
+}
+
+impl PageTable {
+    pub fn new(mode: Mode) -> Result<PageTable, efi::Status> {
+        let mut page_table = PageTable {
+            mode,
+            root: 0,
+            pool: &mut [],
+            table_count: 0,
+        };
+        page_table.root = page_table.allocate_table()?;
+
+        Ok(page_table)
+    }
+
+    /// Number of table pages in use.
+    pub fn table_count(&self) -> usize {
+        self.table_count
+    }
+
+    /// Value of `satp` selecting these tables, with ASID zero.
+    pub fn satp(&self) -> u64 {
+        (self.mode.satp_mode() << 60) | (self.root >> PAGE_SHIFT)
+    }
+
+    /// Map `size` bytes at `virtual_address` to `physical_address`, all
+    /// page aligned, with the permissions in `flags`.
+    ///
+    /// Parts already mapped to the same physical memory are left as they
+    /// are, so overlapping ranges can be mapped. Mapping an address to
+    /// different memory fails with `INVALID_PARAMETER`, as do addresses
+    /// beyond the virtual address width.
+    pub fn map(
+        &mut self,
+        virtual_address: u64,
+        physical_address: u64,
+        size: u64,
+        flags: u64,
+    ) -> Result<(), efi::Status> {
+        if (virtual_address | physical_address | size) & (PAGE_SIZE - 1) != 0 || size == 0 {
+            return Err(efi::status::INVALID_PARAMETER);
+        }
+
+        let last = virtual_address.checked_add(size - 1).ok_or(efi::status::INVALID_PARAMETER)?;
+        if !self.mode.is_canonical(virtual_address) || !self.mode.is_canonical(last) {
+            return Err(efi::status::INVALID_PARAMETER);
+        }
+        // the range must not cross the hole between the two halves
+        if (virtual_address ^ last) >> 63 != 0 {
+            return Err(efi::status::INVALID_PARAMETER);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            offset += self.map_page(
+                virtual_address + offset,
+                physical_address + offset,
+                size - offset,
+                flags,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Map the largest page possible at `virtual_address`, returning the
+    /// number of bytes mapped.
+    fn map_page(
+        &mut self,
+        virtual_address: u64,
+        physical_address: u64,
+        remaining: u64,
+        flags: u64,
+    ) -> Result<u64, efi::Status> {
+        let mut table = self.root;
+
+        for level in (0..self.mode.levels()).rev() {
+            let shift = PAGE_SHIFT + level * INDEX_BITS;
+            let page_size = 1u64 << shift;
+            let index = ((virtual_address >> shift) & INDEX_MASK) as usize;
+            let entry = unsafe { &mut *(table as *mut u64).add(index) };
+
+            if *entry & VALID != 0 {
+                if *entry & (READ | WRITE | EXECUTE) != 0 {
+                    // already mapped by a page of this size
+                    let offset = virtual_address & (page_size - 1);
+                    if entry_address(*entry) + offset != physical_address {
+                        return Err(efi::status::INVALID_PARAMETER);
+                    }
+                    return Ok((page_size - offset).min(remaining));
+                }
+
+                table = entry_address(*entry);
+                continue;
+            }
+
+            let aligned = (virtual_address | physical_address) & (page_size - 1) == 0;
+            if level <= MAX_LEAF_LEVEL && aligned && remaining >= page_size {
+                *entry = (physical_address >> 2) | flags | VALID | ACCESSED | DIRTY;
+                return Ok(page_size);
+            }
+
+            let next = self.allocate_table()?;
+            *entry = (next >> 2) | VALID;
+            table = next;
+        }
+
+        // a 4 KiB page always fits
+        Err(efi::status::INVALID_PARAMETER)
+    }
+
+    fn allocate_table(&mut self) -> Result<u64, efi::Status> {
+        if self.pool.is_empty() {
+            self.pool = efi::allocate_pages(efi::memory::LOADER_DATA, POOL_PAGES)?;
+        }
+
+        let pool = core::mem::replace(&mut self.pool, &mut []);
+        let (table, rest) = pool.split_at_mut(PAGE_SIZE as usize);
+        self.pool = rest;
+        self.table_count += 1;
+
+        Ok(table.as_ptr() as u64)
+    }
+}
+
+fn entry_address(entry: u64) -> u64 {
+    (entry & PPN_MASK) << 2
+}
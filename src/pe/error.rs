@@ -0,0 +1,29 @@
+#[derive(Debug)]
+pub enum PeError {
+    InvalidFormat,
+    IncompatibleMachine,
+    BufferOverflow,
+    InvalidSection,
+    InvalidRelocations,
+}
+
+impl core::fmt::Display for PeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PeError::InvalidFormat =>
+                    "invalid PE format",
+                PeError::IncompatibleMachine =>
+                    "incompatible machine type",
+                PeError::BufferOverflow =>
+                    "data out of bounds",
+                PeError::InvalidSection =>
+                    "section outside of the image",
+                PeError::InvalidRelocations =>
+                    "invalid base relocation table",
+            }
+        )
+    }
+}
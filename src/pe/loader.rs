@@ -0,0 +1,169 @@
+//! Loading PE kernels, to be entered with the Maia entry ABI.
+
+use super::{
+    boot::{self, Error, LoadedKernel},
+    efi,
+    kernel_format::KernelFormat,
+    BaseRelocations, PeError, PeFile, IMAGE_REL_BASED_ABSOLUTE, IMAGE_REL_BASED_DIR64, PROBE_SIZE,
+};
+
+/// Size limit for the headers of PE kernels loaded from the boot volume.
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+
+/// Read the headers of a PE file of `file_size` bytes, up to and including
+/// the section table, into newly allocated pages, returning them along
+/// with the size of the headers.
+pub fn read_headers<F>(file_size: usize, read: &mut F) -> Result<(&'static mut [u8], usize), Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    let mut probe = [0u8; PROBE_SIZE];
+    let probe = &mut probe[..PROBE_SIZE.min(file_size)];
+    read(0, probe)?;
+
+    let size = PeFile::headers_size(probe)?;
+    if size > MAX_HEADERS_SIZE || size > file_size {
+        return Err(Error::InvalidPe(PeError::BufferOverflow));
+    }
+
+    let buffer = efi::allocate_pages(efi::memory::LOADER_DATA, efi::memory::page_count(size))
+        .map_err(|_| Error::MemoryAllocationFailed)?;
+    read(0, &mut buffer[..size])?;
+
+    Ok((buffer, size))
+}
+
+/// Load the headers and sections of `kernel_pe` into newly allocated
+/// memory, with their file data provided by `read(file_offset, destination)`,
+/// and apply its base relocations. The kernel is entered with the Maia
+/// entry ABI, like ELF kernels.
+pub fn load<F>(kernel_pe: &PeFile, mut read: F) -> Result<LoadedKernel, Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    debug!(
+        target: "pe",
+        "Image base: {:#018x}, image size: {:#x}, section alignment: {:#x}",
+        kernel_pe.image_base(),
+        kernel_pe.image_size(),
+        kernel_pe.section_alignment(),
+    );
+
+    // images without base relocations only run at their image base
+    let page_count = efi::memory::page_count(kernel_pe.image_size());
+    let kernel_buffer = if kernel_pe.is_relocatable() {
+        efi::allocate_pages_aligned(
+            efi::memory::LOADER_CODE,
+            page_count,
+            kernel_pe.section_alignment().max(efi::memory::PAGE_SIZE),
+            0,
+        ).map_err(|_| Error::MemoryAllocationFailed)?
+    } else {
+        efi::allocate_pages_at(efi::memory::LOADER_CODE, kernel_pe.image_base(), page_count)
+            .map_err(|status| {
+                let start = kernel_pe.image_base();
+                let end = start + kernel_pe.image_size() as u64;
+                error!(
+                    target: "pe",
+                    "Unable to allocate kernel at {:#018x}-{:#018x}: {:#x}",
+                    start,
+                    end,
+                    status,
+                );
+                boot::report_unavailable_range(start, end);
+
+                Error::KernelRangeUnavailable
+            })?
+    };
+
+    if let Err(error) = place(kernel_pe, kernel_buffer, &mut read) {
+        efi::free_pages(kernel_buffer);
+        return Err(error);
+    }
+
+    boot::synchronize_instruction_cache();
+
+    let entry_point = kernel_buffer[kernel_pe.entry_point()..].as_ptr() as *const core::ffi::c_void;
+    debug!(target: "pe", "Kernel entry point in memory: {:#018X}", entry_point as usize);
+
+    Ok(LoadedKernel::new(KernelFormat::Pe, entry_point, kernel_buffer))
+}
+
+/// Copy the headers and sections of `kernel_pe` into `image`, zero filled
+/// memory of its image size, and relocate them.
+fn place<F>(kernel_pe: &PeFile, image: &mut [u8], read: &mut F) -> Result<(), Error>
+where
+    F: FnMut(usize, &mut [u8]) -> Result<(), Error>,
+{
+    read(0, &mut image[..kernel_pe.size_of_headers()])?;
+
+    for section in kernel_pe.sections() {
+        let section = section?;
+        debug!(
+            target: "pe",
+            "Section {}: rva {:#010x}, size {:#x}, file offset {:#x}, file size {:#x}",
+            section.name(),
+            section.virtual_address,
+            section.virtual_size,
+            section.raw_data_offset,
+            section.raw_data_size,
+        );
+
+        // the sections were checked to lie within the image
+        let start = section.virtual_address as usize;
+        let size = section.loaded_size();
+        if size > 0 {
+            read(section.raw_data_offset as usize, &mut image[start..(start + size)])?;
+        }
+    }
+
+    let delta = (image.as_ptr() as u64).wrapping_sub(kernel_pe.image_base());
+    let directory = kernel_pe.base_relocations();
+    if delta == 0 || directory.size == 0 {
+        return Ok(());
+    }
+
+    debug!(target: "reloc", "Applying base relocations");
+
+    let start = directory.virtual_address as usize;
+    let end = start.checked_add(directory.size as usize)
+        .filter(|&end| end <= image.len())
+        .ok_or(PeError::InvalidRelocations)?;
+
+    // the table is part of the image being patched: it is read through a
+    // shared slice, and relocations must not write to it
+    let image_size = image.len();
+    let image_address = image.as_mut_ptr();
+    let table = unsafe { core::slice::from_raw_parts(image_address.add(start), end - start) };
+    let relocations = BaseRelocations::new(table)?;
+
+    for relocation in relocations {
+        trace!(target: "reloc", "BASE [{}] {:#010x}", relocation.kind, relocation.address);
+
+        match relocation.kind {
+            IMAGE_REL_BASED_ABSOLUTE => {},
+            IMAGE_REL_BASED_DIR64 => {
+                let offset = relocation.address as usize;
+                if offset.saturating_add(8) > image_size {
+                    error!(target: "reloc", "Relocation outside of the image at {:#010x}", offset);
+                    return Err(Error::InvalidKernelImage);
+                }
+                if offset < end && offset + 8 > start {
+                    error!(target: "reloc", "Relocation within the relocation table at {:#010x}", offset);
+                    return Err(Error::InvalidKernelImage);
+                }
+
+                unsafe {
+                    let word = image_address.add(offset) as *mut u64;
+                    word.write_unaligned(word.read_unaligned().wrapping_add(delta));
+                }
+            },
+            kind => {
+                error!(target: "reloc", "Unsupported base relocation type {}", kind);
+                return Err(Error::InvalidKernelImage);
+            },
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! PE32+ images, as used for EFI applications and EFI stub kernels, and
+//! loading them as kernels.
+
+mod error;
+mod loader;
+mod pe_file;
+mod relocation;
+
+use super::{boot, efi, kernel_format};
+
+pub use self::{
+    error::PeError,
+    loader::{load, read_headers},
+    pe_file::{PeFile, MAGIC, PROBE_SIZE},
+    relocation::{BaseRelocations, IMAGE_REL_BASED_ABSOLUTE, IMAGE_REL_BASED_DIR64},
+};
+
+fn read_u16_le(data: &[u8], offset: usize) -> Result<u16, PeError> {
+    data.get(offset..(offset + 2))
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(PeError::BufferOverflow)
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, PeError> {
+    data.get(offset..(offset + 4))
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(PeError::BufferOverflow)
+}
+
+fn read_u64_le(data: &[u8], offset: usize) -> Result<u64, PeError> {
+    let low = read_u32_le(data, offset)? as u64;
+    let high = read_u32_le(data, offset + 4)? as u64;
+    Ok(low | (high << 32))
+}
@@ -0,0 +1,238 @@
+use super::{read_u16_le, read_u32_le, read_u64_le, PeError};
+
+/// Magic of the DOS header every PE image starts with.
+pub static MAGIC: [u8; 2] = *b"MZ";
+
+/// Number of leading bytes that `PeFile::headers_size` usually needs,
+/// enough for the DOS stubs of common linkers.
+pub const PROBE_SIZE: usize = 1024;
+
+const PE_SIGNATURE: [u8; 4] = *b"PE\0\0";
+const IMAGE_FILE_MACHINE_RISCV64: u16 = 0x5064;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+
+/// The image has no base relocations and must be loaded at its image base.
+const IMAGE_FILE_RELOCS_STRIPPED: u16 = 0x0001;
+
+const DOS_HEADER_SIZE: usize = 64;
+const NT_HEADERS_OFFSET: usize = 0x3c;
+const FILE_HEADER_SIZE: usize = 20;
+/// Size of the PE32+ optional header without data directories.
+const OPTIONAL_HEADER_SIZE: usize = 112;
+const SECTION_HEADER_SIZE: usize = 40;
+const DATA_DIRECTORY_SIZE: usize = 8;
+
+const BASE_RELOCATION_DIRECTORY: usize = 5;
+
+#[derive(Clone, Copy, Default)]
+pub struct DataDirectory {
+    pub virtual_address: u32,
+    pub size: u32,
+}
+
+pub struct SectionHeader {
+    pub name: [u8; 8],
+    pub virtual_size: u32,
+    pub virtual_address: u32,
+    pub raw_data_size: u32,
+    pub raw_data_offset: u32,
+}
+
+impl SectionHeader {
+    fn parse(data: &[u8]) -> Result<SectionHeader, PeError> {
+        let mut name = [0u8; 8];
+        name.copy_from_slice(data.get(..8).ok_or(PeError::BufferOverflow)?);
+
+        Ok(SectionHeader {
+            name,
+            virtual_size: read_u32_le(data, 8)?,
+            virtual_address: read_u32_le(data, 12)?,
+            raw_data_size: read_u32_le(data, 16)?,
+            raw_data_offset: read_u32_le(data, 20)?,
+        })
+    }
+
+    /// Section name, up to the first NUL.
+    pub fn name(&self) -> &str {
+        let length = self.name.iter().position(|&byte| byte == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..length]).unwrap_or("?")
+    }
+
+    /// Number of bytes loaded from the file, the rest of the section
+    /// being zero filled.
+    pub fn loaded_size(&self) -> usize {
+        self.raw_data_size.min(self.virtual_size) as usize
+    }
+}
+
+pub struct PeFile<'a> {
+    raw_buffer: &'a [u8],
+    /// Size of the whole file, of which `raw_buffer` may only be the start.
+    file_size: usize,
+    characteristics: u16,
+    entry_point: u32,
+    image_base: u64,
+    section_alignment: u32,
+    image_size: u32,
+    headers_size: u32,
+    base_relocations: DataDirectory,
+    section_table: usize,
+    section_count: usize,
+}
+
+impl <'a> PeFile<'a> {
+    pub fn from_buffer(buffer: &'a [u8]) -> Result<PeFile<'a>, PeError> {
+        PeFile::from_headers(buffer, buffer.len())
+    }
+
+    /// Use the start of a file of `file_size` bytes, containing at least
+    /// all headers including the section table (see `headers_size`).
+    pub fn from_headers(buffer: &'a [u8], file_size: usize) -> Result<PeFile<'a>, PeError> {
+        let nt_headers = PeFile::nt_headers_offset(buffer)?;
+
+        let file_header = nt_headers + PE_SIGNATURE.len();
+        if read_u16_le(buffer, file_header)? != IMAGE_FILE_MACHINE_RISCV64 {
+            return Err(PeError::IncompatibleMachine);
+        }
+        let section_count = read_u16_le(buffer, file_header + 2)? as usize;
+        let optional_header_size = read_u16_le(buffer, file_header + 16)? as usize;
+        let characteristics = read_u16_le(buffer, file_header + 18)?;
+
+        let optional_header = file_header + FILE_HEADER_SIZE;
+        if optional_header_size < OPTIONAL_HEADER_SIZE {
+            return Err(PeError::InvalidFormat);
+        }
+        if read_u16_le(buffer, optional_header)? != PE32_PLUS_MAGIC {
+            return Err(PeError::IncompatibleMachine);
+        }
+
+        let directory_count = read_u32_le(buffer, optional_header + 108)? as usize;
+        let base_relocations = if directory_count > BASE_RELOCATION_DIRECTORY {
+            let offset = optional_header + OPTIONAL_HEADER_SIZE
+                + BASE_RELOCATION_DIRECTORY * DATA_DIRECTORY_SIZE;
+            if offset + DATA_DIRECTORY_SIZE > optional_header + optional_header_size {
+                return Err(PeError::InvalidFormat);
+            }
+
+            DataDirectory {
+                virtual_address: read_u32_le(buffer, offset)?,
+                size: read_u32_le(buffer, offset + 4)?,
+            }
+        } else {
+            DataDirectory::default()
+        };
+
+        let pe_file = PeFile {
+            raw_buffer: buffer,
+            file_size,
+            characteristics,
+            entry_point: read_u32_le(buffer, optional_header + 16)?,
+            image_base: read_u64_le(buffer, optional_header + 24)?,
+            section_alignment: read_u32_le(buffer, optional_header + 32)?,
+            image_size: read_u32_le(buffer, optional_header + 56)?,
+            headers_size: read_u32_le(buffer, optional_header + 60)?,
+            base_relocations,
+            section_table: optional_header + optional_header_size,
+            section_count,
+        };
+
+        if pe_file.section_table + section_count * SECTION_HEADER_SIZE > buffer.len() {
+            return Err(PeError::BufferOverflow);
+        }
+        if (pe_file.headers_size as usize) > file_size
+            || pe_file.headers_size > pe_file.image_size
+            || pe_file.entry_point >= pe_file.image_size
+            || !pe_file.section_alignment.is_power_of_two()
+        {
+            return Err(PeError::InvalidFormat);
+        }
+        for section in pe_file.sections() {
+            pe_file.check_section(&section?)?;
+        }
+
+        Ok(pe_file)
+    }
+
+    /// Size of all headers including the section table, as recorded in
+    /// the optional header. `buffer` must extend past the `SizeOfHeaders`
+    /// field, which `PROBE_SIZE` bytes do for common images.
+    pub fn headers_size(buffer: &[u8]) -> Result<usize, PeError> {
+        let nt_headers = PeFile::nt_headers_offset(buffer)?;
+        let optional_header = nt_headers + PE_SIGNATURE.len() + FILE_HEADER_SIZE;
+
+        Ok(read_u32_le(buffer, optional_header + 60)? as usize)
+    }
+
+    /// Offset of the PE signature, after checking both magics.
+    fn nt_headers_offset(buffer: &[u8]) -> Result<usize, PeError> {
+        if buffer.len() < DOS_HEADER_SIZE || !buffer.starts_with(&MAGIC) {
+            return Err(PeError::InvalidFormat);
+        }
+
+        let offset = read_u32_le(buffer, NT_HEADERS_OFFSET)? as usize;
+        match buffer.get(offset..(offset + PE_SIGNATURE.len())) {
+            Some(signature) if signature == PE_SIGNATURE => Ok(offset),
+            Some(_) => Err(PeError::InvalidFormat),
+            None => Err(PeError::BufferOverflow),
+        }
+    }
+
+    /// Sections must lie within the image in memory and the file on disk.
+    fn check_section(&self, section: &SectionHeader) -> Result<(), PeError> {
+        let memory_end = (section.virtual_address as u64) + (section.virtual_size as u64);
+        let file_end = (section.raw_data_offset as u64) + (section.loaded_size() as u64);
+
+        if memory_end > self.image_size as u64 || file_end > self.file_size as u64 {
+            Err(PeError::InvalidSection)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Relative address of the entry point.
+    pub fn entry_point(&self) -> usize {
+        self.entry_point as usize
+    }
+
+    /// Address the image is linked at.
+    pub fn image_base(&self) -> u64 {
+        self.image_base
+    }
+
+    /// Alignment of the sections in memory, a power of two.
+    pub fn section_alignment(&self) -> usize {
+        self.section_alignment as usize
+    }
+
+    /// Size of the image in memory.
+    pub fn image_size(&self) -> usize {
+        self.image_size as usize
+    }
+
+    /// Size of the headers, which are loaded at the start of the image.
+    pub fn size_of_headers(&self) -> usize {
+        self.headers_size as usize
+    }
+
+    /// Images without base relocations can only run at their image base,
+    /// whether they were stripped or the directory is just empty.
+    pub fn is_relocatable(&self) -> bool {
+        self.characteristics & IMAGE_FILE_RELOCS_STRIPPED == 0 && self.base_relocations.size != 0
+    }
+
+    pub fn base_relocations(&self) -> DataDirectory {
+        self.base_relocations
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = Result<SectionHeader, PeError>> + 'a {
+        let table = self.raw_buffer;
+        let start = self.section_table;
+
+        (0..self.section_count).map(move |index| {
+            let offset = start + index * SECTION_HEADER_SIZE;
+            let data = table.get(offset..(offset + SECTION_HEADER_SIZE))
+                .ok_or(PeError::BufferOverflow)?;
+            SectionHeader::parse(data)
+        })
+    }
+}
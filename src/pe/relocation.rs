@@ -0,0 +1,66 @@
+use super::{read_u16_le, read_u32_le, PeError};
+
+/// Padding entry, to be skipped.
+pub const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+/// The 64 bit word at the address gets the load offset added.
+pub const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+const BLOCK_HEADER_SIZE: usize = 8;
+
+pub struct BaseRelocation {
+    pub kind: u16,
+    /// Relative address of the relocated word.
+    pub address: u32,
+}
+
+/// Entries of the base relocation table, grouped in blocks of one page.
+pub struct BaseRelocations<'a> {
+    table: &'a [u8],
+    /// Offset of the current block.
+    block: usize,
+    /// Offset of the next entry within the table.
+    entry: usize,
+}
+
+impl <'a> BaseRelocations<'a> {
+    /// Check the block structure of `table`, the base relocation directory
+    /// of a loaded image.
+    pub fn new(table: &'a [u8]) -> Result<BaseRelocations<'a>, PeError> {
+        let mut offset = 0;
+        while offset < table.len() {
+            let block_size = read_u32_le(table, offset + 4)
+                .map_err(|_| PeError::InvalidRelocations)? as usize;
+            if block_size < BLOCK_HEADER_SIZE || block_size % 2 != 0 || block_size > table.len() - offset {
+                return Err(PeError::InvalidRelocations);
+            }
+            offset += block_size;
+        }
+
+        Ok(BaseRelocations { table, block: 0, entry: BLOCK_HEADER_SIZE })
+    }
+}
+
+impl <'a> Iterator for BaseRelocations<'a> {
+    type Item = BaseRelocation;
+
+    fn next(&mut self) -> Option<BaseRelocation> {
+        loop {
+            // `new` checked the block headers
+            let page = read_u32_le(self.table, self.block).ok()?;
+            let block_size = read_u32_le(self.table, self.block + 4).ok()? as usize;
+
+            if self.entry < self.block + block_size {
+                let entry = read_u16_le(self.table, self.entry).ok()?;
+                self.entry += 2;
+
+                return Some(BaseRelocation {
+                    kind: entry >> 12,
+                    address: page.wrapping_add((entry & 0xfff) as u32),
+                });
+            }
+
+            self.block += block_size;
+            self.entry = self.block + BLOCK_HEADER_SIZE;
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Serial console backends that keep working after `ExitBootServices`.
+
+pub mod sbi;
+
+mod ns16550;
+mod sifive;
+
+use super::fdt;
+
+pub use self::{
+    ns16550::Ns16550,
+    sbi::SbiConsole,
+    sifive::SiFiveUart,
+};
+
+pub enum Serial {
+    Ns16550(Ns16550),
+    SiFive(SiFiveUart),
+    Sbi(SbiConsole),
+}
+
+impl Serial {
+    /// Locate the UART referenced by `/chosen/stdout-path`.
+    ///
+    /// The UART is expected to be already configured by firmware,
+    /// so neither baud rate nor line settings are touched.
+    pub fn from_device_tree(device_tree: &fdt::DeviceTree) -> Option<Serial> {
+        let chosen = device_tree.chosen()?;
+        let stdout_path = chosen.property("stdout-path")
+            .or_else(|| chosen.property("linux,stdout-path"))?
+            .as_str()?;
+
+        // strip serial options, e.g. "serial0:115200n8"
+        let path = stdout_path.split(':').next()?;
+        let node = device_tree.resolve(path)?;
+        if !node.is_enabled() {
+            return None;
+        }
+
+        let (base, _size) = node.reg()?.next()?;
+        let base = base as usize;
+
+        if node.is_compatible("sifive,uart0") {
+            return Some(Serial::SiFive(unsafe { SiFiveUart::new(base) }));
+        }
+
+        if node.is_compatible("ns16550a") || node.is_compatible("ns16550") {
+            let reg_shift = node.property("reg-shift")
+                .and_then(|p| p.as_u32())
+                .unwrap_or(0);
+            let reg_io_width = node.property("reg-io-width")
+                .and_then(|p| p.as_u32())
+                .unwrap_or(1);
+
+            return Some(Serial::Ns16550(unsafe {
+                Ns16550::new(base, reg_shift, reg_io_width)
+            }));
+        }
+
+        None
+    }
+
+    /// Use the SBI debug console, if the SBI implementation provides one.
+    pub fn sbi() -> Option<Serial> {
+        SbiConsole::probe().map(Serial::Sbi)
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match self {
+            Serial::Ns16550(uart) => uart.write_byte(byte),
+            Serial::SiFive(uart) => uart.write_byte(byte),
+            Serial::Sbi(console) => console.write_byte(byte),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Serial::Ns16550(_) => "ns16550a",
+            Serial::SiFive(_) => "sifive,uart0",
+            Serial::Sbi(_) => "SBI debug console",
+        }
+    }
+}
+
+impl core::fmt::Write for Serial {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
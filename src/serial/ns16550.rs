@@ -0,0 +1,42 @@
+const THR: usize = 0;
+const LSR: usize = 5;
+
+const LSR_THRE: u8 = 0x20;
+
+pub struct Ns16550 {
+    base: usize,
+    reg_shift: u32,
+    reg_io_width: u32,
+}
+
+impl Ns16550 {
+    /// Unsafe: `base` must be the MMIO address of a 16550 compatible UART.
+    pub unsafe fn new(base: usize, reg_shift: u32, reg_io_width: u32) -> Ns16550 {
+        Ns16550 { base, reg_shift, reg_io_width }
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        let address = self.base + (register << self.reg_shift);
+        unsafe {
+            match self.reg_io_width {
+                4 => (address as *const u32).read_volatile() as u8,
+                _ => (address as *const u8).read_volatile(),
+            }
+        }
+    }
+
+    fn write(&mut self, register: usize, value: u8) {
+        let address = self.base + (register << self.reg_shift);
+        unsafe {
+            match self.reg_io_width {
+                4 => (address as *mut u32).write_volatile(value as u32),
+                _ => (address as *mut u8).write_volatile(value),
+            }
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.read(LSR) & LSR_THRE == 0 {}
+        self.write(THR, byte);
+    }
+}
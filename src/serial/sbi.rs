@@ -0,0 +1,145 @@
+const EXTENSION_LEGACY_PUTCHAR: usize = 0x01;
+const EXTENSION_BASE: usize = 0x10;
+const EXTENSION_DBCN: usize = 0x4442_434E;
+const EXTENSION_SRST: usize = 0x5352_5354;
+const EXTENSION_LEGACY_SHUTDOWN: usize = 0x08;
+const EXTENSION_RFENCE: usize = 0x5246_4E43;
+const EXTENSION_LEGACY_REMOTE_FENCE_I: usize = 0x05;
+const EXTENSION_HSM: usize = 0x0048_534D;
+
+const BASE_PROBE_EXTENSION: usize = 3;
+const DBCN_WRITE_BYTE: usize = 2;
+const SRST_SYSTEM_RESET: usize = 0;
+const RFENCE_REMOTE_FENCE_I: usize = 0;
+const HSM_HART_START: usize = 0;
+
+/// Returned for calls of extensions the SBI implementation lacks.
+pub const ERROR_NOT_SUPPORTED: isize = -2;
+
+pub const RESET_TYPE_COLD_REBOOT: u32 = 1;
+pub const RESET_REASON_SYSTEM_FAILURE: u32 = 1;
+
+/// Console output through the SBI implementation.
+///
+/// Prefers the Debug Console extension (DBCN) and falls back to the
+/// legacy `sbi_console_putchar` call.
+pub struct SbiConsole {
+    debug_console: bool,
+}
+
+impl SbiConsole {
+    pub fn probe() -> Option<SbiConsole> {
+        if probe_extension(EXTENSION_DBCN) {
+            Some(SbiConsole { debug_console: true })
+        } else if probe_extension(EXTENSION_LEGACY_PUTCHAR) {
+            Some(SbiConsole { debug_console: false })
+        } else {
+            None
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        if self.debug_console {
+            unsafe { ecall(EXTENSION_DBCN, DBCN_WRITE_BYTE, byte as usize); }
+        } else {
+            unsafe { ecall(EXTENSION_LEGACY_PUTCHAR, 0, byte as usize); }
+        }
+    }
+}
+
+pub fn probe_extension(extension: usize) -> bool {
+    let (error, value) = unsafe {
+        ecall(EXTENSION_BASE, BASE_PROBE_EXTENSION, extension)
+    };
+
+    error == 0 && value != 0
+}
+
+/// Reset the system through the System Reset extension (SRST), falling
+/// back to the legacy shutdown call. Only returns if neither is available.
+pub fn system_reset(reset_type: u32, reason: u32) {
+    if probe_extension(EXTENSION_SRST) {
+        unsafe {
+            ecall2(EXTENSION_SRST, SRST_SYSTEM_RESET, reset_type as usize, reason as usize);
+        }
+    }
+
+    if probe_extension(EXTENSION_LEGACY_SHUTDOWN) {
+        unsafe { ecall(EXTENSION_LEGACY_SHUTDOWN, 0, 0); }
+    }
+}
+
+/// Execute `fence.i` on all other harts, through the RFENCE extension or
+/// the legacy call. Harts not yet started by the kernel are unaffected.
+pub fn remote_fence_i() {
+    // a hart mask base of -1 selects all harts
+    if probe_extension(EXTENSION_RFENCE) {
+        unsafe { ecall2(EXTENSION_RFENCE, RFENCE_REMOTE_FENCE_I, 0, usize::MAX); }
+    } else if probe_extension(EXTENSION_LEGACY_REMOTE_FENCE_I) {
+        // a null hart mask selects all harts
+        unsafe { ecall(EXTENSION_LEGACY_REMOTE_FENCE_I, 0, 0); }
+    }
+}
+
+/// Start a stopped hart through the Hart State Management extension (HSM).
+/// The hart begins execution at the physical `start_address` in supervisor
+/// mode with address translation off, its hart id in `a0` and `opaque` in
+/// `a1`. Returns the SBI error code on failure.
+pub fn hart_start(hart_id: u64, start_address: u64, opaque: u64) -> Result<(), isize> {
+    if !probe_extension(EXTENSION_HSM) {
+        return Err(ERROR_NOT_SUPPORTED);
+    }
+
+    let (error, _) = unsafe {
+        ecall3(
+            EXTENSION_HSM,
+            HSM_HART_START,
+            hart_id as usize,
+            start_address as usize,
+            opaque as usize,
+        )
+    };
+
+    match error {
+        0 => Ok(()),
+        error => Err(error),
+    }
+}
+
+/// Issue an SBI call with a single argument, returning `(error, value)`.
+pub unsafe fn ecall(extension: usize, function: usize, argument: usize) -> (isize, usize) {
+    ecall2(extension, function, argument, 0)
+}
+
+/// Issue an SBI call with two arguments, returning `(error, value)`.
+pub unsafe fn ecall2(
+    extension: usize,
+    function: usize,
+    argument0: usize,
+    argument1: usize,
+) -> (isize, usize) {
+    ecall3(extension, function, argument0, argument1, 0)
+}
+
+/// Issue an SBI call with three arguments, returning `(error, value)`.
+pub unsafe fn ecall3(
+    extension: usize,
+    function: usize,
+    argument0: usize,
+    argument1: usize,
+    argument2: usize,
+) -> (isize, usize) {
+    let error: isize;
+    let value: usize;
+
+    asm!(
+        "ecall",
+        inlateout("a0") argument0 => error,
+        inlateout("a1") argument1 => value,
+        in("a2") argument2,
+        in("a6") function,
+        in("a7") extension,
+    );
+
+    (error, value)
+}
@@ -0,0 +1,22 @@
+const TXDATA: usize = 0x00;
+
+const TXDATA_FULL: u32 = 0x8000_0000;
+
+pub struct SiFiveUart {
+    base: usize,
+}
+
+impl SiFiveUart {
+    /// Unsafe: `base` must be the MMIO address of a SiFive UART.
+    pub unsafe fn new(base: usize) -> SiFiveUart {
+        SiFiveUart { base }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        let txdata = (self.base + TXDATA) as *mut u32;
+        unsafe {
+            while txdata.read_volatile() & TXDATA_FULL != 0 {}
+            txdata.write_volatile(byte as u32);
+        }
+    }
+}
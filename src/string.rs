@@ -0,0 +1,28 @@
+//! String formatting without an allocator.
+
+struct BufferWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl<'a> core::fmt::Write for BufferWriter<'a> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.length + s.len();
+        if end > self.buffer.len() {
+            return Err(core::fmt::Error);
+        }
+
+        self.buffer[self.length..end].copy_from_slice(s.as_bytes());
+        self.length = end;
+        Ok(())
+    }
+}
+
+/// Format into `buffer`, failing if the result does not fit.
+pub fn format<'a>(buffer: &'a mut [u8], args: core::fmt::Arguments) -> Option<&'a str> {
+    let mut writer = BufferWriter { buffer, length: 0 };
+    core::fmt::Write::write_fmt(&mut writer, args).ok()?;
+
+    let BufferWriter { buffer, length } = writer;
+    core::str::from_utf8(&buffer[..length]).ok()
+}